@@ -0,0 +1,122 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use can_adapter::bus::{Bus, BusKind};
+use can_adapter::packet::J1939Packet;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const ITEMS: u64 = 10_000;
+
+/// Push ITEMS into the bus and wait for every consumer to read them all.
+fn throughput(kind: BusKind, consumers: usize) {
+    let mut bus: Box<dyn Bus<u64>> = kind.create();
+    let handles: Vec<_> = (0..consumers)
+        .map(|_| {
+            let iter = bus.iter();
+            thread::spawn(move || iter.flatten().take(ITEMS as usize).count())
+        })
+        .collect();
+    for i in 0..ITEMS {
+        bus.push(Some(i));
+    }
+    for h in handles {
+        assert_eq!(ITEMS as usize, h.join().unwrap());
+    }
+    bus.close();
+}
+
+/// One second of a 10k frames/s bus, received as the RP1210 read thread does: each frame is built from the read
+/// buffer and pushed to every consumer.  Keeping up takes less than 1 s per iteration.
+fn receive(kind: BusKind, consumers: usize) {
+    let mut bus: Box<dyn Bus<J1939Packet>> = kind.create();
+    let handles: Vec<_> = (0..consumers)
+        .map(|_| {
+            let iter = bus.iter();
+            thread::spawn(move || {
+                iter.flatten()
+                    .take(ITEMS as usize)
+                    .map(|p| p.pgn() as u64)
+                    .sum::<u64>()
+            })
+        })
+        .collect();
+    // time stamp, echo, PGN 0xF004, priority, source, destination, 8 bytes of data
+    let mut buf = [
+        0, 0, 0, 0, 0, 0x04, 0xF0, 0x00, 0x03, 0x00, 0xFF, 1, 2, 3, 4, 5, 6, 7, 8,
+    ];
+    for i in 0..ITEMS {
+        buf[..4].copy_from_slice(&(i as u32).to_be_bytes());
+        bus.push(Some(J1939Packet::new_rp1210(false, 0, &buf, 1.0)));
+    }
+    for h in handles {
+        assert_eq!(ITEMS * 0xF004, h.join().unwrap());
+    }
+    bus.close();
+}
+
+/// Time from push until a single consumer sees the item.
+fn latency(kind: BusKind, consumers: usize, iters: u64) -> Duration {
+    let mut bus: Box<dyn Bus<Instant>> = kind.create();
+    // other consumers only add load
+    let idle: Vec<_> = (1..consumers)
+        .map(|_| {
+            let iter = bus.iter();
+            thread::spawn(move || iter.for_each(drop))
+        })
+        .collect();
+    let mut iter = bus.iter();
+    let mut total = Duration::ZERO;
+    for _ in 0..iters {
+        bus.push(Some(Instant::now()));
+        let sent = iter.find_map(|o| o).unwrap();
+        total += sent.elapsed();
+    }
+    bus.close();
+    idle.into_iter().for_each(|h| h.join().unwrap());
+    total
+}
+
+fn bus(c: &mut Criterion) {
+    let mut group = c.benchmark_group("throughput");
+    group.throughput(Throughput::Elements(ITEMS));
+    group.sample_size(10);
+    for consumers in [1, 2, 4, 8] {
+        for kind in [BusKind::PushBus, BusKind::MultiQueue] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", kind), consumers),
+                &consumers,
+                |b, &consumers| b.iter(|| throughput(kind, consumers)),
+            );
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("receive");
+    group.throughput(Throughput::Elements(ITEMS));
+    group.sample_size(10);
+    for consumers in [1, 2, 4, 8] {
+        for kind in [BusKind::PushBus, BusKind::MultiQueue] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", kind), consumers),
+                &consumers,
+                |b, &consumers| b.iter(|| receive(kind, consumers)),
+            );
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("latency");
+    for consumers in [1, 2, 4, 8] {
+        for kind in [BusKind::PushBus, BusKind::MultiQueue] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", kind), consumers),
+                &consumers,
+                |b, &consumers| b.iter_custom(|iters| latency(kind, consumers, iters)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bus);
+criterion_main!(benches);
@@ -0,0 +1,348 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::bus::{Bus, BusIterator, BusKind, Filter};
+use crate::clock::Clock;
+use crate::connection::{Connection, ConnectionInfo};
+use crate::packet::J1939Packet;
+
+/// A packet and the index of the connection it was received on.
+#[derive(Clone, Debug)]
+pub struct TaggedPacket {
+    pub connection: usize,
+    pub packet: J1939Packet,
+}
+
+/// Several connections, e.g. adapters on different networks, read as one stream.
+///
+/// The stream is in time() order.  Each packet is held until every other connection has a later packet waiting,
+/// or has polled empty, so it's at most a poll behind.  Each connection's own packets stay in the order received.
+/// Adapter clocks aren't synchronized, so connect with host time stamps (`TimestampSource::Host`) for time() to
+/// agree between connections.  iter_tagged() tells which connection each packet came from.
+///
+/// send() and transmit() use the first connection.  Use connection() to send on another.
+///
+/// Clones share the relay threads, and clone the connections.
+#[derive(Clone)]
+pub struct AggregateConnection {
+    /// None once this handle is closed
+    relay: Option<Arc<Relay>>,
+    connections: Vec<Box<dyn Connection>>,
+    bus: Box<dyn Bus<TaggedPacket>>,
+}
+
+/// Relay threads, one per connection, shared by clones of an `AggregateConnection`.  Stopped when the last clone
+/// is closed or dropped.
+struct Relay {
+    bus: Box<dyn Bus<TaggedPacket>>,
+    closing: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// Packets waiting to be relayed in time order, shared by the relay threads.
+struct Merge {
+    bus: Box<dyn Bus<TaggedPacket>>,
+    /// by connection
+    waiting: Vec<VecDeque<J1939Packet>>,
+    /// by connection: nothing more is waiting, as its last poll was empty or it ended
+    idle: Vec<bool>,
+}
+
+impl Merge {
+    fn new(bus: Box<dyn Bus<TaggedPacket>>, connections: usize) -> Merge {
+        Merge {
+            bus,
+            waiting: vec![VecDeque::new(); connections],
+            idle: vec![false; connections],
+        }
+    }
+
+    /// Add what connection `id` polled: a packet, or None if it had nothing.  Empty polls are passed on, so
+    /// consumers' deadlines are still checked on a quiet bus.
+    fn push(&mut self, id: usize, packet: Option<J1939Packet>) {
+        let empty = packet.is_none();
+        match packet {
+            Some(packet) => {
+                self.idle[id] = false;
+                self.waiting[id].push_back(packet);
+            }
+            None => self.idle[id] = true,
+        }
+        self.release();
+        if empty {
+            self.bus.push(None);
+        }
+    }
+
+    /// Relay the earliest waiting packet, for as long as every other connection has a packet waiting or is idle.
+    fn release(&mut self) {
+        loop {
+            let earliest = (0..self.waiting.len())
+                .filter_map(|id| self.waiting[id].front().map(|p| (id, p.time())))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let Some((id, _)) = earliest else {
+                return;
+            };
+            let ready = (0..self.waiting.len())
+                .all(|other| other == id || self.idle[other] || !self.waiting[other].is_empty());
+            if !ready {
+                return;
+            }
+            let packet = self.waiting[id].pop_front().unwrap();
+            self.bus.push(Some(TaggedPacket {
+                connection: id,
+                packet,
+            }));
+        }
+    }
+}
+
+impl Relay {
+    fn close(&mut self) -> Result<()> {
+        self.closing.store(true, Ordering::Relaxed);
+        let mut result = Ok(());
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                result = Err(anyhow!("aggregate relay thread panicked"));
+            }
+        }
+        self.bus.close();
+        result
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+impl AggregateConnection {
+    /// Merge `connections`.  Packets are tagged with their connection's index in `connections`.
+    pub fn new(connections: Vec<Box<dyn Connection>>, bus: BusKind) -> Result<AggregateConnection> {
+        if connections.is_empty() {
+            bail!("No connections to aggregate");
+        }
+        let bus = bus.create();
+        let merge = Arc::new(Mutex::new(Merge::new(bus.clone(), connections.len())));
+        let closing = Arc::new(AtomicBool::new(false));
+        let threads = connections
+            .iter()
+            .enumerate()
+            .map(|(id, c)| {
+                // registered before returning, so nothing received after new() is missed
+                let packets = c.iter();
+                let merge = merge.clone();
+                let closing = closing.clone();
+                std::thread::Builder::new()
+                    .name(format!("aggregate {}", id))
+                    .spawn(move || {
+                        for p in packets {
+                            if closing.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            merge.lock().unwrap().push(id, p);
+                        }
+                        // an ended connection holds nothing back
+                        merge.lock().unwrap().push(id, None);
+                    })
+                    .map_err(anyhow::Error::from)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let relay = Relay {
+            bus: bus.clone(),
+            closing,
+            threads,
+        };
+        Ok(AggregateConnection {
+            relay: Some(Arc::new(relay)),
+            connections,
+            bus,
+        })
+    }
+
+    /// The connection with index `id`, e.g. to send on it.
+    pub fn connection(&mut self, id: usize) -> Option<&mut Box<dyn Connection>> {
+        self.connections.get_mut(id)
+    }
+
+    /// Number of connections aggregated.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// iter(), with the index of the connection each packet was received on.
+    pub fn iter_tagged(&self) -> Box<dyn BusIterator<TaggedPacket>> {
+        self.bus.iter()
+    }
+
+    fn first(&mut self) -> Result<&mut Box<dyn Connection>> {
+        if self.relay.is_none() {
+            bail!("Connection closed");
+        }
+        Ok(&mut self.connections[0])
+    }
+}
+
+/// The packets of a `TaggedPacket` iterator.
+struct Untagged(Box<dyn BusIterator<TaggedPacket>>);
+
+impl Iterator for Untagged {
+    type Item = Option<J1939Packet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|t| t.map(|t| t.packet))
+    }
+}
+
+impl BusIterator<J1939Packet> for Untagged {
+    fn flush(&mut self) {
+        self.0.flush()
+    }
+}
+
+impl Connection for AggregateConnection {
+    fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet> {
+        self.first()?.send(packet)
+    }
+
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<()> {
+        self.first()?.transmit(packet)
+    }
+
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
+        Box::new(Untagged(self.bus.iter()))
+    }
+
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+        Box::new(Untagged(
+            self.bus.iter_filtered(Arc::new(move |t| filter(&t.packet))),
+        ))
+    }
+
+    /// Address of the first connection.
+    fn address(&self) -> u8 {
+        self.connections[0].address()
+    }
+
+    /// Clock of the first connection.
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.connections[0].clock()
+    }
+
+    /// Info of the first connection, with frames counted over all connections.
+    fn info(&self) -> ConnectionInfo {
+        let mut info = self.connections[0].info();
+        for c in &self.connections[1..] {
+            let other = c.info();
+            info.frames += other.frames;
+            info.frames_per_second += other.frames_per_second;
+        }
+        info
+    }
+
+    /// True while every connection is being relayed.
+    fn is_running(&self) -> bool {
+        self.relay
+            .as_ref()
+            .is_some_and(|r| r.threads.iter().all(|t| !t.is_finished()))
+    }
+
+    /// Closes this handle's connections.  The relay stops with the last clone.
+    fn close(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for c in &mut self.connections {
+            if let Err(e) = c.close() {
+                result = Err(e);
+            }
+        }
+        match self.relay.take().and_then(Arc::into_inner) {
+            Some(mut relay) => relay.close().and(result),
+            None => result,
+        }
+    }
+
+    fn clone_connection(&self) -> Box<dyn Connection> {
+        Box::new(self.clone())
+    }
+}
+
+impl Drop for AggregateConnection {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rp1210::Rp1210;
+
+    #[test]
+    fn time_order() {
+        let bus: Box<dyn Bus<TaggedPacket>> = BusKind::PushBus.create();
+        let tagged = bus.iter();
+        let mut merge = Merge::new(bus, 2);
+        let at = |time: u32| J1939Packet::new(Some(time), 0, 0x18FFAB00, &[(time / 1000) as u8]);
+        // held until connection 0 has polled
+        merge.push(1, Some(at(2000)));
+        merge.push(0, Some(at(1000)));
+        merge.push(0, Some(at(3000)));
+        merge.push(1, None);
+        merge.push(0, None);
+        let order: Vec<(usize, u8)> = tagged
+            .flatten()
+            .take(3)
+            .map(|t| (t.connection, t.packet.data()[0]))
+            .collect();
+        assert_eq!(vec![(0, 1), (1, 2), (0, 3)], order);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn merges_and_tags() -> Result<()> {
+        let sims: Vec<Rp1210> = (0..2)
+            .map(|device| Rp1210::new("SIM", 20 + device, None, "", 0xF9, true, BusKind::PushBus))
+            .collect::<Result<_>>()?;
+        let mut aggregate = AggregateConnection::new(
+            sims.iter().map(|s| s.clone_connection()).collect(),
+            BusKind::MultiQueue,
+        )?;
+        let tagged = aggregate.iter_tagged();
+        let packets = aggregate.iter_filtered(Arc::new(|p| p.id() == 0x18FFAB00));
+        for (i, mut sim) in sims.into_iter().enumerate() {
+            sim.replay([J1939Packet::new(Some(0), 0, 0x18FFAB00, &[i as u8]).as_received()]);
+        }
+        let mut tags: Vec<(usize, u8)> = tagged
+            .flatten()
+            .filter(|t| t.packet.id() == 0x18FFAB00)
+            .take(2)
+            .map(|t| (t.connection, t.packet.data()[0]))
+            .collect();
+        tags.sort();
+        assert_eq!(vec![(0, 0), (1, 1)], tags);
+        assert_eq!(2, packets.flatten().take(2).count());
+
+        let echo =
+            aggregate
+                .connection(1)
+                .unwrap()
+                .send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[7]))?;
+        assert_eq!([7], echo.data());
+
+        aggregate.close()?;
+        assert!(aggregate
+            .send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[7]))
+            .is_err());
+        assert_eq!(0, aggregate.iter().take(1).count());
+        Ok(())
+    }
+}
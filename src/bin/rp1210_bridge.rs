@@ -0,0 +1,19 @@
+use can_adapter::{bridge, init_logging, rp1210_parsing, Cli};
+use clap::Parser;
+
+/// 32-bit helper process for `can_adapter::bridge::Bridge`.  Takes the same connection arguments as the logger.
+fn main() {
+    let cli = Cli::parse();
+    // stderr is shared with the parent process
+    init_logging(cli.connection.verbose);
+    let time_stamp_weight =
+        rp1210_parsing::time_stamp_weight(&cli.connection.adapter).unwrap_or(1.0);
+    let result = cli
+        .connection
+        .connect()
+        .and_then(|connection| bridge::serve(connection, time_stamp_weight));
+    if let Err(e) = result {
+        let _ = bridge::serve_error(&e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,396 @@
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use clap::ValueEnum;
+
+use crate::bus::{Bus, BusIterator, Filter};
+use crate::connection::{Connection, ConnectionInfo, FrameCounter};
+use crate::packet::J1939Packet;
+use crate::registry::{self, Registration};
+use crate::timestamp::Timestamps;
+use crate::ConnectionDescriptor;
+
+/// RP1210 drivers are typically 32-bit DLLs, which can't be loaded by a 64-bit process.
+/// `Bridge` spawns the 32-bit `rp1210_bridge` executable, which loads the DLL and relays packets over stdin/stdout.
+///
+/// Build the bridge with `cargo build --release --bin rp1210_bridge --target i686-pc-windows-msvc`.
+///
+/// Each message is a frame of kind (u8), payload length (u32 LE), and payload.  Packets are encoded as
+/// channel (u8), tx (u8), and the RP1210 bytes.
+const CONNECTED: u8 = 0; // address (u8), time stamp weight (f64 LE)
+const PACKET: u8 = 1; // received packet
+const SENT: u8 = 2; // echo of sent packet
+const ERROR: u8 = 3; // message (UTF-8)
+const SEND: u8 = 4; // packet to send
+const TRANSMIT: u8 = 5; // packet to send without waiting for the echo.  Errors are not reported.
+
+/// How long to wait for the bridge to answer a send.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn write_frame(out: &mut dyn Write, kind: u8, payload: &[u8]) -> Result<()> {
+    out.write_all(&[kind])?;
+    out.write_all(&(payload.len() as u32).to_le_bytes())?;
+    out.write_all(payload)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn read_frame(input: &mut dyn Read) -> Result<(u8, Vec<u8>)> {
+    let mut head = [0; 5];
+    input.read_exact(&mut head)?;
+    let mut payload = vec![0; u32::from_le_bytes([head[1], head[2], head[3], head[4]]) as usize];
+    input.read_exact(&mut payload)?;
+    Ok((head[0], payload))
+}
+
+fn encode(packet: &J1939Packet) -> Vec<u8> {
+    [
+        &[packet.channel(), packet.tx_format() as u8][..],
+        packet.raw_bytes(),
+    ]
+    .concat()
+}
+
+fn decode(payload: &[u8], time_stamp_weight: f64) -> Result<J1939Packet> {
+    if payload.len() < 2 {
+        bail!("Bridge packet too short: {:?}", payload);
+    }
+    Ok(J1939Packet::new_rp1210(
+        payload[1] != 0,
+        payload[0],
+        &payload[2..],
+        time_stamp_weight,
+    ))
+}
+
+/// Connection to an adapter through the `rp1210_bridge` process.  Clones share the process.
+#[derive(Clone)]
+pub struct Bridge {
+    /// None once this handle is closed
+    process: Option<Arc<Process>>,
+    bus: Box<dyn Bus<J1939Packet>>,
+    address: u8,
+    descriptor: ConnectionDescriptor,
+    frames: FrameCounter,
+    inject_sent: bool,
+}
+
+/// The bridge process, shared by clones of a `Bridge`.  Stopped when the last clone is closed or dropped.
+struct Process {
+    child: Child,
+    stdin: Mutex<Option<ChildStdin>>,
+    sent: Mutex<Receiver<Result<J1939Packet>>>,
+    /// relays the bridge's output.  Finishes when the bridge exits.
+    reader: JoinHandle<()>,
+    bus: Box<dyn Bus<J1939Packet>>,
+    registration: Option<Registration>,
+}
+
+impl Process {
+    fn close(&mut self) -> Result<()> {
+        // closing stdin tells the bridge to disconnect and exit
+        if self.stdin.get_mut().unwrap().take().is_some() {
+            let status = self.child.wait();
+            self.bus.close();
+            self.registration.take();
+            let status = status?;
+            if !status.success() {
+                bail!("Bridge exited with {}", status);
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, kind: u8, packet: &J1939Packet) -> Result<()> {
+        let mut stdin = self.stdin.lock().unwrap();
+        let stdin = stdin.as_mut().ok_or(anyhow!("Bridge closed"))?;
+        let bytes = [&[packet.channel()][..], &packet.to_rp1210_tx()].concat();
+        write_frame(stdin, kind, &bytes)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+impl Bridge {
+    /// Spawn the bridge executable and connect it with `descriptor`.
+    pub fn new(bridge: &Path, descriptor: &ConnectionDescriptor) -> Result<Bridge> {
+        let mut command = Command::new(bridge);
+        command
+            .arg("--adapter")
+            .arg(&descriptor.adapter)
+            .arg("--device")
+            .arg(descriptor.device.to_string())
+            .arg("--connection-string")
+            .arg(&descriptor.connection_string)
+            .arg("--sa")
+            .arg(format!("{:02X}", descriptor.source_address))
+            .arg("--bus")
+            .arg(descriptor.bus.to_possible_value().unwrap().get_name())
+            .arg("--access")
+            .arg(descriptor.access.to_possible_value().unwrap().get_name())
+            .arg("--connect-timeout")
+            .arg(descriptor.connect_timeout.to_string())
+            .arg("--on-read-error")
+            .arg(descriptor.on_read_error.to_possible_value().unwrap().get_name())
+            .arg("--idle-timeout")
+            .arg(descriptor.idle_timeout.to_string())
+            .arg("--bus-state-interval")
+            .arg(descriptor.bus_state_interval.to_string());
+        if let Some(baud) = &descriptor.baud {
+            command.arg("--baud").arg(baud);
+        }
+        if let Some(channel) = descriptor.channel {
+            command.arg("--channel").arg(channel.to_string());
+        }
+        if let Some(timeout) = descriptor.claim_timeout {
+            command.arg("--claim-timeout").arg(timeout.to_string());
+        }
+        if descriptor.verbose {
+            command.arg("--verbose");
+        }
+        if descriptor.app_packetize {
+            command.arg("--app-packetize");
+        }
+        if descriptor.reset_before_connect {
+            command.arg("--reset-before-connect");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Unable to start {}", bridge.display()))?;
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+        let (kind, payload) = read_frame(&mut stdout).context("Bridge exited while connecting")?;
+        let (address, time_stamp_weight) = match kind {
+            CONNECTED if payload.len() == 9 => {
+                (payload[0], f64::from_le_bytes(payload[1..9].try_into()?))
+            }
+            ERROR => bail!("{}", String::from_utf8_lossy(&payload)),
+            _ => bail!("Unexpected bridge message: {}", kind),
+        };
+
+        let bus = descriptor.bus.create();
+        let (tx, rx) = channel();
+        let frames = FrameCounter::new();
+        let reader = {
+            let mut bus = bus.clone();
+            let frames = frames.clone();
+            // stamped here rather than by the bridge, so host time stamps are this process's clock
+            let mut timestamps = Timestamps::default();
+            timestamps.set_source(descriptor.timestamps);
+            std::thread::Builder::new()
+                .name("rp1210 bridge".into())
+                .spawn(move || {
+                    while let Ok((kind, payload)) = read_frame(&mut stdout) {
+                        match kind {
+                            PACKET => {
+                                if let Ok(p) = decode(&payload, time_stamp_weight) {
+                                    bus.push(Some(timestamps.stamp(p)));
+                                    frames.add();
+                                }
+                            }
+                            SENT => {
+                                let _ = tx.send(decode(&payload, time_stamp_weight));
+                            }
+                            ERROR => {
+                                let _ =
+                                    tx.send(Err(anyhow!("{}", String::from_utf8_lossy(&payload))));
+                            }
+                            _ => {}
+                        }
+                    }
+                    // bridge exited
+                    bus.close();
+                })?
+        };
+        let process = Process {
+            stdin: Mutex::new(child.stdin.take()),
+            child,
+            sent: Mutex::new(rx),
+            reader,
+            bus: bus.clone(),
+            registration: Some(registry::register(
+                &descriptor.adapter,
+                descriptor.device,
+                None,
+            )),
+        };
+        Ok(Bridge {
+            process: Some(Arc::new(process)),
+            bus,
+            address,
+            descriptor: descriptor.clone(),
+            frames,
+            inject_sent: descriptor.inject_sent,
+        })
+    }
+
+    fn process(&self) -> Result<&Process> {
+        self.process.as_deref().ok_or(anyhow!("Bridge closed"))
+    }
+
+    /// Sent packets are injected on this side of the bridge, since the bridge process has its own bus.
+    /// send() injects once the bridge reports success, so the copy follows the adapter's echo.
+    fn inject(&mut self, packet: &J1939Packet) {
+        if self.inject_sent {
+            self.bus.push(Some(packet.as_sent()));
+        }
+    }
+}
+
+impl Connection for Bridge {
+    /// Send packet and return packet echoed back from adapter
+    fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet> {
+        self.descriptor.access.check_write()?;
+        let process = self.process()?;
+        // one send at a time across clones, so answers match sends
+        let sent = process.sent.lock().unwrap();
+        // discard answers to sends that timed out
+        while sent.try_recv().is_ok() {}
+        process.write(SEND, packet)?;
+        let echo = sent.recv_timeout(SEND_TIMEOUT)??;
+        drop(sent);
+        self.inject(packet);
+        Ok(echo)
+    }
+
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<()> {
+        // the bridge doesn't report transmit errors, so check here
+        self.descriptor.access.check_write()?;
+        self.process()?.write(TRANSMIT, packet)?;
+        self.inject(packet);
+        Ok(())
+    }
+
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter()
+    }
+
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter_filtered(filter)
+    }
+
+    /// False once the bridge process exits.
+    fn is_running(&self) -> bool {
+        self.process.as_ref().is_some_and(|p| !p.reader.is_finished())
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// The bridge doesn't relay the adapter's baud or driver version.
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            adapter: self.descriptor.adapter.clone(),
+            device: self.descriptor.device,
+            connection_string: self.descriptor.connection_string.clone(),
+            address: self.address,
+            address_claim: None,
+            baud: None,
+            driver_version: None,
+            frames: self.frames.frames(),
+            frames_per_second: self.frames.per_second(),
+        }
+    }
+
+    /// The bridge process stops with the last clone.
+    fn close(&mut self) -> Result<()> {
+        match self.process.take().and_then(Arc::into_inner) {
+            Some(mut process) => process.close(),
+            None => Ok(()),
+        }
+    }
+
+    fn clone_connection(&self) -> Box<dyn Connection> {
+        Box::new(self.clone())
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Relay `connection` over stdin/stdout until stdin is closed.  This is the `rp1210_bridge` side of `Bridge`.
+pub fn serve(mut connection: Box<dyn Connection>, time_stamp_weight: f64) -> Result<()> {
+    let out = Arc::new(Mutex::new(BufWriter::new(std::io::stdout())));
+    let mut connected = vec![connection.address()];
+    connected.extend_from_slice(&time_stamp_weight.to_le_bytes());
+    write_frame(&mut *out.lock().unwrap(), CONNECTED, &connected)?;
+
+    let packets = connection.iter();
+    {
+        let out = out.clone();
+        std::thread::Builder::new()
+            .name("rp1210 bridge".into())
+            .spawn(move || {
+                let mut stdin = BufReader::new(std::io::stdin());
+                while let Ok((kind, payload)) = read_frame(&mut stdin) {
+                    if payload.is_empty() {
+                        continue;
+                    }
+                    let packet = J1939Packet::new_rp1210(true, payload[0], &payload[1..], 1.0);
+                    if kind == TRANSMIT {
+                        let _ = connection.transmit(&packet);
+                        continue;
+                    }
+                    if kind != SEND {
+                        continue;
+                    }
+                    let result = match connection.send(&packet) {
+                        Ok(echo) => write_frame(&mut *out.lock().unwrap(), SENT, &encode(&echo)),
+                        Err(e) => {
+                            write_frame(&mut *out.lock().unwrap(), ERROR, e.to_string().as_bytes())
+                        }
+                    };
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                // stdin closed.  Disconnecting closes the bus, which ends the packet loop.
+                drop(connection);
+            })?;
+    }
+    for p in packets.flatten() {
+        write_frame(&mut *out.lock().unwrap(), PACKET, &encode(&p))?;
+    }
+    Ok(())
+}
+
+/// Report a connection failure to the `Bridge`.
+pub fn serve_error(error: &Error) -> Result<()> {
+    write_frame(
+        &mut std::io::stdout(),
+        ERROR,
+        format!("{:#}", error).as_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_round_trip() -> Result<()> {
+        let packet = J1939Packet::new(Some(1234), 2, 0x18FEF100, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut buf = Vec::new();
+        write_frame(&mut buf, PACKET, &encode(&packet))?;
+        let (kind, payload) = read_frame(&mut buf.as_slice())?;
+        assert_eq!(PACKET, kind);
+        assert_eq!(packet.to_string(), decode(&payload, 1.0)?.to_string());
+        Ok(())
+    }
+}
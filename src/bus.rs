@@ -1,25 +1,75 @@
 use std::collections::VecDeque;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::Weak;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-/// represents the bus.  This is used by the adapter.  Currently is a custom multiqueue (multi headed linked list), but may use a publish subscribe sytem in the future.
-pub(crate) trait Bus<T:'static>: Send + Sync {
+/// represents the bus.  This is used by the adapter.  Implemented by PushBus (queue per consumer) and MultiQueue (multi headed linked list).  See BusKind.
+pub trait Bus<T:'static>: Send + Sync {
     /// used to read packets from the bus
-    fn iter(&self) -> Box<dyn Iterator<Item = Option<T>> + Send + Sync>;
+    ///
+    /// The iterator yields every item pushed after it is registered, in push order, and nothing pushed before.
+    /// Registration and push are serialized by the bus, so a push concurrent with iter() is either entirely
+    /// before or after the registration.  A consumer that registers mid-stream never loses or duplicates items.
+    fn iter(&self) -> Box<dyn BusIterator<T>>;
+    /// iter() that only yields items matching `filter` (and empty polls).  Items that don't match are skipped
+    /// without being cloned.
+    fn iter_filtered(&self, filter: Filter<T>) -> Box<dyn BusIterator<T>>;
     fn push(&mut self, item: Option<T>);
     fn clone_bus(&self) -> Box<dyn Bus<T>>;
     fn close(&mut self);
 }
 
+/// Selects the items a consumer receives.  See `Bus::iter_filtered()`.
+pub type Filter<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// Bus implementation used by a connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BusKind {
+    /// queue per consumer
+    #[default]
+    PushBus,
+    /// shared linked list
+    MultiQueue,
+    /// shared linked list that slows the reader rather than let a consumer fall too far behind, for lossless
+    /// recording.  See `MultiQueue::bounded()`.
+    BlockingMultiQueue,
+}
+
+/// Backlog at which a `BusKind::BlockingMultiQueue` blocks the reader, about 5 s of a busy 250k bus.
+pub const BLOCKING_CAPACITY: usize = 10_000;
+/// Longest a `BusKind::BlockingMultiQueue` blocks the reader for one item.
+pub const BLOCKING_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl BusKind {
+    pub fn create<T: Send + Sync + 'static + Clone>(&self) -> Box<dyn Bus<T>> {
+        match self {
+            BusKind::PushBus => Box::new(PushBus::new()),
+            BusKind::MultiQueue => Box::new(MultiQueue::new()),
+            BusKind::BlockingMultiQueue => {
+                Box::new(MultiQueue::bounded(BLOCKING_CAPACITY, BLOCKING_TIMEOUT))
+            }
+        }
+    }
+}
+
+/// A single consumer of the bus.  Each consumer has its own backlog of unread items.
+pub trait BusIterator<T>: Iterator<Item = Option<T>> + Send + Sync {
+    /// Discard this consumer's backlog.  Other consumers are not affected.
+    ///
+    /// Useful before sending a request, so that stale frames are not mistaken for the response.
+    fn flush(&mut self);
+}
+
 /// PushBusIter is an experiment to use array based queues per thread, instead of a shared Linked List.
 /// Most CPU time is used reading the RP1210 adapter, so the Bus isn't a significant contributer to CPU usage.
 
 #[derive(Clone)]
 pub struct PushBus<T> {
-    iters: Arc<Mutex<Vec<PushBusIter<T>>>>,
+    iters: Arc<Mutex<Vec<Queue<T>>>>,
 }
 impl<T> PushBus<T> {
     pub fn new() -> Self {
@@ -28,11 +78,38 @@ impl<T> PushBus<T> {
         }
     }
 }
+impl<T: Send + Sync + 'static + Clone> PushBus<T> {
+    fn register(&self, filter: Option<Filter<T>>) -> Box<dyn BusIterator<T>> {
+        let queue = Queue {
+            data: Arc::new(Mutex::new(VecDeque::new())),
+            running: Arc::new(AtomicBool::new(true)),
+            filter,
+        };
+        self.iters.lock().unwrap().push(queue.clone());
+        Box::new(PushBusIter {
+            queue,
+            iters: Arc::downgrade(&self.iters),
+        })
+    }
+}
+impl<T> Default for PushBus<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+/// A consumer's queue, shared by the bus and the consumer's iterator.
 #[derive(Clone)]
-struct PushBusIter<T> {
+struct Queue<T> {
     data: Arc<Mutex<VecDeque<Option<T>>>>,
     running: Arc<AtomicBool>,
+    filter: Option<Filter<T>>,
+}
+
+struct PushBusIter<T> {
+    queue: Queue<T>,
+    /// the bus's queues, so dropping the iterator removes its queue
+    iters: Weak<Mutex<Vec<Queue<T>>>>,
 }
 
 impl<T> Iterator for PushBusIter<T> {
@@ -42,37 +119,65 @@ impl<T> Iterator for PushBusIter<T> {
     /// Some(Packet) is a CAN packet
     type Item = Option<T>;
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.running.load(std::sync::atomic::Ordering::Relaxed) {
+        if !self.queue.running.load(std::sync::atomic::Ordering::Relaxed) {
             // done
             return None;
         }
-        let v = self.data.lock().unwrap().pop_front();
+        let v = self.queue.data.lock().unwrap().pop_front();
         if v.is_some() {
             return v;
         }
         // this means there was an empty response from poll()
         // sleep to avoid busy spinning
         thread::sleep(Duration::from_millis(1));
-        return Some(None);
+        Some(None)
+    }
+}
+
+impl<T: Send> BusIterator<T> for PushBusIter<T> {
+    fn flush(&mut self) {
+        self.queue.data.lock().unwrap().clear();
+    }
+}
+
+impl<T> Drop for PushBusIter<T> {
+    /// stop pushing to this consumer, so short lived iterators don't accumulate in a long lived bus
+    fn drop(&mut self) {
+        if let Some(iters) = self.iters.upgrade() {
+            // may be dropped while unwinding from a panic, so a poisoned lock is left alone
+            if let Ok(mut iters) = iters.lock() {
+                iters.retain(|q| !Arc::ptr_eq(&q.data, &self.queue.data));
+            }
+        }
     }
 }
 
 impl<T: Send + Sync + 'static + Clone> Bus<T> for PushBus<T> {
-    fn iter(&self) -> Box<dyn Iterator<Item = Option<T>> + Send + Sync> {
-        let x = PushBusIter {
-            data: Arc::new(Mutex::new(VecDeque::new())),
-            running: Arc::new(AtomicBool::new(true)),
-        };
-        self.iters.lock().unwrap().push(x.clone());
-        Box::new(x)
+    fn iter(&self) -> Box<dyn BusIterator<T>> {
+        self.register(None)
+    }
+
+    fn iter_filtered(&self, filter: Filter<T>) -> Box<dyn BusIterator<T>> {
+        self.register(Some(filter))
     }
 
     fn push(&mut self, item: Option<T>) {
-        self.iters
-            .lock()
-            .unwrap()
-            .iter_mut()
-            .for_each(|i| i.data.lock().unwrap().push_back(item.clone()));
+        // holding the iters lock for the whole fan-out keeps the order the same for every iterator,
+        // and keeps iter() from registering part way through a push
+        let iters = self.iters.lock().unwrap();
+        let mut matching = iters.iter().filter(|i| match (&i.filter, &item) {
+            (Some(filter), Some(item)) => filter(item),
+            _ => true,
+        });
+        let Some(mut last) = matching.next() else {
+            return;
+        };
+        for i in matching {
+            last.data.lock().unwrap().push_back(item.clone());
+            last = i;
+        }
+        // the last consumer gets the item itself, so a single consumer never clones
+        last.data.lock().unwrap().push_back(item);
     }
 
     fn clone_bus(&self) -> Box<dyn Bus<T>> {
@@ -93,3 +198,317 @@ impl<T:'static> Clone for Box<dyn Bus<T>> {
         self.clone_bus()
     }
 }
+
+/// MultiQueue is a singly linked list with a head per consumer.  Each push appends one node that is shared by all
+/// consumers, so pushing doesn't depend on the number of consumers.  Nodes are freed once every consumer has read them.
+#[derive(Clone)]
+pub struct MultiQueue<T> {
+    tail: Arc<Mutex<Arc<Node<T>>>>,
+    running: Arc<AtomicBool>,
+    bound: Option<Arc<Bound>>,
+}
+
+struct Node<T> {
+    /// number of items pushed before this one
+    seq: u64,
+    item: Option<T>,
+    next: OnceLock<Arc<Node<T>>>,
+}
+
+/// Backlog limit of a bounded MultiQueue.
+struct Bound {
+    capacity: u64,
+    timeout: Duration,
+    /// seq of the last node each consumer read.  Dropped consumers don't hold the queue back.
+    consumers: Mutex<Vec<Weak<AtomicU64>>>,
+}
+
+impl Bound {
+    /// Backlog of the consumer furthest behind `tail`.
+    fn backlog(&self, tail: u64) -> u64 {
+        let mut consumers = self.consumers.lock().unwrap();
+        consumers.retain(|c| c.strong_count() > 0);
+        consumers
+            .iter()
+            .filter_map(|c| c.upgrade())
+            .map(|c| tail - c.load(std::sync::atomic::Ordering::Relaxed))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl<T> Drop for Node<T> {
+    /// drop the list iteratively, so a long backlog doesn't overflow the stack
+    fn drop(&mut self) {
+        let mut next = self.next.take();
+        while let Some(node) = next {
+            next = match Arc::try_unwrap(node) {
+                Ok(mut node) => node.next.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl<T> MultiQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            tail: Arc::new(Mutex::new(Arc::new(Node {
+                seq: 0,
+                item: None,
+                next: OnceLock::new(),
+            }))),
+            running: Arc::new(AtomicBool::new(true)),
+            bound: None,
+        }
+    }
+
+    /// MultiQueue where push() blocks while any consumer has `capacity` unread items, applying backpressure to the
+    /// reader instead of letting the backlog grow.  After `timeout` the item is pushed anyway, so a stalled
+    /// consumer can't stop the reader for good.  Empty polls (None) never block.
+    pub fn bounded(capacity: usize, timeout: Duration) -> Self {
+        Self {
+            bound: Some(Arc::new(Bound {
+                capacity: capacity as u64,
+                timeout,
+                consumers: Mutex::new(Vec::new()),
+            })),
+            ..Self::new()
+        }
+    }
+}
+
+impl<T: Clone> MultiQueue<T> {
+    fn register(&self, filter: Option<Filter<T>>) -> MultiQueueIter<T> {
+        let node = self.tail.lock().unwrap().clone();
+        let position = Arc::new(AtomicU64::new(node.seq));
+        if let Some(bound) = &self.bound {
+            bound.consumers.lock().unwrap().push(Arc::downgrade(&position));
+        }
+        MultiQueueIter {
+            node,
+            queue: self.clone(),
+            filter,
+            position,
+        }
+    }
+}
+impl<T> Default for MultiQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct MultiQueueIter<T> {
+    node: Arc<Node<T>>,
+    queue: MultiQueue<T>,
+    filter: Option<Filter<T>>,
+    /// node.seq, read by bounded queues
+    position: Arc<AtomicU64>,
+}
+
+impl<T: Clone> Iterator for MultiQueueIter<T> {
+    type Item = Option<T>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.queue.running.load(std::sync::atomic::Ordering::Relaxed) {
+            return None;
+        }
+        while let Some(next) = self.node.next.get().cloned() {
+            self.node = next;
+            self.position
+                .store(self.node.seq, std::sync::atomic::Ordering::Relaxed);
+            let matches = match (&self.filter, &self.node.item) {
+                (Some(filter), Some(item)) => filter(item),
+                _ => true,
+            };
+            if matches {
+                return Some(self.node.item.clone());
+            }
+        }
+        // sleep to avoid busy spinning
+        thread::sleep(Duration::from_millis(1));
+        Some(None)
+    }
+}
+
+impl<T: Send + Sync + Clone> BusIterator<T> for MultiQueueIter<T> {
+    fn flush(&mut self) {
+        self.node = self.queue.tail.lock().unwrap().clone();
+        self.position
+            .store(self.node.seq, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+impl<T: Send + Sync + 'static + Clone> Bus<T> for MultiQueue<T> {
+    fn iter(&self) -> Box<dyn BusIterator<T>> {
+        Box::new(self.register(None))
+    }
+
+    fn iter_filtered(&self, filter: Filter<T>) -> Box<dyn BusIterator<T>> {
+        Box::new(self.register(Some(filter)))
+    }
+
+    fn push(&mut self, item: Option<T>) {
+        if let (Some(bound), Some(_)) = (&self.bound, &item) {
+            let end = Instant::now() + bound.timeout;
+            while bound.backlog(self.tail.lock().unwrap().seq) >= bound.capacity
+                && self.running.load(std::sync::atomic::Ordering::Relaxed)
+            {
+                if Instant::now() > end {
+                    log::warn!("Bus consumer still behind after {:?}, pushing anyway", bound.timeout);
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        // iter() reads the tail under the same lock, so a new iterator starts after this node or before it
+        let mut tail = self.tail.lock().unwrap();
+        let node = Arc::new(Node {
+            seq: tail.seq + 1,
+            item,
+            next: OnceLock::new(),
+        });
+        let _ = tail.next.set(node.clone());
+        *tail = node;
+    }
+
+    fn clone_bus(&self) -> Box<dyn Bus<T>> {
+        Box::new(self.clone())
+    }
+
+    fn close(&mut self) {
+        self.running.store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flush_only_affects_one_iterator() {
+        for kind in [BusKind::PushBus, BusKind::MultiQueue, BusKind::BlockingMultiQueue] {
+            flush_only_affects_one(kind.create());
+        }
+    }
+
+    fn flush_only_affects_one(mut bus: Box<dyn Bus<i32>>) {
+        let mut a = bus.iter();
+        let mut b = bus.iter();
+        bus.push(Some(1));
+        bus.push(Some(2));
+        a.flush();
+        bus.push(Some(3));
+        assert_eq!(Some(Some(3)), a.next());
+        assert_eq!(Some(Some(1)), b.next());
+        assert_eq!(Some(Some(2)), b.next());
+        assert_eq!(Some(Some(3)), b.next());
+        assert_eq!(Some(None), b.next());
+    }
+
+    #[test]
+    fn dropped_iterators_deregister() {
+        let mut bus = PushBus::<u32>::new();
+        let kept = bus.iter();
+        for _ in 0..100 {
+            let mut dropped = bus.iter();
+            bus.push(Some(1));
+            assert_eq!(Some(Some(1)), dropped.next());
+        }
+        assert_eq!(1, bus.iters.lock().unwrap().len());
+        drop(kept);
+        assert!(bus.iters.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn filtered() {
+        for kind in [BusKind::PushBus, BusKind::MultiQueue] {
+            let mut bus: Box<dyn Bus<i32>> = kind.create();
+            let mut odd = bus.iter_filtered(Arc::new(|i| i % 2 == 1));
+            let all = bus.iter();
+            (1..=4).for_each(|i| bus.push(Some(i)));
+            bus.push(None);
+            assert_eq!(
+                vec![Some(1), Some(3), None],
+                odd.by_ref().take(3).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec![Some(1), Some(2), Some(3), Some(4), None],
+                all.take(5).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn bounded_blocks_push() {
+        let mut bus: Box<dyn Bus<i32>> = Box::new(MultiQueue::bounded(2, Duration::from_secs(10)));
+        let mut consumer = bus.iter();
+        bus.push(Some(1));
+        bus.push(Some(2));
+        let (pushed, done) = std::sync::mpsc::channel();
+        let pusher = {
+            let mut bus = bus.clone();
+            thread::spawn(move || {
+                bus.push(Some(3));
+                pushed.send(()).unwrap();
+            })
+        };
+        // the consumer is 2 behind, so the push waits for it
+        assert!(done.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(Some(Some(1)), consumer.next());
+        done.recv_timeout(Duration::from_secs(5)).unwrap();
+        pusher.join().unwrap();
+        assert_eq!(
+            vec![Some(2), Some(3)],
+            consumer.by_ref().take(2).collect::<Vec<_>>()
+        );
+
+        // dropped consumers and timeouts don't block
+        drop(consumer);
+        (4..10).for_each(|i| bus.push(Some(i)));
+        let mut stalled: Box<dyn Bus<i32>> = Box::new(MultiQueue::bounded(1, Duration::from_millis(5)));
+        let _stalled_consumer = stalled.iter();
+        (0..3).for_each(|i| stalled.push(Some(i)));
+    }
+
+    #[test]
+    fn iter_registered_mid_stream() {
+        for kind in [BusKind::PushBus, BusKind::MultiQueue, BusKind::BlockingMultiQueue] {
+            iter_mid_stream(kind.create());
+        }
+    }
+
+    /// consumers registered while another thread pushes see a gap free, duplicate free suffix of the stream
+    fn iter_mid_stream(bus: Box<dyn Bus<u32>>) {
+        const COUNT: u32 = 20_000;
+        let pusher = {
+            let mut bus = bus.clone();
+            thread::spawn(move || (0..COUNT).for_each(|i| bus.push(Some(i))))
+        };
+        let consumers: Vec<_> = (0..8)
+            .map(|_| {
+                let iter = bus.iter();
+                thread::spawn(move || {
+                    let mut items = iter.flatten();
+                    let Some(first) = items.next() else {
+                        return;
+                    };
+                    for (expected, i) in (first + 1..).zip(items) {
+                        assert_eq!(expected, i);
+                        if i == COUNT - 1 {
+                            return;
+                        }
+                    }
+                })
+            })
+            .collect();
+        pusher.join().unwrap();
+        // consumers registered after the last push see nothing at all
+        let mut late = bus.iter();
+        assert_eq!(Some(None), late.next());
+        let mut bus = bus;
+        bus.push(Some(COUNT));
+        bus.close();
+        consumers.into_iter().for_each(|c| c.join().unwrap());
+    }
+}
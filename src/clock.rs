@@ -0,0 +1,86 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the current time for timeouts and rate limits, so they can be tested without waiting.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    /// Wait `duration`, or for a `MockClock`, advance by it.
+    fn sleep(&self, duration: Duration);
+}
+
+impl Debug for dyn Clock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Clock({:?})", self.now())
+    }
+}
+
+/// The host's clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// The host's clock, shared.  The default for connections.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Clock that only moves when advanced, for deterministic tests.  sleep() advances it rather than waiting.
+/// Clones share the time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_only_moves_when_advanced() {
+        let clock = MockClock::new();
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        let start = shared.now();
+        assert_eq!(start, shared.now());
+        clock.advance(Duration::from_secs(5));
+        shared.sleep(Duration::from_secs(1));
+        assert_eq!(Duration::from_secs(6), shared.now() - start);
+    }
+}
@@ -1,6 +1,16 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use crate::packet::J1939Packet;
+use anyhow::Context;
+
+use crate::bus::{BusIterator, Filter};
+use crate::clock::{self, Clock};
+use crate::echo::EchoSuppressor;
+use crate::j1939::{self, AckControl, AddressClaim, ComponentId, J1939Name, NodeInfo, TestResult};
+use crate::packet::{Direction, J1939Packet};
+use crate::tp::{self, Reassembler};
 
 /// Represents an adapter. This may be RP1210 or J2534 (eventually)
 ///
@@ -12,7 +22,7 @@ use crate::packet::J1939Packet;
 /// use can_adapter::packet::J1939Packet;
 /// fn vin(rp1210: & mut dyn Connection) ->Result<(),anyhow::Error> {
 ///   let packets = rp1210.iter_for(Duration::from_secs(2));
-///   rp1210.send(&J1939Packet::new(1, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]))?;
+///   rp1210.send(&J1939Packet::new(None, 1, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]))?;
 ///   packets
 ///     .filter(|p| p.pgn() == 0xFEEC )
 ///     .for_each(|p| println!("VIN: {} packet: {}",String::from_utf8(p.data().to_owned()).unwrap(),p));
@@ -23,24 +33,632 @@ use crate::packet::J1939Packet;
 ///    Ok(())
 /// }
 /// ```
-
 pub trait Connection: Send + Sync {
     // Send packet on CAN adapter
     fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet, anyhow::Error>;
 
+    /// send(), recording when the packet was queued and when the echo arrived.
+    fn send_timed(&mut self, packet: &J1939Packet) -> Result<SendResult, anyhow::Error> {
+        let queued_at = Instant::now();
+        let echoed = self.send(packet)?;
+        Ok(SendResult {
+            echoed,
+            queued_at,
+            echoed_at: Instant::now(),
+        })
+    }
+
+    /// Send packet without waiting for the echo.
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<(), anyhow::Error>;
+
+    /// Transmit each packet, waiting `gap` between them.  Stops at the first failure.
+    /// Returns the number of packets sent.
+    fn send_all(&mut self, packets: &[J1939Packet], gap: Duration) -> Result<usize, anyhow::Error> {
+        for (i, packet) in packets.iter().enumerate() {
+            if i > 0 {
+                std::thread::sleep(gap);
+            }
+            self.transmit(packet)
+                .with_context(|| format!("Sending packet {} of {}", i + 1, packets.len()))?;
+        }
+        Ok(packets.len())
+    }
+
+    /// Wait up to `timeout` for a packet from another node matching `trigger`, then transmit `packet` at once,
+    /// e.g. to answer an ECU's query within the J1939 response time.  Only packets received after the call
+    /// count, and the bus filters them, so nothing else is queued in between.  Returns the trigger.
+    fn send_on_trigger(
+        &mut self,
+        trigger: Filter<J1939Packet>,
+        packet: &J1939Packet,
+        timeout: Duration,
+    ) -> Result<J1939Packet, anyhow::Error> {
+        let trigger = self
+            .next_matching(Arc::new(move |p| p.direction() == Direction::Rx && trigger(p)), timeout)
+            .with_context(|| format!("No trigger for {}", packet))?;
+        self.transmit(packet)?;
+        Ok(trigger)
+    }
+
+    /// The first packet received after the call that matches `predicate`, or an error once `timeout` passes
+    /// without one, or if the connection closes.  Unlike iter_for().find(), a timeout is an error.
+    fn next_matching(
+        &self,
+        predicate: Filter<J1939Packet>,
+        timeout: Duration,
+    ) -> Result<J1939Packet, anyhow::Error> {
+        let clock = self.clock();
+        let end = clock.now() + timeout;
+        let mut packets = self.iter_filtered(predicate);
+        while clock.now() < end {
+            match packets.next() {
+                Some(Some(p)) => return Ok(p),
+                Some(None) => {}
+                None => anyhow::bail!("Connection closed"),
+            }
+        }
+        anyhow::bail!("Nothing matched within {:?}", timeout)
+    }
+
     // read packets. Some(None) does not indicate end of iterator. Some(None) indicates that a poll() returned None.
-    fn iter(&self) -> Box<dyn Iterator<Item = Option<J1939Packet>> + Send + Sync>;
-    
+    // Use BusIterator::flush() to discard stale packets before sending a request.
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>>;
+
+    /// iter() that only yields packets matching `filter`.  Filtering is done by the bus, so packets that
+    /// don't match are never queued or cloned for this consumer.
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>>;
+
+    /// Packets from one channel of a multi-channel adapter.  RP1210C messages don't carry a channel, so packets
+    /// have the channel their client connected with (`Channel=` in the connection string).
+    fn iter_channel(&self, channel: u8) -> Box<dyn BusIterator<J1939Packet>> {
+        self.iter_filtered(Arc::new(move |p| p.channel() == channel))
+    }
+
+    /// Packets received until `end`.  The deadline is checked on every poll, so this ends on a quiet bus too.
     fn iter_until(&self, end: Instant) -> Box<dyn Iterator<Item = J1939Packet> + Send + Sync> {
-        Box::new(self.iter().filter(|o| o.is_some()).map_while(move |o| {
-            if Instant::now() > end {
-                None
-            } else {
-                o
-            }
-        }))
+        let clock = self.clock();
+        Box::new(
+            self.iter()
+                .map_while(move |o| (clock.now() <= end).then_some(o))
+                .flatten(),
+        )
     }
     fn iter_for(&self, duration: Duration) -> Box<dyn Iterator<Item = J1939Packet> + Send + Sync> {
-        self.iter_until(Instant::now() + duration)
+        self.iter_until(self.clock().now() + duration)
+    }
+
+    /// iter_for(), continued for another window with `Window::next_window()`.  The subscription outlives each
+    /// window, so packets arriving between windows are in the next one, e.g. for back to back requests.
+    fn iter_window(&self, duration: Duration) -> Window {
+        Window::new(self.iter(), self.clock(), duration)
+    }
+
+    /// Packets without the adapter's echoes of our own transmissions.  See `EchoSuppressor`.
+    fn iter_without_echoes(
+        &self,
+        window: Duration,
+    ) -> Box<dyn Iterator<Item = J1939Packet> + Send + Sync> {
+        let mut echo = EchoSuppressor::new(self.address(), window);
+        Box::new(self.iter().flatten().filter_map(move |p| echo.push(p)))
+    }
+
+    /// Wait until no packet has arrived for `quiet`, e.g. before injecting traffic.
+    /// Fails if the bus is still busy after `overall_timeout`, or the connection closes.
+    fn wait_for_idle(&self, quiet: Duration, overall_timeout: Duration) -> Result<(), anyhow::Error> {
+        let clock = self.clock();
+        let end = clock.now() + overall_timeout;
+        let mut last = clock.now();
+        for p in self.iter() {
+            let now = clock.now();
+            if p.is_some() {
+                last = now;
+            } else if now - last >= quiet {
+                return Ok(());
+            }
+            if now > end {
+                anyhow::bail!("Bus not idle for {:?} within {:?}", quiet, overall_timeout);
+            }
+        }
+        anyhow::bail!("Connection closed")
+    }
+
+    /// Source address used when sending requests.
+    fn address(&self) -> u8;
+
+    /// Time for iter_for(), iter_until(), wait_for_idle() and rate limits.  `ConnectOptions::clock` for adapters.
+    fn clock(&self) -> Arc<dyn Clock> {
+        clock::system()
+    }
+
+    /// Adapter, settings and statistics for status displays.
+    fn info(&self) -> ConnectionInfo;
+
+    /// Whether packets are still being read.  False after close(), and if the reader stopped on its own, e.g. its
+    /// thread panicked or the bridge exited, in which case iterators yield nothing more; reconnect.
+    fn is_running(&self) -> bool;
+
+    /// Stop reading, release the address and disconnect.  Iterators end, and send() fails after closing.
+    /// Closing again does nothing.
+    ///
+    /// With clones, only this handle is closed.  The adapter is disconnected when the last clone is closed or
+    /// dropped, and iterators end then.
+    fn close(&mut self) -> Result<(), anyhow::Error>;
+
+    /// Another handle to the same connection.  Clones share the adapter client and bus, so they all see the
+    /// same stream, rather than opening another client.  Use `Box<dyn Connection>::clone()`.
+    fn clone_connection(&self) -> Box<dyn Connection>;
+
+    /// Send `request` and collect the packets that match until the timeout, reassembling any sent with TP.
+    /// Packets received before the request are ignored.
+    fn transact(
+        &mut self,
+        request: &J1939Packet,
+        matcher: &dyn Fn(&J1939Packet) -> bool,
+        timeout: Duration,
+    ) -> Result<Vec<J1939Packet>, anyhow::Error> {
+        collect_matching(self, request, matcher, timeout, false)
+    }
+
+    /// Request a PGN and collect the responses, reassembling any sent with TP.
+    ///
+    /// A destination specific request returns the first response. A global request (0xFF) collects until the timeout.
+    fn request_pgn(
+        &mut self,
+        pgn: u32,
+        dest: u8,
+        timeout: Duration,
+    ) -> Result<Vec<J1939Packet>, anyhow::Error> {
+        let request = j1939::request(pgn, dest, self.address());
+        collect_responses(self, &request, pgn, dest, timeout)
+    }
+
+    /// request_pgn(), repeated up to `retries` more times while nothing answers within the timeout, for ECUs
+    /// that miss requests.  Each attempt subscribes afresh, so nothing from an earlier attempt is left queued.
+    /// Returns the responses to the first attempt answered, or none.
+    fn request_pgn_retry(
+        &mut self,
+        pgn: u32,
+        dest: u8,
+        timeout: Duration,
+        retries: u32,
+    ) -> Result<Vec<J1939Packet>, anyhow::Error> {
+        for attempt in 1..=retries {
+            let responses = self.request_pgn(pgn, dest, timeout)?;
+            if !responses.is_empty() {
+                return Ok(responses);
+            }
+            log::debug!("No response to request {} for {:04X} from {:02X}", attempt, pgn, dest);
+        }
+        self.request_pgn(pgn, dest, timeout)
+    }
+
+    /// Global request for a PGN, collecting responses until the timeout, grouped by source address.
+    /// TP sessions from different ECUs are reassembled separately, so each entry is a complete response.
+    fn request_pgn_by_source(
+        &mut self,
+        pgn: u32,
+        timeout: Duration,
+    ) -> Result<HashMap<u8, Vec<J1939Packet>>, anyhow::Error> {
+        let mut by_source: HashMap<u8, Vec<J1939Packet>> = HashMap::new();
+        for p in self.request_pgn(pgn, 0xFF, timeout)? {
+            by_source.entry(p.source()).or_default().push(p);
+        }
+        Ok(by_source)
+    }
+
+    /// Request several PGNs from `dest` (0xFF for all nodes) and collect the responses until the timeout, grouped
+    /// by PGN, e.g. for a snapshot of many parameters.  The requests are sent one after another, and the responses
+    /// collected together, reassembling any sent with TP.  A destination specific request ends once every PGN is
+    /// answered.  PGNs that weren't answered are missing from the map.
+    fn request_pgns(
+        &mut self,
+        pgns: &[u32],
+        dest: u8,
+        timeout: Duration,
+    ) -> Result<HashMap<u32, Vec<J1939Packet>>, anyhow::Error> {
+        let mut requested: Vec<u32> = Vec::new();
+        for pgn in pgns {
+            if !requested.contains(pgn) {
+                requested.push(*pgn);
+            }
+        }
+        let address = self.address();
+        let mut tp = Reassembler::new();
+        // subscribe before sending, so fast responses aren't missed.  Ends at the timeout even if nothing answers.
+        let responses = self.iter_for(timeout).filter_map(move |p| tp.push(p));
+        for pgn in &requested {
+            self.send(&j1939::request(*pgn, dest, address))?;
+        }
+        let mut by_pgn: HashMap<u32, Vec<J1939Packet>> = HashMap::new();
+        for p in responses {
+            let pgn = tp::base_pgn(&p);
+            if requested.contains(&pgn) && is_response(&p, dest, address) {
+                by_pgn.entry(pgn).or_default().push(p);
+                if dest != 0xFF && by_pgn.len() == requested.len() {
+                    break;
+                }
+            }
+        }
+        Ok(by_pgn)
+    }
+
+    /// Answer `dest`'s request for `requested_pgn` with an Acknowledgment, e.g. NACK for a PGN we don't send, as
+    /// an ECU does.
+    fn send_ack(
+        &mut self,
+        requested_pgn: u32,
+        control: AckControl,
+        dest: u8,
+    ) -> Result<J1939Packet, anyhow::Error> {
+        self.send(&j1939::acknowledgment(control, requested_pgn, dest, self.address()))
     }
+
+    /// Request Address Claimed from all nodes and decode their NAMEs.  Sorted by address.
+    fn scan_network(&mut self, timeout: Duration) -> Result<Vec<NodeInfo>, anyhow::Error> {
+        let mut nodes: Vec<NodeInfo> = self
+            .request_pgn(j1939::ADDRESS_CLAIMED, 0xFF, timeout)?
+            .iter()
+            .filter_map(|p| {
+                Some(NodeInfo {
+                    address: p.source(),
+                    name: J1939Name::from_bytes(p.data().try_into().ok()?),
+                })
+            })
+            .collect();
+        nodes.sort_by_key(|n| n.address);
+        // a node may answer more than once
+        nodes.dedup_by_key(|n| n.address);
+        Ok(nodes)
+    }
+
+    /// Command a test with DM7 and return the DM30 results.
+    fn request_test_results(
+        &mut self,
+        spn: u32,
+        test_id: u8,
+        fmi: u8,
+        dest: u8,
+        timeout: Duration,
+    ) -> Result<Vec<TestResult>, anyhow::Error> {
+        let head = 0x18000000 | j1939::DM7 << 8 | (dest as u32) << 8 | self.address() as u32;
+        let dm7 = J1939Packet::new(None, 0, head, &j1939::dm7(test_id, spn, fmi));
+        Ok(collect_responses(self, &dm7, j1939::DM30, dest, timeout)?
+            .iter()
+            .flat_map(|p| TestResult::parse_dm30(p.source(), p.data()))
+            .collect())
+    }
+
+    /// Request Component Identification (make, model, serial number and unit number) from `dest`.
+    fn component_id(&mut self, dest: u8, timeout: Duration) -> Result<ComponentId, anyhow::Error> {
+        self.request_pgn(j1939::COMPONENT_ID, dest, timeout)?
+            .first()
+            .map(|p| ComponentId::parse(p.data()))
+            .ok_or_else(|| anyhow::anyhow!("No component identification from {:02X}", dest))
+    }
+}
+
+impl Clone for Box<dyn Connection> {
+    fn clone(&self) -> Self {
+        self.clone_connection()
+    }
+}
+
+/// Whether this client may transmit.
+///
+/// Read-only affects only this client: it doesn't claim an address and its sends fail, but other clients of the
+/// adapter, and the bus, are unaffected.  Receive filters still apply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Access {
+    #[default]
+    ReadWrite,
+    ReadOnly,
+}
+
+impl Access {
+    /// Fails for read-only connections.  Called before transmitting.
+    pub(crate) fn check_write(&self) -> Result<(), anyhow::Error> {
+        match self {
+            Access::ReadWrite => Ok(()),
+            Access::ReadOnly => anyhow::bail!("Read-only connection"),
+        }
+    }
+}
+
+/// What the read thread does when the driver fails a read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnReadError {
+    /// Log the error and keep reading after 250 ms.
+    #[default]
+    Retry,
+    /// Stop reading.  Iterators end, and `iter_results()` ends with the error.  Wrap the connection in
+    /// `Reconnecting` to reconnect.
+    Terminate,
+}
+
+/// Longest `ConnectOptions::default()` waits for the adapter to connect.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings applied while connecting.
+#[derive(Clone, Debug)]
+pub struct ConnectOptions {
+    pub access: Access,
+    /// Longest to wait for the driver to connect and claim the address, which can block indefinitely, e.g.
+    /// auto-baud on an unpowered bus.  None waits as long as the driver does.
+    pub connect_timeout: Option<Duration>,
+    pub on_read_error: OnReadError,
+    /// Disconnect once nothing has been received for this long, e.g. when the vehicle is switched off, so an
+    /// unattended logger frees the adapter.  Restarts with every received packet; echoes of our own don't count.
+    /// None stays connected.
+    pub idle_timeout: Option<Duration>,
+    pub read_mode: ReadMode,
+    /// Time for the idle timeout and iter_for().  A `MockClock` tests them without waiting.
+    pub clock: Arc<dyn Clock>,
+    /// Read the adapter's hardware status this often, reporting bus-off and error-passive with
+    /// `iter_bus_state()`, so a bus-off adapter isn't mistaken for a quiet bus.  From the read thread, so not with
+    /// ReadMode::Poll.  None doesn't read it.
+    pub bus_state_interval: Option<Duration>,
+    /// How connecting waits for the address claim.
+    pub claim_mode: ClaimMode,
+}
+
+/// Clocks compare by identity.
+impl PartialEq for ConnectOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.access == other.access
+            && self.connect_timeout == other.connect_timeout
+            && self.on_read_error == other.on_read_error
+            && self.idle_timeout == other.idle_timeout
+            && self.read_mode == other.read_mode
+            && Arc::ptr_eq(&self.clock, &other.clock)
+            && self.bus_state_interval == other.bus_state_interval
+            && self.claim_mode == other.claim_mode
+    }
+}
+
+impl Eq for ConnectOptions {}
+
+impl ConnectOptions {
+    /// Fails for options that can't work together.
+    pub(crate) fn check(&self) -> Result<(), anyhow::Error> {
+        if self.read_mode == ReadMode::Poll && matches!(self.claim_mode, ClaimMode::Timeout(_)) {
+            anyhow::bail!(
+                "ClaimMode::Timeout waits for the adapter's echo of the claim, which ReadMode::Poll doesn't read"
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            access: Access::ReadWrite,
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            on_read_error: OnReadError::Retry,
+            idle_timeout: None,
+            read_mode: ReadMode::Thread,
+            clock: clock::system(),
+            bus_state_interval: None,
+            claim_mode: ClaimMode::BlockUntilDone,
+        }
+    }
+}
+
+/// Who reads received packets from the driver.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadMode {
+    /// A read thread pushes them onto the bus, for iter().
+    #[default]
+    Thread,
+    /// The application calls `poll()` from its own loop, so the driver is only called from its thread.  Nothing
+    /// is pushed onto the bus, so iter() only sees packets injected locally, and send() doesn't wait for the echo.
+    /// The idle timeout and OnReadError don't apply: poll() returns read errors.
+    Poll,
+}
+
+/// How the adapter claims the address while connecting (RP1210_SendCommand Protect J1939 Address).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClaimMode {
+    /// The driver returns once the claim is done, failing on contention.  Some drivers never return while another
+    /// node contends for the address, so this is only bounded by the connect timeout.
+    #[default]
+    BlockUntilDone,
+    /// The driver returns at once, then connecting waits up to this long for the adapter's echo of our Address
+    /// Claimed, failing on Cannot Claim Address or if there's no echo in time.  Needs an adapter that echoes its
+    /// claims, and the read thread, so not with ReadMode::Poll.
+    Timeout(Duration),
+    /// The driver returns at once and connecting doesn't wait for the outcome, so address_claim() may not have the
+    /// claimed address.  The adapter fails sends if the claim is lost.
+    NonBlocking,
+}
+
+/// `packets`, then the error in `failure`, if the stream ended because of one.  For `iter_results()`.
+pub(crate) fn until_failure<E>(
+    packets: Box<dyn BusIterator<J1939Packet>>,
+    failure: Arc<Mutex<Option<E>>>,
+) -> impl Iterator<Item = Result<Option<J1939Packet>, anyhow::Error>>
+where
+    E: std::error::Error + Clone + Send + Sync + 'static,
+{
+    let mut packets = Some(packets);
+    std::iter::from_fn(move || {
+        let p = packets.as_mut()?.next();
+        match p {
+            Some(p) => Some(Ok(p)),
+            None => {
+                packets = None;
+                failure.lock().unwrap().clone().map(|e| Err(e.into()))
+            }
+        }
+    })
+}
+
+/// Packets received within a window of time, from `Connection::iter_window()`.  Ends when the window does, or the
+/// connection closes.
+pub struct Window {
+    packets: Box<dyn BusIterator<J1939Packet>>,
+    clock: Arc<dyn Clock>,
+    duration: Duration,
+    end: Instant,
+}
+
+impl Window {
+    fn new(
+        packets: Box<dyn BusIterator<J1939Packet>>,
+        clock: Arc<dyn Clock>,
+        duration: Duration,
+    ) -> Window {
+        let end = clock.now() + duration;
+        Window {
+            packets,
+            clock,
+            duration,
+            end,
+        }
+    }
+
+    /// Start another window of the same duration, from now.  It begins with the packets that arrived since this
+    /// one ended.
+    pub fn next_window(&mut self) {
+        self.next_window_for(self.duration);
+    }
+
+    /// next_window(), lasting `duration`.
+    pub fn next_window_for(&mut self, duration: Duration) {
+        self.duration = duration;
+        self.end = self.clock.now() + duration;
+    }
+}
+
+impl Iterator for Window {
+    type Item = J1939Packet;
+
+    /// Checks the end on empty polls too, so a quiet bus doesn't hold the window open.
+    fn next(&mut self) -> Option<J1939Packet> {
+        while self.clock.now() < self.end {
+            if let Some(p) = self.packets.next()? {
+                return Some(p);
+            }
+        }
+        None
+    }
+}
+
+/// RP1210_SendMessage flags.  Drivers that don't support them ignore them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SendOptions {
+    /// Return once the message is on the bus (BLOCKING_IO), rather than once it's queued.  send() then returns
+    /// the packet without waiting for the echo.
+    pub blocking: bool,
+    /// nNotifyStatusOnTx.  Obsolete in RP1210C, but some drivers still act on it.
+    pub notify: bool,
+}
+
+/// Echo of a sent packet, from `Connection::send_timed()`.  The echo's adapter time stamp is `echoed.time()`.
+#[derive(Debug, Clone)]
+pub struct SendResult {
+    pub echoed: J1939Packet,
+    /// just before the packet was passed to the adapter
+    pub queued_at: Instant,
+    /// when send() found the echo
+    pub echoed_at: Instant,
+}
+
+impl SendResult {
+    /// Time from queuing to the echo arriving.
+    pub fn latency(&self) -> Duration {
+        self.echoed_at - self.queued_at
+    }
+}
+
+/// Summary of a connection, from `Connection::info()`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConnectionInfo {
+    pub adapter: String,
+    pub device: i16,
+    pub connection_string: String,
+    pub address: u8,
+    /// None if app packetized
+    pub address_claim: Option<AddressClaim>,
+    /// speed reported by the adapter, if it supports the query
+    pub baud: Option<String>,
+    pub driver_version: Option<String>,
+    /// packets received since connecting
+    pub frames: u64,
+    /// average since connecting
+    pub frames_per_second: f64,
+}
+
+/// Counts received packets for `ConnectionInfo`.  Clones share the count.
+#[derive(Clone)]
+pub(crate) struct FrameCounter {
+    frames: Arc<AtomicU64>,
+    start: Instant,
+}
+
+impl FrameCounter {
+    pub(crate) fn new() -> Self {
+        Self {
+            frames: Arc::new(AtomicU64::new(0)),
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn add(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn per_second(&self) -> f64 {
+        self.frames() as f64 / self.start.elapsed().as_secs_f64()
+    }
+}
+
+/// Opens a `Connection` to a particular adapter, such as a `ConnectionDescriptor` from the command line.
+pub trait ConnectionFactory: Send + Sync {
+    fn connect(&self) -> Result<Box<dyn Connection>, anyhow::Error>;
+}
+
+/// Send `request` and collect `pgn` responses from `dest` (or anyone if global).
+fn collect_responses<C: Connection + ?Sized>(
+    connection: &mut C,
+    request: &J1939Packet,
+    pgn: u32,
+    dest: u8,
+    timeout: Duration,
+) -> Result<Vec<J1939Packet>, anyhow::Error> {
+    let address = connection.address();
+    let matcher = |p: &J1939Packet| tp::base_pgn(p) == pgn && is_response(p, dest, address);
+    collect_matching(connection, request, &matcher, timeout, dest != 0xFF)
+}
+
+/// Whether `p` is from `dest` (or anyone if global), and sent to `address` or everyone.
+fn is_response(p: &J1939Packet, dest: u8, address: u8) -> bool {
+    (dest == 0xFF || p.source() == dest)
+        && (p.dest() == address || p.dest() == 0xFF || tp::base_pgn(p) >= 0xF000)
+}
+
+/// Subscribe, send `request`, then collect reassembled packets that match.
+fn collect_matching<C: Connection + ?Sized>(
+    connection: &mut C,
+    request: &J1939Packet,
+    matcher: &dyn Fn(&J1939Packet) -> bool,
+    timeout: Duration,
+    first_only: bool,
+) -> Result<Vec<J1939Packet>, anyhow::Error> {
+    let mut tp = Reassembler::new();
+    // subscribe before sending, so fast responses aren't missed
+    let responses = connection
+        .iter_for(timeout)
+        .filter_map(move |p| tp.push(p))
+        .filter(|p| matcher(p));
+    connection.send(request)?;
+    Ok(if first_only {
+        responses.take(1).collect()
+    } else {
+        responses.collect()
+    })
 }
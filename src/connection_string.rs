@@ -0,0 +1,268 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+/// Protocol and parameters of a connection string, split without validation.
+/// Parameters are separated by `;` or `,`.
+fn split(connection_string: &str) -> (&str, Vec<(&str, Option<&str>)>) {
+    let (protocol, params) = connection_string
+        .split_once(':')
+        .unwrap_or((connection_string, ""));
+    let params = params
+        .split([';', ','])
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((k, v)) => (k.trim(), Some(v.trim())),
+            None => (p, None),
+        })
+        .collect();
+    (protocol.trim(), params)
+}
+
+/// A parsed RP1210 connection string, e.g. `J1939:Baud=500;Channel=2`.
+///
+/// Parameters keep their order, so `to_string()` gives back the parsed string, with `;` separators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionString {
+    /// `J1939`, `CAN`, `ISO15765`, `J1708`...
+    pub protocol: String,
+    /// key and value.  Flags, such as `Extended`, have no value.
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl ConnectionString {
+    /// Parse and validate.  Baud must be `Auto` or a number, and Channel a number from 1 to 255.
+    pub fn parse(connection_string: &str) -> Result<ConnectionString> {
+        let (protocol, params) = split(connection_string);
+        if protocol.is_empty() || !protocol.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid protocol in connection string \"{}\"", connection_string);
+        }
+        let mut parsed = ConnectionString {
+            protocol: protocol.to_string(),
+            params: Vec::new(),
+        };
+        for (key, value) in params {
+            if key.is_empty() {
+                bail!("Missing parameter name in \"{}\"", connection_string);
+            }
+            if parsed.params.iter().any(|(k, _)| k.eq_ignore_ascii_case(key)) {
+                bail!("{} is repeated in \"{}\"", key, connection_string);
+            }
+            let valid = if key.eq_ignore_ascii_case("Baud") {
+                value.is_some_and(|v| v.eq_ignore_ascii_case("Auto") || v.parse::<u32>().is_ok())
+            } else if key.eq_ignore_ascii_case("Channel") {
+                value.is_some_and(|v| v.parse::<u8>().is_ok_and(|c| c > 0))
+            } else {
+                true
+            };
+            if !valid {
+                bail!("Invalid {} in \"{}\"", key, connection_string);
+            }
+            parsed
+                .params
+                .push((key.to_string(), value.map(str::to_string)));
+        }
+        Ok(parsed)
+    }
+
+    /// Value of the parameter named `key`, ignoring case.  Some("") for flags.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_deref().unwrap_or(""))
+    }
+}
+
+impl FromStr for ConnectionString {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl Display for ConnectionString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.protocol)?;
+        for (i, (k, v)) in self.params.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { ":" } else { ";" }, k)?;
+            if let Some(v) = v {
+                write!(f, "={}", v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds RP1210 connection strings such as `J1939:Baud=500;Channel=1`.
+///
+/// Baud and Channel are held separately from the other parameters, so setting them replaces any value
+/// already in the string instead of appending a duplicate.  The string is always written protocol first, then
+/// Baud, Channel, and the other parameters in their original order.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConnectionStringBuilder {
+    protocol: String,
+    baud: Option<String>,
+    channel: Option<u8>,
+    params: Vec<(String, Option<String>)>,
+}
+
+impl ConnectionStringBuilder {
+    pub fn new(protocol: &str) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Start from an existing connection string, such as the one given on the command line.
+    /// Parameters are separated by `;` or `,`.  Baud and Channel are matched case insensitively.
+    pub fn from_connection_string(connection_string: &str) -> Self {
+        let (protocol, params) = split(connection_string);
+        params
+            .into_iter()
+            .fold(Self::new(protocol), |b, (k, v)| match v {
+                Some(v) => b.param(k, v),
+                None => b.flag(k),
+            })
+    }
+
+    pub fn baud(mut self, baud: &str) -> Self {
+        self.baud = Some(baud.to_string());
+        self
+    }
+
+    pub fn channel(mut self, channel: u8) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Set a `key=value` parameter, replacing any existing value.
+    pub fn param(mut self, key: &str, value: &str) -> Self {
+        if key.eq_ignore_ascii_case("Baud") {
+            return self.baud(value);
+        }
+        if key.eq_ignore_ascii_case("Channel") {
+            if let Ok(channel) = value.parse() {
+                return self.channel(channel);
+            }
+        }
+        self.params.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.params.push((key.to_string(), Some(value.to_string())));
+        self
+    }
+
+    /// Set a parameter without a value.
+    pub fn flag(mut self, key: &str) -> Self {
+        self.params.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.params.push((key.to_string(), None));
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let params: Vec<String> = self
+            .baud
+            .iter()
+            .map(|b| format!("Baud={}", b))
+            .chain(self.channel.iter().map(|c| format!("Channel={}", c)))
+            .chain(self.params.iter().map(|(k, v)| match v {
+                Some(v) => format!("{}={}", k, v),
+                None => k.clone(),
+            }))
+            .collect();
+        if params.is_empty() {
+            self.protocol.clone()
+        } else {
+            format!("{}:{}", self.protocol, params.join(";"))
+        }
+    }
+}
+
+/// The connection string to give the driver.  Without a channel the user's string is passed through untouched,
+/// since drivers may care about its exact form.  Otherwise the channel is merged in with the builder.
+pub fn with_channel(connection_string: &str, channel: Option<u8>) -> String {
+    match channel {
+        Some(channel) => ConnectionStringBuilder::from_connection_string(connection_string)
+            .channel(channel)
+            .build(),
+        None => connection_string.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_replaces_existing() {
+        assert_eq!(
+            "J1939:Baud=500000;Channel=2",
+            ConnectionStringBuilder::from_connection_string("J1939:Baud=500000;Channel=1")
+                .channel(2)
+                .build()
+        );
+        assert_eq!(
+            "J1939:Baud=Auto;Channel=3",
+            ConnectionStringBuilder::from_connection_string("J1939:channel=1,BAUD=Auto")
+                .channel(3)
+                .build()
+        );
+    }
+
+    #[test]
+    fn with_channel_only_rewrites_for_a_channel() {
+        let s = "CAN:SampleLocation=80,Baud=500";
+        assert_eq!(s, with_channel(s, None));
+        assert_eq!(
+            "CAN:Baud=500;Channel=2;SampleLocation=80",
+            with_channel(s, Some(2))
+        );
+    }
+
+    #[test]
+    fn parse() -> Result<()> {
+        for s in [
+            "J1939:Baud=500;Channel=2",
+            "CAN:Baud=Auto;SampleLocation=80;Extended",
+            "ISO15765",
+        ] {
+            assert_eq!(s, ConnectionString::parse(s)?.to_string());
+        }
+        let cs: ConnectionString = "j1939 : baud=250, channel=3".parse()?;
+        assert_eq!("j1939", cs.protocol);
+        assert_eq!(Some("250"), cs.get("Baud"));
+        assert_eq!(Some("3"), cs.get("CHANNEL"));
+        assert_eq!(None, cs.get("SampleLocation"));
+        assert_eq!("j1939:baud=250;channel=3", cs.to_string());
+
+        for bad in [
+            "",
+            ":Baud=500",
+            "J1939:Baud=fast",
+            "J1939:Channel=0",
+            "J1939:Channel",
+            "J1939:=5",
+            "J1939:Baud=500;baud=250",
+        ] {
+            assert!(ConnectionString::parse(bad).is_err(), "{}", bad);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn canonical() {
+        assert_eq!("J1939", ConnectionStringBuilder::new("J1939").build());
+        assert_eq!(
+            "CAN:Baud=250;Channel=1;SampleLocation=80;Extended",
+            ConnectionStringBuilder::new("CAN")
+                .param("SampleLocation", "80")
+                .flag("Extended")
+                .channel(1)
+                .baud("250")
+                .build()
+        );
+    }
+}
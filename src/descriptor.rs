@@ -0,0 +1,214 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use crate::bus::BusKind;
+use crate::connection::{Access, ClaimMode, OnReadError};
+use crate::connection_string::ConnectionString;
+use crate::timestamp::TimestampSource;
+use crate::ConnectionDescriptor;
+
+/// Builds a `ConnectionDescriptor` without parsing arguments, for applications that don't use clap.  Starts from
+/// the command line defaults.
+#[derive(Debug, Clone)]
+pub struct ConnectionDescriptorBuilder {
+    descriptor: ConnectionDescriptor,
+}
+
+impl ConnectionDescriptor {
+    /// Builder for connecting to `device` of RP1210 adapter `adapter`, e.g. `builder("NULN2R32", 1)`.
+    pub fn builder(adapter: &str, device: i16) -> ConnectionDescriptorBuilder {
+        ConnectionDescriptorBuilder {
+            descriptor: ConnectionDescriptor {
+                adapter: adapter.to_string(),
+                device,
+                connection_string: "J1939:Baud=Auto".to_string(),
+                baud: None,
+                channel: None,
+                source_address: 0xF9,
+                verbose: false,
+                app_packetize: false,
+                bus: BusKind::PushBus,
+                reset_before_connect: false,
+                inject_sent: false,
+                access: Access::ReadWrite,
+                connect_timeout: 10,
+                on_read_error: OnReadError::Retry,
+                idle_timeout: 0,
+                bus_state_interval: 0,
+                claim_timeout: None,
+                timestamps: TimestampSource::Adapter,
+                bridge: None,
+            },
+        }
+    }
+}
+
+impl ConnectionDescriptorBuilder {
+    pub fn connection_string(mut self, connection_string: &str) -> Self {
+        self.descriptor.connection_string = connection_string.to_string();
+        self
+    }
+
+    /// Bus speed, e.g. "500" or "Auto", replacing the connection string's Baud.  connect() checks that the
+    /// device supports it.
+    pub fn baud(mut self, baud: &str) -> Self {
+        self.descriptor.baud = Some(baud.to_string());
+        self
+    }
+
+    /// Channel of a multi-channel adapter, from 1.  connect() checks that the device has it.
+    pub fn channel(mut self, channel: u8) -> Self {
+        self.descriptor.channel = Some(channel);
+        self
+    }
+
+    pub fn source_address(mut self, address: u8) -> Self {
+        self.descriptor.source_address = address;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.descriptor.verbose = verbose;
+        self
+    }
+
+    pub fn app_packetize(mut self, app_packetize: bool) -> Self {
+        self.descriptor.app_packetize = app_packetize;
+        self
+    }
+
+    pub fn bus(mut self, bus: BusKind) -> Self {
+        self.descriptor.bus = bus;
+        self
+    }
+
+    pub fn reset_before_connect(mut self, reset: bool) -> Self {
+        self.descriptor.reset_before_connect = reset;
+        self
+    }
+
+    pub fn inject_sent(mut self, inject: bool) -> Self {
+        self.descriptor.inject_sent = inject;
+        self
+    }
+
+    pub fn access(mut self, access: Access) -> Self {
+        self.descriptor.access = access;
+        self
+    }
+
+    /// None waits as long as the driver does.  Rounded up to seconds.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.descriptor.connect_timeout =
+            timeout.map_or(0, |t| t.as_millis().div_ceil(1000) as u64);
+        self
+    }
+
+    pub fn on_read_error(mut self, on_read_error: OnReadError) -> Self {
+        self.descriptor.on_read_error = on_read_error;
+        self
+    }
+
+    /// None stays connected.  Rounded up to seconds.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.descriptor.idle_timeout = timeout.map_or(0, |t| t.as_millis().div_ceil(1000) as u64);
+        self
+    }
+
+    /// None doesn't read the bus state.  Rounded up to seconds.
+    pub fn bus_state_interval(mut self, interval: Option<Duration>) -> Self {
+        self.descriptor.bus_state_interval =
+            interval.map_or(0, |t| t.as_millis().div_ceil(1000) as u64);
+        self
+    }
+
+    /// How connecting waits for the address claim.  Timeouts are rounded up to milliseconds.
+    pub fn claim_mode(mut self, mode: ClaimMode) -> Self {
+        self.descriptor.claim_timeout = match mode {
+            ClaimMode::BlockUntilDone => None,
+            ClaimMode::NonBlocking => Some(0),
+            ClaimMode::Timeout(t) => Some((t.as_micros().div_ceil(1000) as u64).max(1)),
+        };
+        self
+    }
+
+    pub fn timestamps(mut self, timestamps: TimestampSource) -> Self {
+        self.descriptor.timestamps = timestamps;
+        self
+    }
+
+    /// Connect through the 32-bit `rp1210_bridge` executable at `bridge`.
+    pub fn bridge(mut self, bridge: Option<PathBuf>) -> Self {
+        self.descriptor.bridge = bridge;
+        self
+    }
+
+    /// The descriptor, once the connection string is checked as the command line does.
+    pub fn build(self) -> Result<ConnectionDescriptor> {
+        ConnectionString::parse(&self.descriptor.connection_string)?;
+        if let Some(baud) = &self.descriptor.baud {
+            crate::valid_baud(baud)?;
+        }
+        if self.descriptor.channel == Some(0) {
+            bail!("Channels start from 1");
+        }
+        Ok(self.descriptor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::Cli;
+
+    #[test]
+    fn defaults_match_the_command_line() -> Result<()> {
+        let cli = Cli::try_parse_from(["", "--adapter", "SIM", "--device", "1"])?;
+        assert_eq!(
+            cli.connection,
+            ConnectionDescriptor::builder("SIM", 1).build()?
+        );
+
+        let descriptor = ConnectionDescriptor::builder("SIM", 2)
+            .source_address(0xFE)
+            .access(Access::ReadOnly)
+            .connect_timeout(Some(Duration::from_millis(1500)))
+            .claim_mode(ClaimMode::Timeout(Duration::from_millis(250)))
+            .build()?;
+        assert_eq!(
+            (0xFE, Access::ReadOnly, 2),
+            (
+                descriptor.source_address,
+                descriptor.access,
+                descriptor.connect_timeout
+            )
+        );
+        assert_eq!(
+            ClaimMode::Timeout(Duration::from_millis(250)),
+            descriptor.connect_options().claim_mode
+        );
+        descriptor.connect()?.close()?;
+
+        let fast = ConnectionDescriptor::builder("SIM", 1).baud("500").build()?;
+        assert_eq!("J1939:Baud=500", fast.effective_connection_string()?);
+        assert!(ConnectionDescriptor::builder("SIM", 1)
+            .baud("1000")
+            .build()?
+            .connect()
+            .is_err());
+        assert!(ConnectionDescriptor::builder("SIM", 1)
+            .channel(3)
+            .build()?
+            .connect()
+            .is_err());
+        assert!(ConnectionDescriptor::builder("SIM", 1)
+            .connection_string("J1939:Baud=Fast")
+            .build()
+            .is_err());
+        Ok(())
+    }
+}
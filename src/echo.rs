@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::packet::J1939Packet;
+
+/// Drops the adapter's echoes of packets sent from `address`, for logging.
+///
+/// With echo on, the adapter echoes each packet we send, and some networks also repeat it back to us.
+/// Echoes are dropped, as are received copies with the same PGN and data within `window` (by packet time
+/// stamp) of an echo.  Sent packets injected into the stream (`J1939Packet::sent()`) and packets from other
+/// addresses are always passed through.
+pub struct EchoSuppressor {
+    address: u8,
+    window: f64,
+    recent: VecDeque<(f64, u32, Vec<u8>)>,
+}
+
+impl EchoSuppressor {
+    pub fn new(address: u8, window: Duration) -> Self {
+        Self {
+            address,
+            window: window.as_secs_f64(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Returns the packet, or None if it's an echo of one of ours.
+    pub fn push(&mut self, packet: J1939Packet) -> Option<J1939Packet> {
+        if packet.source() != self.address || packet.sent() {
+            return Some(packet);
+        }
+        let time = packet.time();
+        while self
+            .recent
+            .front()
+            .is_some_and(|(t, _, _)| time - t > self.window || time < *t)
+        {
+            self.recent.pop_front();
+        }
+        let pgn = packet.pgn();
+        if packet.echo() {
+            self.recent.push_back((time, pgn, packet.data().to_vec()));
+            return None;
+        }
+        let repeat = self
+            .recent
+            .iter()
+            .any(|(_, p, d)| *p == pgn && d.as_slice() == packet.data());
+        (!repeat).then_some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// received (not echoed) copy, time in microseconds
+    fn received(time: u32, sa: u8, data: &[u8]) -> J1939Packet {
+        let head = [&time.to_be_bytes()[..], &[0, 0x00, 0xEA, 0, 6, sa, 0]].concat();
+        J1939Packet::new_rp1210(false, 0, &[&head[..], data].concat(), 1.0)
+    }
+
+    #[test]
+    fn suppress_echoes() {
+        let mut echo = EchoSuppressor::new(0xF9, Duration::from_millis(100));
+        let request = [0xEC, 0xFE, 0x00];
+        let ours = |time| J1939Packet::new(Some(time), 0, 0x18EA00F9, &request);
+        assert!(echo.push(ours(1_000)).is_none());
+        // the network repeating our request back
+        assert!(echo.push(received(2_000, 0xF9, &request)).is_none());
+        assert!(echo.push(received(3_000, 0x00, &request)).is_some());
+        // outside the window, or different data, is someone else using our address
+        assert!(echo.push(received(200_000, 0xF9, &request)).is_some());
+        assert!(echo.push(received(200_001, 0xF9, &[0xEB, 0xFE, 0x00])).is_some());
+        // our own record of what we sent
+        let sent = J1939Packet::new(None, 0, 0x18EA00F9, &request).as_sent();
+        assert!(echo.push(sent).is_some());
+    }
+}
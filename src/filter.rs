@@ -0,0 +1,121 @@
+use crate::packet::{pgn_from_id, J1939Packet};
+
+/// RP1210 J1939 filter flags
+const FILTER_PGN: u8 = 0x01;
+const FILTER_PRIORITY: u8 = 0x02;
+const FILTER_SOURCE: u8 = 0x04;
+const FILTER_DESTINATION: u8 = 0x08;
+
+/// A pass filter for CMD_SET_MESSAGE_FILTERING_FOR_J1939.  Fields that aren't set match anything, so
+/// `J1939Filter::new().source(0)` passes everything from the engine.
+///
+/// Each filter is 7 bytes: flags, PGN (3 bytes, LSB first), priority, source and destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct J1939Filter {
+    pgn: Option<u32>,
+    priority: Option<u8>,
+    source: Option<u8>,
+    dest: Option<u8>,
+}
+
+impl J1939Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// PDU1 PGNs don't include the destination.  Use dest() to filter on it.
+    pub fn pgn(mut self, pgn: u32) -> Self {
+        self.pgn = Some(pgn);
+        self
+    }
+
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn source(mut self, source: u8) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn dest(mut self, dest: u8) -> Self {
+        self.dest = Some(dest);
+        self
+    }
+
+    /// Whether the adapter passes `packet` with this filter.  The PGN is compared without the destination.
+    pub fn matches(&self, packet: &J1939Packet) -> bool {
+        self.pgn.is_none_or(|pgn| pgn == pgn_from_id(packet.id()))
+            && self.priority.is_none_or(|priority| priority == packet.priority())
+            && self.source.is_none_or(|source| source == packet.source())
+            && self.dest.is_none_or(|dest| dest == packet.dest())
+    }
+
+    pub fn to_bytes(&self) -> [u8; 7] {
+        let flag = |set: bool, flag: u8| if set { flag } else { 0 };
+        let pgn = self.pgn.unwrap_or(0).to_le_bytes();
+        [
+            flag(self.pgn.is_some(), FILTER_PGN)
+                | flag(self.priority.is_some(), FILTER_PRIORITY)
+                | flag(self.source.is_some(), FILTER_SOURCE)
+                | flag(self.dest.is_some(), FILTER_DESTINATION),
+            pgn[0],
+            pgn[1],
+            pgn[2],
+            self.priority.unwrap_or(0),
+            self.source.unwrap_or(0),
+            self.dest.unwrap_or(0),
+        ]
+    }
+}
+
+/// Whether a received `packet` passes `filters`, as set with `apply_filters()`.  No filters passes everything.
+pub fn passes(filters: &[J1939Filter], packet: &J1939Packet) -> bool {
+    filters.is_empty() || filters.iter().any(|f| f.matches(packet))
+}
+
+/// Filters concatenated, as the command expects.
+pub fn filter_block(filters: &[J1939Filter]) -> Vec<u8> {
+    filters.iter().flat_map(|f| f.to_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        assert_eq!(
+            [0x01, 0xEC, 0xFE, 0x00, 0, 0, 0],
+            J1939Filter::new().pgn(0xFEEC).to_bytes()
+        );
+        assert_eq!([0x04, 0, 0, 0, 0, 0x17, 0], J1939Filter::new().source(0x17).to_bytes());
+        assert_eq!([0x08, 0, 0, 0, 0, 0, 0xF9], J1939Filter::new().dest(0xF9).to_bytes());
+        assert_eq!(
+            [0x0F, 0x00, 0xEA, 0x01, 6, 0x00, 0xF9],
+            J1939Filter::new()
+                .pgn(0x1EA00)
+                .priority(6)
+                .source(0)
+                .dest(0xF9)
+                .to_bytes()
+        );
+        assert_eq!(
+            14,
+            filter_block(&[J1939Filter::new(), J1939Filter::new().source(0)]).len()
+        );
+    }
+
+    #[test]
+    fn matching() {
+        let request = J1939Packet::new(None, 0, 0x18EA00F9, &[0xEC, 0xFE, 0x00]);
+        assert!(J1939Filter::new().pgn(0xEA00).dest(0x00).matches(&request));
+        assert!(J1939Filter::new().priority(6).source(0xF9).matches(&request));
+        assert!(!J1939Filter::new().pgn(0xEA00).dest(0xFF).matches(&request));
+        assert!(!J1939Filter::new().source(0x00).matches(&request));
+        assert!(passes(&[], &request));
+        assert!(passes(&[J1939Filter::new().source(0), J1939Filter::new().pgn(0xEA00)], &request));
+        assert!(!passes(&[J1939Filter::new().pgn(0xFEEC)], &request));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{Bus, BusIterator, BusKind};
+
+/// Size of the RP1210C RP1210_GetHardwareStatus buffer: 16 bytes each for the hardware, J1939, J1708 and CAN.
+pub const HARDWARE_STATUS_SIZE: usize = 64;
+/// Offset of the J1939 status in the RP1210C buffer.
+const J1939_STATUS: usize = 16;
+
+/// CAN error state of the adapter's J1939 link, from RP1210_GetHardwareStatus.
+///
+/// A bus-off node neither sends nor receives, so a bus-off adapter looks like a quiet bus, e.g. a vehicle that's
+/// switched off.  Bus-off and error-passive usually mean wiring or termination faults, or the wrong baud rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusState {
+    Active,
+    /// too many errors to signal them on the bus, but still sending and receiving
+    ErrorPassive,
+    /// too many transmit errors, so disconnected from the bus
+    BusOff,
+    /// the J1939 link isn't activated, e.g. no client is connected to it
+    Inactive,
+}
+
+impl BusState {
+    /// Parse the J1939 status of an RP1210C hardware status buffer: bit 0 of its first byte is set while the link
+    /// is activated, bit 1 while transmit is bus-off, and bit 2 while it's error-passive.  None if the buffer is
+    /// too short.
+    pub fn parse(buf: &[u8]) -> Option<BusState> {
+        let status = *buf.get(J1939_STATUS)?;
+        Some(if status & 0x01 == 0 {
+            BusState::Inactive
+        } else if status & 0x02 != 0 {
+            BusState::BusOff
+        } else if status & 0x04 != 0 {
+            BusState::ErrorPassive
+        } else {
+            BusState::Active
+        })
+    }
+
+    /// Nothing can be sent or received.
+    pub fn is_off(&self) -> bool {
+        matches!(self, BusState::BusOff | BusState::Inactive)
+    }
+}
+
+/// The last bus state read and its changes, shared by a connection's handles and its read thread.
+#[derive(Clone)]
+pub(crate) struct BusStates {
+    state: Arc<Mutex<Option<BusState>>>,
+    changes: Box<dyn Bus<BusState>>,
+}
+
+impl BusStates {
+    pub(crate) fn new() -> BusStates {
+        BusStates {
+            state: Default::default(),
+            changes: BusKind::PushBus.create(),
+        }
+    }
+
+    /// Record `state`, read from `adapter`, reporting it if it changed.
+    pub(crate) fn update(&mut self, adapter: &str, state: BusState) {
+        let previous = self.state.lock().unwrap().replace(state);
+        if previous == Some(state) {
+            return;
+        }
+        if state == BusState::Active {
+            log::info!("{}: bus {:?}", adapter, state);
+        } else {
+            log::warn!("{}: bus {:?}", adapter, state);
+        }
+        self.changes.push(Some(state));
+    }
+
+    pub(crate) fn get(&self) -> Option<BusState> {
+        *self.state.lock().unwrap()
+    }
+
+    pub(crate) fn iter(&self) -> Box<dyn BusIterator<BusState>> {
+        self.changes.iter()
+    }
+
+    pub(crate) fn close(&mut self) {
+        self.changes.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let status = |j1939: u8| {
+            let mut buf = [0; HARDWARE_STATUS_SIZE];
+            buf[J1939_STATUS] = j1939;
+            BusState::parse(&buf)
+        };
+        assert_eq!(Some(BusState::Active), status(0x01));
+        assert_eq!(Some(BusState::BusOff), status(0x03));
+        assert_eq!(Some(BusState::ErrorPassive), status(0x05));
+        assert_eq!(Some(BusState::Inactive), status(0x00));
+        assert_eq!(None, BusState::parse(&[0x01; 16]));
+        assert!(BusState::BusOff.is_off() && !BusState::ErrorPassive.is_off());
+    }
+}
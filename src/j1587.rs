@@ -0,0 +1,98 @@
+use std::fmt::{Display, Formatter};
+
+/// Default J1708 transmit priority (lowest).
+pub const DEFAULT_PRIORITY: u8 = 8;
+/// PID 255 escapes to page 2 PIDs (256-511).
+const PAGE_2: u8 = 255;
+
+/// A J1708 message with its J1587 MID and first PID.
+///
+/// RP1210 reads are time stamp (4 bytes, big endian), echo (1 byte) and the message without the checksum.
+/// Sends are priority (1 byte) and the message; the adapter adds the checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct J1587Message {
+    /// adapter time stamp in microseconds.  None for messages to send.
+    pub time: Option<u32>,
+    pub echo: bool,
+    pub priority: u8,
+    pub mid: u8,
+    /// PID followed by its data, and any further PIDs
+    pub data: Vec<u8>,
+}
+
+impl J1587Message {
+    /// Message to send.  `data` starts with the PID.
+    pub fn new(mid: u8, data: &[u8]) -> J1587Message {
+        J1587Message {
+            time: None,
+            echo: false,
+            priority: DEFAULT_PRIORITY,
+            mid,
+            data: data.to_vec(),
+        }
+    }
+
+    /// Parse a message read from RP1210.  None if too short to have a MID.
+    pub fn from_rp1210(buf: &[u8]) -> Option<J1587Message> {
+        if buf.len() < 6 {
+            return None;
+        }
+        Some(J1587Message {
+            time: Some(u32::from_be_bytes(buf[0..4].try_into().unwrap())),
+            echo: buf[4] != 0,
+            priority: DEFAULT_PRIORITY,
+            mid: buf[5],
+            data: buf[6..].to_vec(),
+        })
+    }
+
+    pub fn to_rp1210_tx(&self) -> Vec<u8> {
+        [&[self.priority, self.mid][..], &self.data].concat()
+    }
+
+    /// First PID, including page 2 PIDs.
+    pub fn pid(&self) -> Option<u16> {
+        match self.data.first()? {
+            &PAGE_2 => self.data.get(1).map(|p| 256 + *p as u16),
+            p => Some(*p as u16),
+        }
+    }
+}
+
+impl Display for J1587Message {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:12.4} MID {:3} PID {} [{}]",
+            self.time.unwrap_or(0) as f64 * 0.000001,
+            self.mid,
+            self.pid().map_or("-".to_string(), |p| p.to_string()),
+            self.data.len(),
+        )?;
+        for b in &self.data {
+            write!(f, " {:02X}", b)?;
+        }
+        if self.echo {
+            write!(f, " (TX)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rp1210_round_trip() {
+        let m = J1587Message::from_rp1210(&[0, 0, 0x03, 0xE8, 0, 128, 84, 100]).unwrap();
+        assert_eq!((Some(1000), 128, Some(84)), (m.time, m.mid, m.pid()));
+        assert_eq!("      0.0010 MID 128 PID 84 [2] 54 64", m.to_string());
+        assert_eq!(
+            vec![8, 128, 84, 100],
+            J1587Message::new(128, &[84, 100]).to_rp1210_tx()
+        );
+        assert_eq!(Some(256 + 10), J1587Message::new(128, &[255, 10, 1]).pid());
+        assert!(J1587Message::from_rp1210(&[0, 0, 0, 0, 0]).is_none());
+    }
+}
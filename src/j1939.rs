@@ -0,0 +1,379 @@
+use crate::packet::{Direction, J1939Packet};
+use crate::tp;
+
+/// Request PGN
+pub const REQUEST: u32 = 0xEA00;
+/// DM7 Command Non-Continuously Monitored Test
+pub const DM7: u32 = 0xE300;
+/// DM30 Scaled Test Results
+pub const DM30: u32 = 0xA400;
+
+/// Acknowledgment, answering a request with ACK or NACK
+pub const ACKNOWLEDGMENT: u32 = 0xE800;
+/// Address Claimed
+pub const ADDRESS_CLAIMED: u32 = 0xEE00;
+/// Source address of Cannot Claim Address, and of tools that haven't claimed an address.
+///
+/// J1939-81 only allows a node without an address to send Requests, e.g. for Address Claimed, and Cannot Claim
+/// Address.  Responses to a Request from the null address are sent to the global address.
+pub const NULL_ADDRESS: u8 = 0xFE;
+/// Component Identification
+pub const COMPONENT_ID: u32 = 0xFEEB;
+
+/// Test identifier requesting all test results for an SPN/FMI.
+pub const ALL_TESTS: u8 = 247;
+
+/// SPN and FMI as packed in DM7 and DM30.
+fn spn_fmi(spn: u32, fmi: u8) -> [u8; 3] {
+    [
+        spn as u8,
+        (spn >> 8) as u8,
+        (((spn >> 16) & 0x7) << 5) as u8 | (fmi & 0x1F),
+    ]
+}
+
+/// DM7 payload to command a test.
+pub fn dm7(test_id: u8, spn: u32, fmi: u8) -> [u8; 8] {
+    let s = spn_fmi(spn, fmi);
+    [test_id, s[0], s[1], s[2], 0xFF, 0xFF, 0xFF, 0xFF]
+}
+
+/// One test result from a DM30.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    /// address of the ECU that reported the result
+    pub source: u8,
+    pub test_id: u8,
+    pub spn: u32,
+    pub fmi: u8,
+    /// Scaling, Limit, Offset, and Transfer function identifier (J1939-71) for value, max and min.
+    pub slot: u16,
+    pub value: u16,
+    pub max: u16,
+    pub min: u16,
+}
+
+impl TestResult {
+    /// Parse all of the 12 byte test results in a DM30 payload.
+    pub fn parse_dm30(source: u8, data: &[u8]) -> Vec<TestResult> {
+        data.chunks_exact(12)
+            .map(|d| TestResult {
+                source,
+                test_id: d[0],
+                spn: d[1] as u32 | (d[2] as u32) << 8 | ((d[3] as u32) >> 5) << 16,
+                fmi: d[3] & 0x1F,
+                slot: u16::from_le_bytes([d[4], d[5]]),
+                value: u16::from_le_bytes([d[6], d[7]]),
+                max: u16::from_le_bytes([d[8], d[9]]),
+                min: u16::from_le_bytes([d[10], d[11]]),
+            })
+            .collect()
+    }
+
+    /// 0xFB00 indicates the test has not completed.
+    pub fn completed(&self) -> bool {
+        self.value != 0xFB00
+    }
+}
+
+/// Outcome of claiming a J1939 address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressClaim {
+    pub requested: u8,
+    /// Address of the last Address Claimed carrying our NAME that the adapter echoed while claiming.
+    /// NULL_ADDRESS if the adapter sent Cannot Claim.  None if the adapter doesn't echo the claims it sends,
+    /// as RP1210 has no command to read the claimed address back.
+    pub claimed: Option<u8>,
+    /// another node claimed the same address while we were claiming it
+    pub contention: bool,
+}
+
+impl AddressClaim {
+    /// Outcome of claiming `requested` as `name`, from the packets seen while claiming.
+    ///
+    /// Received claims are another node's, even with our NAME (e.g. a second tool using the same NAME).
+    pub(crate) fn from_packets(
+        requested: u8,
+        name: &J1939Name,
+        packets: impl IntoIterator<Item = J1939Packet>,
+    ) -> AddressClaim {
+        let mut claim = AddressClaim {
+            requested,
+            claimed: None,
+            contention: false,
+        };
+        for p in packets {
+            claim.add(name, &p);
+        }
+        claim
+    }
+
+    /// Update the outcome with `p`, seen while claiming as `name`.
+    pub(crate) fn add(&mut self, name: &J1939Name, p: &J1939Packet) {
+        if tp::base_pgn(p) != ADDRESS_CLAIMED || p.data().len() != 8 {
+            return;
+        }
+        if p.direction() == Direction::Rx {
+            self.contention |= p.source() == self.requested;
+        } else if p.data() == name.to_bytes() {
+            self.claimed = Some(p.source());
+        }
+    }
+}
+
+/// Address Claimed (or Cannot Claim Address, from NULL_ADDRESS) to send.
+pub fn address_claimed(address: u8, name: &J1939Name) -> J1939Packet {
+    J1939Packet::new(None, 0, 0x18EEFF00 | address as u32, &name.to_bytes())
+}
+
+/// Connections that haven't claimed an address send from the null address.  0xFF is the global address, which is
+/// never a source, so it also means the null address.
+pub(crate) fn unclaimed(address: u8) -> bool {
+    address >= NULL_ADDRESS
+}
+
+/// `packet` as sent from `address`: from the null address, the source is always NULL_ADDRESS.
+pub(crate) fn outgoing(packet: &J1939Packet, address: u8) -> J1939Packet {
+    if unclaimed(address) && packet.source() != NULL_ADDRESS {
+        packet.with_source(NULL_ADDRESS)
+    } else {
+        packet.clone()
+    }
+}
+
+/// Request (59904) for `pgn` from `dest` (0xFF for all nodes).
+pub fn request(pgn: u32, dest: u8, source: u8) -> J1939Packet {
+    let head = 0x18000000 | REQUEST << 8 | (dest as u32) << 8 | source as u32;
+    J1939Packet::new(None, 0, head, &pgn.to_le_bytes()[..3])
+}
+
+/// Control byte of an Acknowledgment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckControl {
+    Ack = 0,
+    Nack = 1,
+    AccessDenied = 2,
+    /// cannot respond now, e.g. still busy with an earlier request
+    Busy = 3,
+}
+
+/// Acknowledgment (59392) from `source` of `pgn`, requested by `dest`.  J1939-21 sends it to the global address,
+/// with the requester's address in the payload.  The group function value isn't used, so is 0xFF.
+pub fn acknowledgment(control: AckControl, pgn: u32, dest: u8, source: u8) -> J1939Packet {
+    let head = 0x18000000 | ACKNOWLEDGMENT << 8 | 0xFF << 8 | source as u32;
+    let pgn = pgn.to_le_bytes();
+    let data = [control as u8, 0xFF, 0xFF, 0xFF, dest, pgn[0], pgn[1], pgn[2]];
+    J1939Packet::new(None, 0, head, &data)
+}
+
+/// J1939-81 NAME, sent in Address Claimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct J1939Name {
+    pub arbitrary_address_capable: bool,
+    pub industry_group: u8,
+    pub vehicle_system_instance: u8,
+    pub vehicle_system: u8,
+    pub reserved: bool,
+    pub function: u8,
+    pub function_instance: u8,
+    pub ecu_instance: u8,
+    pub manufacturer_code: u16,
+    pub identity_number: u32,
+}
+
+impl J1939Name {
+    pub fn from_bytes(bytes: &[u8; 8]) -> J1939Name {
+        let name = u64::from_le_bytes(*bytes);
+        let bits = |shift: u32, len: u32| (name >> shift) & ((1 << len) - 1);
+        J1939Name {
+            identity_number: bits(0, 21) as u32,
+            manufacturer_code: bits(21, 11) as u16,
+            ecu_instance: bits(32, 3) as u8,
+            function_instance: bits(35, 5) as u8,
+            function: bits(40, 8) as u8,
+            reserved: bits(48, 1) != 0,
+            vehicle_system: bits(49, 7) as u8,
+            vehicle_system_instance: bits(56, 4) as u8,
+            industry_group: bits(60, 3) as u8,
+            arbitrary_address_capable: bits(63, 1) != 0,
+        }
+    }
+
+    /// Pack the fields.  Values too large for their field are truncated.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let field = |value: u64, shift: u32, len: u32| (value & ((1 << len) - 1)) << shift;
+        (field(self.identity_number as u64, 0, 21)
+            | field(self.manufacturer_code as u64, 21, 11)
+            | field(self.ecu_instance as u64, 32, 3)
+            | field(self.function_instance as u64, 35, 5)
+            | field(self.function as u64, 40, 8)
+            | field(self.reserved as u64, 48, 1)
+            | field(self.vehicle_system as u64, 49, 7)
+            | field(self.vehicle_system_instance as u64, 56, 4)
+            | field(self.industry_group as u64, 60, 3)
+            | field(self.arbitrary_address_capable as u64, 63, 1))
+        .to_le_bytes()
+    }
+}
+
+/// NAME claimed by the adapter: an off-board diagnostic-service tool (function 129) with no manufacturer assigned.
+pub const TOOL_NAME: J1939Name = J1939Name {
+    arbitrary_address_capable: false,
+    industry_group: 0,
+    vehicle_system_instance: 0,
+    vehicle_system: 0,
+    reserved: false,
+    function: 129,
+    function_instance: 0,
+    ecu_instance: 0,
+    manufacturer_code: 0x7FF,
+    identity_number: 0,
+};
+
+/// A node found by `Connection::scan_network()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub address: u8,
+    pub name: J1939Name,
+}
+
+/// Component Identification (PGN 65259)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ComponentId {
+    pub make: String,
+    pub model: String,
+    pub serial_number: String,
+    pub unit_number: String,
+}
+
+impl ComponentId {
+    /// Parse the `*` delimited fields.  Missing fields are empty.
+    pub fn parse(data: &[u8]) -> ComponentId {
+        let text = String::from_utf8_lossy(data);
+        let mut fields = text.split('*').map(|s| s.trim().to_string());
+        ComponentId {
+            make: fields.next().unwrap_or_default(),
+            model: fields.next().unwrap_or_default(),
+            serial_number: fields.next().unwrap_or_default(),
+            unit_number: fields.next().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acknowledgment() {
+        let nack = super::acknowledgment(AckControl::Nack, 0xFECA, 0x17, 0xF9);
+        assert_eq!((0x18E8FFF9, 0xE8FF), (nack.id(), nack.pgn()));
+        assert_eq!(&[1, 0xFF, 0xFF, 0xFF, 0x17, 0xCA, 0xFE, 0x00], nack.data());
+    }
+
+    #[test]
+    fn dm30_round_trip() {
+        let spn = 0x7FFFF - 1;
+        let s = spn_fmi(spn, 31);
+        let data = [
+            &[
+                ALL_TESTS, s[0], s[1], s[2], 0x01, 0x02, 0x10, 0x00, 0x20, 0x00, 0x05, 0x00,
+            ][..],
+            &[
+                0xF7, 0x64, 0x00, 0x03, 0x01, 0x02, 0x00, 0xFB, 0xFF, 0xFF, 0xFF, 0xFF,
+            ],
+        ]
+        .concat();
+        let results = TestResult::parse_dm30(0, &data);
+        assert_eq!(2, results.len());
+        assert_eq!(spn, results[0].spn);
+        assert_eq!(31, results[0].fmi);
+        assert_eq!(0x0201, results[0].slot);
+        assert_eq!(
+            (0x10, 0x20, 0x05),
+            (results[0].value, results[0].max, results[0].min)
+        );
+        assert!(results[0].completed());
+        assert_eq!((100, 3), (results[1].spn, results[1].fmi));
+        assert!(!results[1].completed());
+        assert_eq!(
+            [0xF7, 0x64, 0x00, 0x03, 0xFF, 0xFF, 0xFF, 0xFF],
+            dm7(ALL_TESTS, 100, 3)
+        );
+    }
+
+    #[test]
+    fn name_from_bytes() {
+        // Cummins (33) engine (function 0), identity 0x12345, on-highway (industry group 1)
+        let name = J1939Name::from_bytes(&[0x45, 0x23, 0x21, 0x04, 0x00, 0x00, 0x00, 0x90]);
+        assert_eq!(0x12345, name.identity_number);
+        assert_eq!(33, name.manufacturer_code);
+        assert_eq!((0, 0, 0), (name.function, name.function_instance, name.ecu_instance));
+        assert_eq!(1, name.industry_group);
+        assert!(name.arbitrary_address_capable);
+
+        let name = J1939Name::from_bytes(&[0, 0, 0, 0, 0x0B, 0x81, 0x00, 0x00]);
+        assert_eq!((0x81, 1, 3), (name.function, name.function_instance, name.ecu_instance));
+        assert!(!name.arbitrary_address_capable);
+    }
+
+    #[test]
+    fn name_round_trip() {
+        for bytes in [
+            [0x45, 0x23, 0x21, 0x04, 0x00, 0x00, 0x00, 0x90],
+            [0xFF; 8],
+            [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+        ] {
+            assert_eq!(bytes, J1939Name::from_bytes(&bytes).to_bytes());
+        }
+        assert_eq!([0, 0, 0xE0, 0xFF, 0, 0x81, 0, 0], TOOL_NAME.to_bytes());
+    }
+
+    #[test]
+    fn address_claim() {
+        let other = J1939Name {
+            identity_number: 1,
+            ..TOOL_NAME
+        };
+        let echoed = |address: u8, name: &J1939Name| address_claimed(address, name).as_sent();
+        let received = |address: u8, name: &J1939Name| {
+            J1939Packet::new(Some(0), 0, 0x18EEFF00 | address as u32, &name.to_bytes()).as_received()
+        };
+        let claim = AddressClaim::from_packets(0xF9, &TOOL_NAME, [echoed(0xF9, &TOOL_NAME)]);
+        assert_eq!((Some(0xF9), false), (claim.claimed, claim.contention));
+        // another tool with the same NAME
+        let claim = AddressClaim::from_packets(
+            0xF9,
+            &TOOL_NAME,
+            [echoed(0xF9, &TOOL_NAME), received(0xF9, &TOOL_NAME)],
+        );
+        assert_eq!((Some(0xF9), true), (claim.claimed, claim.contention));
+        let claim = AddressClaim::from_packets(
+            0xF9,
+            &TOOL_NAME,
+            [
+                echoed(0xF9, &TOOL_NAME),
+                received(0xF9, &other),
+                echoed(NULL_ADDRESS, &TOOL_NAME),
+            ],
+        );
+        assert_eq!((Some(NULL_ADDRESS), true), (claim.claimed, claim.contention));
+        // claims for other addresses, and adapters that don't echo their claims
+        let claim = AddressClaim::from_packets(0xF9, &TOOL_NAME, [received(0xFA, &other)]);
+        assert_eq!((None, false), (claim.claimed, claim.contention));
+    }
+
+    #[test]
+    fn component_id() {
+        assert_eq!(
+            ComponentId {
+                make: "CMMNS".into(),
+                model: "X15".into(),
+                serial_number: "12345".into(),
+                unit_number: "".into(),
+            },
+            ComponentId::parse(b"CMMNS*X15*12345**")
+        );
+        assert_eq!("MAKE", ComponentId::parse(b"MAKE").make);
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::Result;
+
+/// Calls `send` every `interval` on a dedicated thread until stopped or dropped.
+///
+/// Used to hold diagnostic sessions open with periodic traffic from the tool.
+pub(crate) struct Keepalive {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Keepalive {
+    pub(crate) fn start(
+        interval: Duration,
+        mut send: impl FnMut() -> Result<()> + Send + 'static,
+    ) -> Result<Keepalive> {
+        let (stop, stopped) = channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("keepalive".into())
+            .spawn(move || loop {
+                if let Err(e) = send() {
+                    log::warn!("keepalive: {}", e);
+                }
+                // dropping the sender wakes the thread immediately
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            })?;
+        Ok(Keepalive {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Stop sending.  Returns after the last send has completed.
+    pub(crate) fn stop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn sends_until_stopped() -> Result<()> {
+        let count = Arc::new(AtomicUsize::new(0));
+        let (sent, sends) = std::sync::mpsc::channel();
+        let mut keepalive = {
+            let count = count.clone();
+            Keepalive::start(Duration::from_millis(10), move || {
+                count.fetch_add(1, Ordering::Relaxed);
+                let _ = sent.send(());
+                Ok(())
+            })?
+        };
+        for _ in 0..3 {
+            sends.recv_timeout(Duration::from_secs(5))?;
+        }
+        keepalive.stop();
+        // stop() waits for the thread, so nothing is sent afterwards
+        let sent = count.load(Ordering::Relaxed);
+        assert!(sent >= 3);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(sent, count.load(Ordering::Relaxed));
+        Ok(())
+    }
+}
@@ -1,30 +1,63 @@
-use std::{fmt::Write, time::Duration};
+use std::{fmt::Write, path::PathBuf, thread, time::Duration};
 
+use bus::BusKind;
 use clap::{Args, CommandFactory, FromArgMatches, Parser};
-use connection::Connection;
+use connection::{Access, ClaimMode, ConnectOptions, Connection, ConnectionFactory, OnReadError};
+use output::OutputFormat;
 use packet::J1939Packet;
+use timestamp::TimestampSource;
 
+pub mod aggregate;
+pub mod bridge;
 pub mod bus;
+pub mod clock;
 pub mod connection;
+pub mod connection_string;
+pub mod descriptor;
+pub mod echo;
+pub mod filter;
+pub mod hardware_status;
+pub mod j1587;
+pub mod j1939;
+pub(crate) mod keepalive;
+pub mod output;
 pub mod packet;
+pub mod pcap;
+pub mod pgn_defs;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod registry;
+pub mod ring_capture;
 
 #[cfg_attr(
-    not(all(target_os = "windows")),
+    not(all(target_os = "windows", feature = "rp1210", not(feature = "sim-only"))),
     path = "sim.rs"
 )]
 #[cfg_attr(
-    all(target_os = "windows"),
+    all(target_os = "windows", feature = "rp1210", not(feature = "sim-only")),
     path = "rp1210.rs"
 )]
 pub mod rp1210;
+pub mod rp1210_error;
 pub mod rp1210_parsing;
+pub mod scheduler;
+pub mod session;
+#[cfg(all(target_os = "linux", feature = "socketcan"))]
+pub mod socketcan;
+pub mod timestamp;
+pub mod tp;
+pub mod wireless;
 
 #[derive(Parser, Debug, Default, Clone)]
 pub struct Cli {
     #[command(flatten)]
     pub connection: ConnectionDescriptor,
+
+    /// How each packet is printed
+    #[arg(long, value_enum, default_value_t = OutputFormat::Default)]
+    pub format: OutputFormat,
 }
-#[derive(Args, Debug, Default, Clone)]
+#[derive(Args, Debug, Default, Clone, PartialEq)]
 pub struct ConnectionDescriptor {
     /// RP1210 Adapter Identifier
     #[arg(long, short('D'))]
@@ -34,49 +67,233 @@ pub struct ConnectionDescriptor {
     #[arg(long, short('d'))]
     pub device: i16,
 
-    #[arg(long, short('C'), default_value = "J1939:Baud=Auto")]
+    #[arg(long, short('C'), default_value = "J1939:Baud=Auto", value_parser = valid_connection_string)]
     /// RP1210 Connection String
     pub connection_string: String,
 
+    /// Bus speed, e.g. 500 or Auto, replacing the connection string's Baud.  Checked against the speeds the adapter
+    /// supports
+    #[arg(long, value_parser = valid_baud)]
+    pub baud: Option<String>,
+
+    /// Channel of a multi-channel adapter, from 1.  Checked against the channels the adapter lists
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    pub channel: Option<u8>,
+
     #[arg(long="sa", short('a'), default_value = "F9",value_parser=hex8)]
-    /// RP1210 Adapter Address (used for packets send and transport protocol)
+    /// RP1210 Adapter Address (used for packets send and transport protocol).  FE sends requests without claiming an address
     pub source_address: u8,
 
+    /// Log debug messages and every RP1210 driver call with its arguments and return code
     #[arg(long, short('v'), default_value = "false")]
     pub verbose: bool,
 
     #[arg(long, default_value = "false")]
     pub app_packetize: bool,
+
+    /// Bus implementation
+    #[arg(long, value_enum, default_value_t = BusKind::PushBus)]
+    pub bus: BusKind,
+
+    /// Reset the adapter (RP1210_Reset_Device) before connecting, for adapters that stop responding
+    #[arg(long, default_value = "false")]
+    pub reset_before_connect: bool,
+
+    /// Include sent packets in the received stream, marked (TX), regardless of the adapter's echo setting
+    #[arg(long, default_value = "false")]
+    pub inject_sent: bool,
+
+    /// Read-only clients don't claim an address or transmit, so they can share the adapter with another tool
+    #[arg(long, value_enum, default_value_t = Access::ReadWrite)]
+    pub access: Access,
+
+    /// Seconds to wait for the adapter to connect and claim the address before failing.  0 waits as long as the
+    /// driver does
+    #[arg(long, default_value_t = 10)]
+    pub connect_timeout: u64,
+
+    /// When the driver fails a read, retry, or stop reading so the failure reaches the application
+    #[arg(long, value_enum, default_value_t = OnReadError::Retry)]
+    pub on_read_error: OnReadError,
+
+    /// Seconds without receiving anything, e.g. after the vehicle is switched off, before disconnecting to free
+    /// the adapter.  0 stays connected
+    #[arg(long, default_value_t = 0)]
+    pub idle_timeout: u64,
+
+    /// Seconds between reads of the adapter's hardware status, logging bus-off and error-passive, which otherwise
+    /// look like a quiet bus.  0 doesn't read it
+    #[arg(long, default_value_t = 0)]
+    pub bus_state_interval: u64,
+
+    /// Milliseconds to wait for the address claim without blocking in the driver, failing if another node won't
+    /// yield the address.  0 doesn't wait for the outcome.  Unset, the driver blocks until the claim is done, up
+    /// to the connect timeout
+    #[arg(long)]
+    pub claim_timeout: Option<u64>,
+
+    /// Source of received packet time stamps.  Host uses the PC's clock, for adapters with unreliable time stamps
+    #[arg(long, value_enum, default_value_t = TimestampSource::Adapter)]
+    pub timestamps: TimestampSource,
+
+    /// 32-bit rp1210_bridge executable, used to load 32-bit RP1210 drivers from a 64-bit process
+    #[arg(long)]
+    pub bridge: Option<PathBuf>,
 }
 
 impl ConnectionDescriptor {
-    pub fn connect(&self) -> Result<impl Connection, anyhow::Error> {
+    pub fn connect(&self) -> Result<Box<dyn Connection>, anyhow::Error> {
+        if let Some(bridge) = &self.bridge {
+            return Ok(Box::new(bridge::Bridge::new(bridge, self)?));
+        }
         // FIXME don't assume RP1210.  Also support J2534
-        rp1210::Rp1210::new(
+        let connection_string = self.effective_connection_string()?;
+        if let Some(channel) = self.channel {
+            let protocol = connection_string::ConnectionString::parse(&connection_string)?.protocol;
+            rp1210_parsing::check_channel(&self.adapter, self.device, &protocol, channel)?;
+        }
+        if self.reset_before_connect {
+            rp1210::Rp1210::reset(&self.adapter, self.device, &connection_string)?;
+        }
+        #[allow(deprecated)]
+        let mut rp1210 = rp1210::Rp1210::new_with_options(
             &self.adapter,
             self.device,
-            None,
-            &self.connection_string,
+            self.channel,
+            &connection_string,
             self.source_address,
             false,
-        )
+            &self.connect_options(),
+            self.bus,
+        )?;
+        rp1210.set_inject_sent(self.inject_sent);
+        rp1210.set_timestamp_source(self.timestamps);
+        Ok(Box::new(rp1210))
+    }
+
+    /// The connection string with `baud` applied, once the device is known to support it.
+    pub fn effective_connection_string(&self) -> Result<String, anyhow::Error> {
+        match &self.baud {
+            Some(baud) => {
+                let protocol =
+                    connection_string::ConnectionString::parse(&self.connection_string)?.protocol;
+                rp1210_parsing::check_baud(&self.adapter, self.device, &protocol, baud)?;
+                Ok(connection_string::ConnectionStringBuilder::from_connection_string(
+                    &self.connection_string,
+                )
+                .baud(baud)
+                .build())
+            }
+            None => Ok(self.connection_string.clone()),
+        }
+    }
+
+    /// Options for connecting the adapter.
+    pub fn connect_options(&self) -> ConnectOptions {
+        ConnectOptions {
+            access: self.access,
+            connect_timeout: (self.connect_timeout > 0)
+                .then(|| Duration::from_secs(self.connect_timeout)),
+            on_read_error: self.on_read_error,
+            idle_timeout: (self.idle_timeout > 0).then(|| Duration::from_secs(self.idle_timeout)),
+            bus_state_interval: (self.bus_state_interval > 0)
+                .then(|| Duration::from_secs(self.bus_state_interval)),
+            claim_mode: match self.claim_timeout {
+                None => ClaimMode::BlockUntilDone,
+                Some(0) => ClaimMode::NonBlocking,
+                Some(ms) => ClaimMode::Timeout(Duration::from_millis(ms)),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// connect(), retrying up to `attempts` times while the device is in use by another client
+    /// (e.g. a previous process that hasn't finished disconnecting).
+    pub fn connect_with_retry(
+        &self,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<Box<dyn Connection>, anyhow::Error> {
+        let mut attempt = 1;
+        loop {
+            match self.connect() {
+                Err(e)
+                    if attempt < attempts
+                        && rp1210_error::code(&e) == Some(rp1210_error::ERR_DEVICE_IN_USE) =>
+                {
+                    attempt += 1;
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl ConnectionFactory for ConnectionDescriptor {
+    fn connect(&self) -> Result<Box<dyn Connection>, anyhow::Error> {
+        ConnectionDescriptor::connect(self)
     }
 }
 
+/// Writes log records to stderr.  Installed by the command line tools with `init_logging()`.
+/// Applications embedding this crate route the `log` records to their own logger instead.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Log warnings and errors to stderr, plus debug messages and a trace of every RP1210 driver call if `verbose`.
+pub fn init_logging(verbose: bool) {
+    if log::set_logger(&StderrLogger).is_ok() {
+        log::set_max_level(if verbose {
+            log::LevelFilter::Trace
+        } else {
+            log::LevelFilter::Warn
+        });
+    }
+}
+
+fn valid_connection_string(s: &str) -> Result<String, anyhow::Error> {
+    connection_string::ConnectionString::parse(s)?;
+    Ok(s.to_string())
+}
+
+fn valid_baud(s: &str) -> Result<String, anyhow::Error> {
+    if !s.eq_ignore_ascii_case("Auto") && s.parse::<u32>().is_err() {
+        anyhow::bail!("Baud must be Auto or a number");
+    }
+    Ok(s.to_string())
+}
+
 fn hex8(str: &str) -> Result<u8, std::num::ParseIntError> {
     u8::from_str_radix(str, 16)
 }
 
 pub fn main() -> Result<(), anyhow::Error> {
     // parse command
-    let help = rp1210_parsing::list_all_products()
-        .unwrap()
+    let products = rp1210_parsing::list_all_products()?;
+    let help = products
         .iter()
         .flat_map(|p| {
-            std::iter::once(format!(
-                color_print::cstr!("  <b>{}</> <b>{}</>"),
-                p.id, p.description
-            ))
+            std::iter::once(match &p.error {
+                Some(error) => format!(color_print::cstr!("  <b>{}</> {}"), p.id, error),
+                None => format!(
+                    color_print::cstr!("  <b>{}</> <b>{}</>"),
+                    p.id, p.description
+                ),
+            })
             .chain(p.devices.iter().map(|dev| {
                 format!(
                     color_print::cstr!("    --adapter <bold>{}</> --device <bold>{}</>: {}"),
@@ -92,9 +309,19 @@ pub fn main() -> Result<(), anyhow::Error> {
     let mut usage = command.render_usage();
     usage.write_str(color_print::cstr!("\n\n<bold>RP1210 Devices:<bold>\n"))?;
     usage.write_str(help.as_str())?;
-    command = command.override_usage(usage);
+    command = command
+        .override_usage(usage)
+        .subcommand(
+            clap::Command::new("list").about("Print the RP1210 adapters and devices as JSON"),
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true);
     let parse = {
         let mut matches = command.clone().get_matches();
+        if matches.subcommand_name() == Some("list") {
+            println!("{}", serde_json::to_string_pretty(&products)?);
+            return Ok(());
+        }
         let res = Cli::from_arg_matches_mut(&mut matches).map_err(|err| err.format(&mut command));
         match res {
             Ok(s) => s,
@@ -102,6 +329,8 @@ pub fn main() -> Result<(), anyhow::Error> {
         }
     };
 
+    init_logging(parse.connection.verbose);
+
     // open the adapter
     let mut rp1210 = parse.connection.connect()?;
 
@@ -112,18 +341,11 @@ pub fn main() -> Result<(), anyhow::Error> {
     rp1210.send(&J1939Packet::new(None,1, 0x18EA00F9, &[0xEC, 0xFE, 0x00]))?;
 
     // filter for ECM result
-    packets
-        .find(|p| p.pgn() == 0xFEEC && p.source() == 0)
-        // log the VIN
-        .map(|p| {
-            print!(
-                    "ECM {:02X} VIN: {}\n{}",
-                    p.source(),
-                    String::from_utf8(p.data().into()).unwrap(),
-                    p
-                )
-            },
-        );
+    if let Some(p) = packets.find(|p| p.pgn() == 0xFEEC && p.source() == 0) {
+        // log the VIN.  Only packets go to stdout, so --format json and candump output stays machine readable.
+        eprintln!("ECM {:02X} VIN: {}", p.source(), String::from_utf8_lossy(p.data()));
+        println!("{}", parse.format.format(&p));
+    }
     }
 {    // request VIN from Broadcast
     // start collecting packets
@@ -136,16 +358,13 @@ pub fn main() -> Result<(), anyhow::Error> {
         .filter(|p| p.pgn() == 0xFEEC)
         // log the VINs
         .for_each(|p| {
-            println!(
-                "SA: {:02X} VIN: {}",
-                p.source(),
-                String::from_utf8(p.data().into()).unwrap()
-            )
+            eprintln!("SA: {:02X} VIN: {}", p.source(), String::from_utf8_lossy(p.data()));
+            println!("{}", parse.format.format(&p));
         });
     }
     // log everything for the next 30 days
     rp1210
         .iter_for(Duration::from_secs(60 * 60 * 24 * 30))
-        .for_each(|p| println!("{}", p));
+        .for_each(|p| println!("{}", parse.format.format(&p)));
     Ok(())
 }
@@ -0,0 +1,108 @@
+use serde::Serialize;
+
+use crate::packet::{Direction, J1939Packet};
+use crate::pgn_defs::{self, Broadcast};
+
+/// How the command line prints each packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// time, channel, header, length and data, as J1939Packet displays
+    #[default]
+    Default,
+    /// time, then the message as the adapter delivered it
+    Hex,
+    /// default, followed by the fields of common broadcast PGNs
+    Decoded,
+    /// candump -L log lines, for can-utils
+    Candump,
+    /// one JSON object per line
+    Json,
+}
+
+/// A packet's line in `OutputFormat::Json`.
+#[derive(Serialize)]
+struct Line<'a> {
+    time: f64,
+    channel: u8,
+    direction: Direction,
+    id: String,
+    pgn: u32,
+    priority: u8,
+    source: u8,
+    dest: u8,
+    data: String,
+    /// fields of a common broadcast PGN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded: Option<&'a Broadcast>,
+}
+
+impl OutputFormat {
+    /// `packet` as one line, without the newline.
+    pub fn format(&self, packet: &J1939Packet) -> String {
+        match self {
+            OutputFormat::Default => packet.to_string(),
+            OutputFormat::Hex => format!("{:12.4} {}", packet.time(), hex(packet.raw_bytes())),
+            OutputFormat::Decoded => match pgn_defs::decode(packet) {
+                Some(decoded) => format!("{} {}", packet, decoded),
+                None => packet.to_string(),
+            },
+            OutputFormat::Candump => format!(
+                "({:.6}) can{} {:08X}#{}",
+                packet.time(),
+                packet.channel(),
+                packet.id(),
+                hex(packet.data())
+            ),
+            OutputFormat::Json => {
+                let decoded = pgn_defs::decode(packet);
+                let line = Line {
+                    time: packet.time(),
+                    channel: packet.channel(),
+                    direction: packet.direction(),
+                    id: format!("{:08X}", packet.id()),
+                    pgn: packet.pgn(),
+                    priority: packet.priority(),
+                    source: packet.source(),
+                    dest: packet.dest(),
+                    data: hex(packet.data()),
+                    decoded: decoded.as_ref(),
+                };
+                // plain data, so serializing can't fail
+                serde_json::to_string(&line).unwrap_or_default()
+            }
+        }
+    }
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let packet = J1939Packet::new(Some(1500), 0, 0x18FEEE00, &[130, 0xFF]).as_received();
+        assert_eq!(packet.to_string(), OutputFormat::Default.format(&packet));
+        assert_eq!(
+            format!("{} ET1 coolant=90.0", packet),
+            OutputFormat::Decoded.format(&packet)
+        );
+        assert_eq!(
+            "      0.0015 000005DC00EEFE0006000082FF",
+            OutputFormat::Hex.format(&packet)
+        );
+        assert_eq!(
+            "(0.001500) can0 18FEEE00#82FF",
+            OutputFormat::Candump.format(&packet)
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&OutputFormat::Json.format(&packet)).unwrap();
+        assert_eq!("18FEEE00", json["id"]);
+        assert_eq!(0xFEEE, json["pgn"]);
+        assert_eq!("rx", json["direction"]);
+        assert_eq!(90.0, json["decoded"]["Et1"]["coolant"]);
+    }
+}
@@ -1,16 +1,43 @@
-use std::{fmt::*, ops::Deref};
+use std::{fmt::*, ops::Deref, sync::Arc};
 
+use serde::{Deserialize, Serialize};
+
+/// A message in the RP1210 format.  The bytes are shared, so clones (one per bus consumer) don't copy them.
 #[derive(Default, Debug, Clone)]
 pub struct Packet {
-    pub data: Vec<u8>,
+    pub data: Arc<[u8]>,
+}
+
+/// Whether a packet was received from another node, sent by us, or is the adapter's echo of one we sent.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Rx,
+    Tx,
+    Echo,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        f.write_str(match self {
+            Direction::Rx => "RX",
+            Direction::Tx => "TX",
+            Direction::Echo => "echo",
+        })
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct J1939Packet {
     pub packet: Packet,
-    pub tx: bool,
+    /// RP1210 transmit format: no time stamp or echo byte.  How the packet was sent or received is direction.
+    tx: bool,
+    direction: Direction,
     channel: u8,
     time_stamp_weight: f64,
+    /// added to the adapter's time stamp, in seconds
+    time_offset: f64,
 }
 
 impl Deref for J1939Packet {
@@ -29,14 +56,92 @@ impl Display for J1939Packet {
     fn fmt(&self, f: &mut Formatter) -> Result {
         write!(
             f,
-            "{:12.4} {} {} [{}] {}{}",
+            "{:12.4} {} {} [{}] {}",
             self.time(),
             self.channel(),
             self.header(),
             self.len(),
             self.data_str(),
-            if self.echo() { " (TX)" } else { "" }
-        )
+        )?;
+        if self.direction != Direction::Rx {
+            write!(f, " ({})", self.direction)?;
+        }
+        Ok(())
+    }
+}
+
+/// J1939-21 PGN of a 29-bit CAN identifier: data page and PDU format, plus the group extension of PDU2 (PF 240 and
+/// up).  The PDU specific byte of PDU1 is the destination, so isn't part of the PGN.
+pub fn pgn_from_id(id: u32) -> u32 {
+    let pgn = (id >> 8) & 0x3FFFF;
+    if (pgn >> 8) & 0xFF < 0xF0 {
+        pgn & 0x3FF00
+    } else {
+        pgn
+    }
+}
+
+/// Source address of a 29-bit CAN identifier.
+pub fn source_from_id(id: u32) -> u8 {
+    id as u8
+}
+
+/// A J1939 message in the RP1210 format: the receive format of RP1210_ReadMessage, or the transmit format of
+/// RP1210_SendMessage, which has no time stamp or echo byte.
+///
+/// | receive | transmit | size | field |
+/// |---------|----------|------|-------|
+/// | 0       |          | 4    | time stamp, big endian, in the adapter's time stamp units |
+/// | 4       |          | 1    | echo: non-zero for the adapter's echo of a message we sent |
+/// | 5       | 0        | 3    | PGN, LSB first.  The destination is the low byte of PDU1 PGNs |
+/// | 8       | 3        | 1    | how/priority: bit 7 sends long messages with BAM, bits 0 to 2 are the priority |
+/// | 9       | 4        | 1    | source address |
+/// | 10      | 5        | 1    | destination address |
+/// | 11      | 6        |      | data |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rp1210Message<'a> {
+    /// None in the transmit format
+    pub time_stamp: Option<u32>,
+    /// the adapter's echo of a message we sent.  Always false in the transmit format.
+    pub echo: bool,
+    /// bit 7 of how/priority: messages over 8 bytes are sent with BAM rather than RTS/CTS
+    pub bam: bool,
+    /// 29-bit CAN identifier
+    pub id: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> Rp1210Message<'a> {
+    /// Parse `buf`, in the transmit format if `tx`.  None if it's shorter than the header.
+    pub fn parse(buf: &'a [u8], tx: bool) -> Option<Rp1210Message<'a>> {
+        let (time_stamp, echo, head) = if tx {
+            (None, false, buf)
+        } else {
+            let time_stamp = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?);
+            (Some(time_stamp), *buf.get(4)? != 0, buf.get(5..)?)
+        };
+        let &[ps, pf, dp, how, source, dest, ref data @ ..] = head else {
+            return None;
+        };
+        let ps = if pf < 0xF0 { dest } else { ps };
+        Some(Rp1210Message {
+            time_stamp,
+            echo,
+            bam: how & 0x80 != 0,
+            id: u32::from_be_bytes([((how & 0x07) << 2) | (dp & 0x03), pf, ps, source]),
+            data,
+        })
+    }
+}
+
+/// Packets in the RP1210 transmit format are ours to send.  Received packets have an echo byte after the time stamp.
+fn direction(tx: bool, data: &[u8]) -> Direction {
+    if tx {
+        Direction::Tx
+    } else if Rp1210Message::parse(data, tx).is_some_and(|m| m.echo) {
+        Direction::Echo
+    } else {
+        Direction::Rx
     }
 }
 
@@ -63,20 +168,21 @@ impl Display for Packet {
 impl Packet {
     #[allow(dead_code)]
     pub fn new_rp1210(data: &[u8]) -> Packet {
-        Packet {
-            data: data.to_vec(),
-        }
+        Packet { data: data.into() }
     }
 }
 
 impl J1939Packet {
+    /// Packet of an RP1210 message, in the transmit format if `tx`.  See `Rp1210Message` for the layout.
     #[allow(dead_code)]
     pub fn new_rp1210(tx: bool, channel: u8, data: &[u8], time_stamp_weight: f64) -> J1939Packet {
         J1939Packet {
             packet: Packet::new_rp1210(data),
             tx,
+            direction: direction(tx, data),
             channel,
             time_stamp_weight,
+            time_offset: 0.0,
         }
     }
 
@@ -84,6 +190,10 @@ impl J1939Packet {
         self.data.len() - 6 - self.offset()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     #[allow(dead_code)]
     pub fn new_packet(
         time: Option<u32>,
@@ -94,13 +204,18 @@ impl J1939Packet {
         sa: u8,
         data: &[u8],
     ) -> J1939Packet {
-        let da = if pgn >= 0xF000 { 0 } else { da };
+        // PDU1 PGNs carry the destination in place of a group extension
+        let ps = if (pgn >> 8) & 0xFF < 0xF0 {
+            da
+        } else {
+            pgn as u8
+        };
         Self::new(
             time,
             channel,
-            ((priority as u32) << 24)
-                | (pgn << 8)
-                | if pgn >= 0xf000 { 0 } else { (da as u32) << 8 }
+            ((priority as u32 & 0x07) << 26)
+                | ((pgn & 0x3FF00) << 8)
+                | (ps as u32) << 8
                 | (sa as u32),
             data,
         )
@@ -112,23 +227,99 @@ impl J1939Packet {
         let pgn = 0xFFFF & (head >> 8);
         let da = if pgn < 0xF000 { 0xFF & pgn } else { 0 } as u8;
         let hb = head.to_be_bytes();
-        let mut buf = [&[hb[2], hb[1], hb[0] & 0x3, hb[0] >> 2, hb[3], da], data].concat();
-        if time.is_some() {
-            buf = [&time.unwrap().to_be_bytes()[..], &[0xFF], &buf].concat();
+        let mut buf = [
+            &[hb[2], hb[1], hb[0] & 0x3, (hb[0] >> 2) & 0x07, hb[3], da],
+            data,
+        ]
+        .concat();
+        if let Some(time) = time {
+            buf = [&time.to_be_bytes()[..], &[0xFF], &buf].concat();
         }
         J1939Packet {
+            direction: direction(time.is_none(), &buf),
             packet: Packet::new_rp1210(&buf),
             tx: time.is_none(),
             channel,
             time_stamp_weight: 1.0,
+            time_offset: 0.0,
         }
     }
 
+    /// Same time, channel, priority and addresses as self, with a new PGN and payload.  Used for TP reassembly.
+    pub(crate) fn reassembled(&self, pgn: u32, data: &[u8]) -> J1939Packet {
+        let o = self.offset();
+        let pgn = pgn.to_le_bytes();
+        let head = [pgn[0], pgn[1], pgn[2], self.data[o + 3]];
+        let buf = [&self.data[..o], &head, &[self.source(), self.dest()], data].concat();
+        J1939Packet {
+            packet: Packet::new_rp1210(&buf),
+            tx: self.tx,
+            direction: self.direction,
+            channel: self.channel,
+            time_stamp_weight: self.time_stamp_weight,
+            time_offset: self.time_offset,
+        }
+    }
+
+    /// Copy of this packet with a new source address.
+    pub(crate) fn with_source(&self, source: u8) -> J1939Packet {
+        let mut packet = self.clone();
+        let o = packet.offset();
+        Arc::make_mut(&mut packet.packet.data)[4 + o] = source;
+        packet
+    }
+
+    /// Copy of this packet sent at `priority`, 0 (highest) to 7.  Only the low 3 bits are used.
+    pub fn with_priority(&self, priority: u8) -> J1939Packet {
+        let mut packet = self.clone();
+        let o = packet.offset();
+        let how = &mut Arc::make_mut(&mut packet.packet.data)[3 + o];
+        *how = (*how & !0x07) | (priority & 0x07);
+        packet
+    }
+
+    /// Copy of this packet with `direction`, e.g. when reading a recorded session.
+    pub(crate) fn with_direction(&self, direction: Direction) -> J1939Packet {
+        J1939Packet {
+            direction,
+            ..self.clone()
+        }
+    }
+
+    /// Copy of this packet marked as sent, for connections that inject sent packets into their own bus.
+    pub(crate) fn as_sent(&self) -> J1939Packet {
+        J1939Packet {
+            direction: Direction::Tx,
+            ..self.clone()
+        }
+    }
+
+    /// Copy of this packet as received from another node, e.g. for simulated traffic.  Clears the echo byte.
+    pub(crate) fn as_received(&self) -> J1939Packet {
+        let mut packet = J1939Packet {
+            direction: Direction::Rx,
+            ..self.clone()
+        };
+        if !packet.tx {
+            Arc::make_mut(&mut packet.packet.data)[4] = 0;
+        }
+        packet
+    }
+
+    /// True for packets we sent, including those injected into the bus locally.  Adapter echoes are not sent().
+    pub fn sent(&self) -> bool {
+        self.direction == Direction::Tx
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
     pub fn to_rp1210_rx(&self) -> Vec<u8> {
         if self.tx {
             [&[0, 0, 0, 0, 0][..], &*self.data].concat()
         } else {
-            self.data.clone()
+            self.data.to_vec()
         }
     }
     pub fn to_rp1210_tx(&self) -> Vec<u8> {
@@ -136,16 +327,43 @@ impl J1939Packet {
     }
 
     pub fn time(&self) -> f64 {
+        self.adapter_time() + self.time_offset
+    }
+
+    /// Copy of this packet with time() replaced, e.g. by the host's clock.
+    pub(crate) fn with_time(&self, time: f64) -> J1939Packet {
+        J1939Packet {
+            time_offset: time - self.adapter_time(),
+            ..self.clone()
+        }
+    }
+
+    fn adapter_time(&self) -> f64 {
+        self.time_stamp()
+            .map_or(0.0, |t| self.time_stamp_to_seconds(t as f64))
+    }
+
+    /// The adapter's raw time stamp.  None in the transmit format.
+    pub(crate) fn time_stamp(&self) -> Option<u32> {
         if self.tx {
-            0.0
+            None
         } else {
-            let bytes = &self.data[0..4];
-            u32::from_be_bytes(bytes.try_into().unwrap()) as f64
-                * 0.000001 // convert to s
-                * self.time_stamp_weight
+            Some(u32::from_be_bytes(self.data[0..4].try_into().unwrap()))
         }
     }
 
+    /// Microseconds per `time_stamp()` tick, from the INI's TimeStampWeight.
+    pub(crate) fn time_stamp_weight(&self) -> f64 {
+        self.time_stamp_weight
+    }
+
+    /// Seconds for `ticks` of the adapter's time stamp counter.
+    pub(crate) fn time_stamp_to_seconds(&self, ticks: f64) -> f64 {
+        ticks
+            * 0.000001 // convert to s
+            * self.time_stamp_weight
+    }
+
     /// offset into array for data common to tx and rx RP1210 formats
     fn offset(&self) -> usize {
         if self.tx {
@@ -155,22 +373,35 @@ impl J1939Packet {
         }
     }
 
+    /// The adapter's echo of a packet we sent.
     pub fn echo(&self) -> bool {
-        self.tx || self.data[4] != 0
+        self.direction == Direction::Echo
+    }
+
+    /// The adapter's echo of `sent`, rather than another node's packet with the same data.  Only the echo byte of
+    /// the received message says which it is.
+    pub fn echo_of(&self, sent: &J1939Packet) -> bool {
+        self.echo() && self.id() == sent.id() && self.data() == sent.data()
+    }
+
+    /// True if data is in the RP1210 transmit format rather than the receive format.
+    pub fn tx_format(&self) -> bool {
+        self.tx
     }
 
     pub fn source(&self) -> u8 {
         self.data[4 + self.offset()]
     }
 
+    /// PGN, including the destination address of PDU1 packets, e.g. EAFF for a global request.  See `pgn_from_id()`
+    /// for the J1939-21 PGN.
     pub fn pgn(&self) -> u32 {
-        let mut pgn = ((self.data[2 + self.offset()] as u32 & 0xFF) << 16)
-            | ((self.data[1 + self.offset()] as u32 & 0xFF) << 8)
-            | (self.data[self.offset()] as u32 & 0xFF);
-        if pgn < 0xF000 {
-            pgn |= self.dest() as u32;
+        let pgn = pgn_from_id(self.id());
+        if (pgn >> 8) & 0xFF < 0xF0 {
+            pgn | self.dest() as u32
+        } else {
+            pgn
         }
-        pgn
     }
 
     pub fn dest(&self) -> u8 {
@@ -189,21 +420,26 @@ impl J1939Packet {
         )
     }
 
+    /// 29-bit CAN identifier: priority, data page, PDU format, PDU specific (destination or group extension) and source.
     pub fn id(&self) -> u32 {
         let d = &self.data;
         let o = self.offset();
-        let id = u32::from_le_bytes([d[o + 4], d[o], d[o + 1], d[o + 2]]);
-        if id > 0xF000 {
-            id | ((d[o + 4] as u32) << 8)
-        } else {
-            id
-        }
+        let pf = d[o + 1];
+        let ps = if pf < 0xF0 { self.dest() } else { d[o] };
+        let dp = d[o + 2] & 0x3;
+        u32::from_be_bytes([(self.priority() << 2) | dp, pf, ps, self.source()])
+    }
+
+    /// J1939 always uses extended (29-bit) identifiers.
+    pub fn is_extended(&self) -> bool {
+        true
     }
 
     pub fn data_str(&self) -> String {
-        as_hex(&self.data())
+        as_hex(self.data())
     }
 
+    /// J1939 payload, without the RP1210 header.  See `raw_bytes()` for the whole message.
     pub fn data(&self) -> &[u8] {
         &self.data[self.offset() + 6..]
     }
@@ -211,6 +447,20 @@ impl J1939Packet {
     pub fn channel(&self) -> u8 {
         self.channel
     }
+
+    /// The RP1210 message as the adapter delivered it, or as it will be sent: the header, then `data()`.
+    ///
+    /// Received messages start with the time stamp (4 bytes, big endian) and echo byte; both formats then have the
+    /// PGN (3 bytes, LSB first), priority, source and destination.  Reassembled transport protocol messages have the
+    /// header of their last data transfer frame, with the PGN of the whole message.
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.packet.data
+    }
+
+    /// The fields of `raw_bytes()`.  None if the adapter delivered less than a header.
+    pub fn message(&self) -> Option<Rp1210Message<'_>> {
+        Rp1210Message::parse(&self.packet.data, self.tx)
+    }
 }
 
 #[cfg(test)]
@@ -218,6 +468,84 @@ mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
+    #[test]
+    fn from_id() {
+        assert_eq!(0xEA00, pgn_from_id(0x18EAFFF9));
+        assert_eq!(0xFEF1, pgn_from_id(0x18FEF100));
+        assert_eq!(0x1EA00, pgn_from_id(0x19EA00F9));
+        assert_eq!(0xF9, source_from_id(0x18EAFFF9));
+        let request = J1939Packet::new(None, 0, 0x18EA00F9, &[0xEC, 0xFE, 0x00]);
+        assert_eq!((0xEA00, 0xF9), (request.pgn(), request.source()));
+        let global = J1939Packet::new(None, 0, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]);
+        assert_eq!(0xEAFF, global.pgn());
+    }
+
+    #[test]
+    fn priority() {
+        let packet = J1939Packet::new_packet(None, 0, 3, 0xEF00, 0x00, 0xF9, &[1, 2, 3]);
+        assert_eq!(
+            (3, 0x0CEF00F9, 0xEF00),
+            (packet.priority(), packet.id(), packet.pgn())
+        );
+        let packet = J1939Packet::new_packet(Some(1), 0, 6, 0x1FEF1, 0x00, 0x17, &[1]);
+        assert_eq!(
+            (6, 0x19FEF117, 0x1FEF1),
+            (packet.priority(), packet.id(), packet.pgn())
+        );
+
+        let packet = J1939Packet::new(None, 0, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]).with_priority(2);
+        assert_eq!((2, 0x08EAFFF9), (packet.priority(), packet.id()));
+        let received = J1939Packet::new(Some(1), 0, 0x18FEF117, &[1]).with_priority(7);
+        assert_eq!(
+            (7, 0x1CFEF117, 0xFEF1),
+            (received.priority(), received.id(), received.pgn())
+        );
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let sent = J1939Packet::new(None, 0, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]);
+        assert_eq!(
+            &[0xFF, 0xEA, 0x00, 0x06, 0xF9, 0xFF, 0xEC, 0xFE, 0x00],
+            sent.raw_bytes()
+        );
+        let received = J1939Packet::new(Some(0x01020304), 0, 0x18FEF117, &[1]).as_received();
+        assert_eq!(
+            &[1, 2, 3, 4, 0, 0xF1, 0xFE, 0x00, 0x06, 0x17, 0x00, 1],
+            received.raw_bytes()
+        );
+        assert_eq!(&[1], received.data());
+    }
+
+    #[test]
+    fn message() {
+        let echo = J1939Packet::new_rp1210(
+            false,
+            1,
+            &[0, 0, 0, 1, 1, 0xF1, 0xFE, 0, 0x86, 0xF9, 0, 1],
+            1.0,
+        );
+        let expected = Rp1210Message {
+            time_stamp: Some(1),
+            echo: true,
+            bam: true,
+            id: 0x18FEF1F9,
+            data: &[1],
+        };
+        assert_eq!(Some(expected), echo.message());
+        let request = J1939Packet::new(None, 0, 0x18EA00F9, &[0xEC, 0xFE, 0x00]);
+        let message = request.message().unwrap();
+        assert_eq!(
+            (None, false, false),
+            (message.time_stamp, message.echo, message.bam)
+        );
+        assert_eq!((request.id(), request.data()), (message.id, message.data));
+        assert_eq!(
+            None,
+            Rp1210Message::parse(&[0, 0, 0, 1, 0, 0xF1, 0xFE, 0, 6, 0], false)
+        );
+    }
+
     #[test]
     fn test_j1939packet_display() {
         assert_eq!(
@@ -225,7 +553,7 @@ mod tests {
             J1939Packet::new(None, 1, 0x18FFAAFA, &[1, 2, 3]).to_string()
         );
         assert_eq!(
-            "      0.0000 1 18FFAAFA [3] 01 02 03 (TX)",
+            "      0.0006 1 18FFAAFA [3] 01 02 03 (echo)",
             J1939Packet::new(Some(555), 1, 0x18FFAAFA, &[1, 2, 3]).to_string()
         );
         assert_eq!(
@@ -243,4 +571,40 @@ mod tests {
             .to_string()
         );
     }
+
+    #[test]
+    fn test_direction() {
+        let rx =
+            J1939Packet::new_rp1210(false, 1, &[0, 0, 0, 1, 0, 0xF1, 0xFE, 0, 6, 0, 0, 1], 1.0);
+        assert_eq!(Direction::Rx, rx.direction());
+        assert_eq!("      0.0000 1 18FEF100 [1] 01", rx.to_string());
+        let echo = J1939Packet::new_rp1210(
+            false,
+            1,
+            &[0, 0, 0, 1, 1, 0xF1, 0xFE, 0, 6, 0xF9, 0, 1],
+            1.0,
+        );
+        assert_eq!(Direction::Echo, echo.direction());
+        assert!(echo.echo() && !echo.sent());
+        assert!(rx.as_sent().sent());
+        let tx = J1939Packet::new(None, 1, 0x18FEF1F9, &[1]);
+        assert_eq!(Direction::Tx, tx.direction());
+        assert!(tx.tx_format() && !tx.echo());
+        assert!(!rx.echo());
+        let received = echo.as_received();
+        assert_eq!(Direction::Rx, received.direction());
+        let sent = J1939Packet::new(None, 1, 0x18FEF1F9, &[1]);
+        assert!(echo.echo_of(&sent) && !received.echo_of(&sent));
+        assert!(!echo.echo_of(&J1939Packet::new(None, 1, 0x18FEF1FA, &[1])));
+        assert_eq!(rx.data(), received.data());
+        assert_eq!("\"echo\"", serde_json::to_string(&Direction::Echo).unwrap());
+    }
+
+    #[test]
+    fn test_id() {
+        for id in [0x18FEF100, 0x18EA00F9, 0x0CEAFFFE, 0x1DEF0102, 0x18FEEC00] {
+            assert_eq!(id, J1939Packet::new(None, 1, id, &[1, 2, 3]).id());
+            assert_eq!(id, J1939Packet::new(Some(1234), 1, id, &[1, 2, 3]).id());
+        }
+    }
 }
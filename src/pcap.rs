@@ -0,0 +1,76 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::packet::J1939Packet;
+
+/// LINKTYPE_CAN_SOCKETCAN: each record is a Linux `can_frame`, dissected by Wireshark's SocketCAN and J1939 dissectors.
+const LINKTYPE_CAN_SOCKETCAN: u32 = 227;
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+/// can_frame: ID and flags (u32, big endian in captures), length, 3 reserved bytes, 8 data bytes.
+const FRAME_SIZE: u32 = 16;
+
+/// Write `packets` as a PCAP capture that opens in Wireshark and SavvyCAN.  Each packet is a SocketCAN frame with
+/// the 29-bit ID flag set, time stamped with time().  Packets longer than a CAN frame, e.g. reassembled TP
+/// messages, are skipped.  Returns the number of packets written.
+pub fn write_pcap(
+    writer: &mut dyn Write,
+    packets: impl IntoIterator<Item = J1939Packet>,
+) -> Result<usize> {
+    // magic (microsecond time stamps), version 2.4, UTC, accuracy, snapshot length, link type
+    writer.write_all(&0xA1B2_C3D4u32.to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&4u16.to_le_bytes())?;
+    writer.write_all(&0i32.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&FRAME_SIZE.to_le_bytes())?;
+    writer.write_all(&LINKTYPE_CAN_SOCKETCAN.to_le_bytes())?;
+
+    let mut written = 0;
+    for packet in packets {
+        let data = packet.data();
+        if data.len() > 8 {
+            continue;
+        }
+        let micros = (packet.time().max(0.0) * 1_000_000.0).round() as u64;
+        writer.write_all(&((micros / 1_000_000) as u32).to_le_bytes())?;
+        writer.write_all(&((micros % 1_000_000) as u32).to_le_bytes())?;
+        writer.write_all(&FRAME_SIZE.to_le_bytes())?;
+        writer.write_all(&FRAME_SIZE.to_le_bytes())?;
+
+        writer.write_all(&(packet.id() | CAN_EFF_FLAG).to_be_bytes())?;
+        writer.write_all(&[data.len() as u8, 0, 0, 0])?;
+        let mut payload = [0u8; 8];
+        payload[..data.len()].copy_from_slice(data);
+        writer.write_all(&payload)?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture() -> Result<()> {
+        let mut out = Vec::new();
+        let packets = [
+            J1939Packet::new(Some(1_500_000), 0, 0x18FEF100, &[1, 2, 3]),
+            J1939Packet::new(Some(0), 0, 0x18FEF100, &[0; 9]),
+        ];
+        assert_eq!(1, write_pcap(&mut out, packets)?);
+        assert_eq!(24 + 16 + 16, out.len());
+        assert_eq!([0xD4, 0xC3, 0xB2, 0xA1], out[0..4]);
+        assert_eq!(227, u32::from_le_bytes(out[20..24].try_into()?));
+
+        let record = &out[24..];
+        assert_eq!(1, u32::from_le_bytes(record[0..4].try_into()?));
+        assert_eq!(500_000, u32::from_le_bytes(record[4..8].try_into()?));
+        assert_eq!([0x98, 0xFE, 0xF1, 0x00], record[16..20]);
+        assert_eq!(3, record[20]);
+        assert_eq!([1, 2, 3, 0, 0, 0, 0, 0], record[24..32]);
+        Ok(())
+    }
+}
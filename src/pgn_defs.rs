@@ -0,0 +1,433 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::packet::J1939Packet;
+
+/// Electronic Engine Controller 1
+pub const EEC1: u32 = 0xF004;
+/// Electronic Engine Controller 2
+pub const EEC2: u32 = 0xF003;
+/// Engine Hours, Revolutions
+pub const HOURS: u32 = 0xFEE5;
+/// Vehicle Distance
+pub const VD: u32 = 0xFEE0;
+/// Engine Temperature 1
+pub const ET1: u32 = 0xFEEE;
+/// Engine Fluid Level/Pressure 1
+pub const EFL_P1: u32 = 0xFEEF;
+/// Cruise Control/Vehicle Speed 1
+pub const CCVS1: u32 = 0xFEF1;
+/// Fuel Economy (Liquid)
+pub const LFE1: u32 = 0xFEF2;
+/// Ambient Conditions
+pub const AMB: u32 = 0xFEF5;
+/// Vehicle Electrical Power 1
+pub const VEP1: u32 = 0xFEF7;
+
+/// A common J1939-71 broadcast in engineering units, from `decode()`, for dashboards that don't need a DBC.
+///
+/// Fields are None when the sender reports them as not available or in error, or the packet is too short.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Broadcast {
+    Eec1(Eec1),
+    Eec2(Eec2),
+    EngineHours(EngineHours),
+    VehicleDistance(VehicleDistance),
+    Et1(Et1),
+    EflP1(EflP1),
+    Ccvs1(Ccvs1),
+    Lfe1(Lfe1),
+    Ambient(Ambient),
+    Vep1(Vep1),
+}
+
+impl Broadcast {
+    /// J1939-71 acronym, e.g. "ET1".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Broadcast::Eec1(_) => "EEC1",
+            Broadcast::Eec2(_) => "EEC2",
+            Broadcast::EngineHours(_) => "HOURS",
+            Broadcast::VehicleDistance(_) => "VD",
+            Broadcast::Et1(_) => "ET1",
+            Broadcast::EflP1(_) => "EFL/P1",
+            Broadcast::Ccvs1(_) => "CCVS1",
+            Broadcast::Lfe1(_) => "LFE1",
+            Broadcast::Ambient(_) => "AMB",
+            Broadcast::Vep1(_) => "VEP1",
+        }
+    }
+}
+
+/// The acronym, then the available fields, e.g. `ET1 coolant=90 oil=100`.
+impl Display for Broadcast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())?;
+        // serialized as {"Variant": {fields}}
+        let value = serde_json::to_value(self).map_err(|_| std::fmt::Error)?;
+        let fields = value
+            .as_object()
+            .and_then(|v| v.values().next()?.as_object());
+        for (name, value) in fields.into_iter().flatten() {
+            if !value.is_null() {
+                write!(f, " {}={}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode `packet` if its PGN is one of this module's.
+pub fn decode(packet: &J1939Packet) -> Option<Broadcast> {
+    let data = packet.data();
+    Some(match packet.pgn() {
+        EEC1 => Broadcast::Eec1(Eec1::parse(data)),
+        EEC2 => Broadcast::Eec2(Eec2::parse(data)),
+        HOURS => Broadcast::EngineHours(EngineHours::parse(data)),
+        VD => Broadcast::VehicleDistance(VehicleDistance::parse(data)),
+        ET1 => Broadcast::Et1(Et1::parse(data)),
+        EFL_P1 => Broadcast::EflP1(EflP1::parse(data)),
+        CCVS1 => Broadcast::Ccvs1(Ccvs1::parse(data)),
+        LFE1 => Broadcast::Lfe1(Lfe1::parse(data)),
+        AMB => Broadcast::Ambient(Ambient::parse(data)),
+        VEP1 => Broadcast::Vep1(Vep1::parse(data)),
+        _ => return None,
+    })
+}
+
+/// Electronic Engine Controller 1 (PGN 61444)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Eec1 {
+    /// SPN 512, %
+    pub drivers_demand_torque: Option<f64>,
+    /// SPN 513, %
+    pub actual_torque: Option<f64>,
+    /// SPN 190, rpm
+    pub engine_speed: Option<f64>,
+    /// SPN 1483, address of the device controlling engine speed or torque
+    pub controlling_address: Option<u8>,
+    /// SPN 2432, %
+    pub demand_torque: Option<f64>,
+}
+
+impl Eec1 {
+    pub fn parse(data: &[u8]) -> Eec1 {
+        Eec1 {
+            drivers_demand_torque: scaled8(data, 1, 1.0, -125.0),
+            actual_torque: scaled8(data, 2, 1.0, -125.0),
+            engine_speed: scaled16(data, 3, 0.125, 0.0),
+            controlling_address: data.get(5).copied(),
+            demand_torque: scaled8(data, 7, 1.0, -125.0),
+        }
+    }
+}
+
+/// Electronic Engine Controller 2 (PGN 61443)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Eec2 {
+    /// SPN 91, %
+    pub accelerator_pedal: Option<f64>,
+    /// SPN 92, % of the maximum torque at the current speed
+    pub load: Option<f64>,
+}
+
+impl Eec2 {
+    pub fn parse(data: &[u8]) -> Eec2 {
+        Eec2 {
+            accelerator_pedal: scaled8(data, 1, 0.4, 0.0),
+            load: scaled8(data, 2, 1.0, 0.0),
+        }
+    }
+}
+
+/// Engine Hours, Revolutions (PGN 65253)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct EngineHours {
+    /// SPN 247, h
+    pub hours: Option<f64>,
+    /// SPN 249, revolutions
+    pub revolutions: Option<f64>,
+}
+
+impl EngineHours {
+    pub fn parse(data: &[u8]) -> EngineHours {
+        EngineHours {
+            hours: scaled32(data, 0, 0.05, 0.0),
+            revolutions: scaled32(data, 4, 1000.0, 0.0),
+        }
+    }
+}
+
+/// Vehicle Distance (PGN 65248)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct VehicleDistance {
+    /// SPN 244, km
+    pub trip: Option<f64>,
+    /// SPN 245, km
+    pub total: Option<f64>,
+}
+
+impl VehicleDistance {
+    pub fn parse(data: &[u8]) -> VehicleDistance {
+        VehicleDistance {
+            trip: scaled32(data, 0, 0.125, 0.0),
+            total: scaled32(data, 4, 0.125, 0.0),
+        }
+    }
+}
+
+/// Engine Temperature 1 (PGN 65262)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Et1 {
+    /// SPN 110, °C
+    pub coolant: Option<f64>,
+    /// SPN 174, °C
+    pub fuel: Option<f64>,
+    /// SPN 175, °C
+    pub oil: Option<f64>,
+    /// SPN 176, °C
+    pub turbo_oil: Option<f64>,
+    /// SPN 52, °C
+    pub intercooler: Option<f64>,
+    /// SPN 1134, %
+    pub intercooler_thermostat: Option<f64>,
+}
+
+impl Et1 {
+    pub fn parse(data: &[u8]) -> Et1 {
+        Et1 {
+            coolant: scaled8(data, 0, 1.0, -40.0),
+            fuel: scaled8(data, 1, 1.0, -40.0),
+            oil: scaled16(data, 2, 0.03125, -273.0),
+            turbo_oil: scaled16(data, 4, 0.03125, -273.0),
+            intercooler: scaled8(data, 6, 1.0, -40.0),
+            intercooler_thermostat: scaled8(data, 7, 0.4, 0.0),
+        }
+    }
+}
+
+/// Engine Fluid Level/Pressure 1 (PGN 65263)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct EflP1 {
+    /// SPN 94, kPa
+    pub fuel_delivery_pressure: Option<f64>,
+    /// SPN 98, %
+    pub oil_level: Option<f64>,
+    /// SPN 100, kPa
+    pub oil_pressure: Option<f64>,
+    /// SPN 101, kPa
+    pub crankcase_pressure: Option<f64>,
+    /// SPN 109, kPa
+    pub coolant_pressure: Option<f64>,
+    /// SPN 111, %
+    pub coolant_level: Option<f64>,
+}
+
+impl EflP1 {
+    pub fn parse(data: &[u8]) -> EflP1 {
+        EflP1 {
+            fuel_delivery_pressure: scaled8(data, 0, 4.0, 0.0),
+            oil_level: scaled8(data, 2, 0.4, 0.0),
+            oil_pressure: scaled8(data, 3, 4.0, 0.0),
+            crankcase_pressure: scaled16(data, 4, 1.0 / 128.0, -250.0),
+            coolant_pressure: scaled8(data, 6, 2.0, 0.0),
+            coolant_level: scaled8(data, 7, 0.4, 0.0),
+        }
+    }
+}
+
+/// Cruise Control/Vehicle Speed 1 (PGN 65265)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Ccvs1 {
+    /// SPN 70
+    pub parking_brake: Option<bool>,
+    /// SPN 84, km/h
+    pub wheel_speed: Option<f64>,
+    /// SPN 595
+    pub cruise_active: Option<bool>,
+    /// SPN 597
+    pub brake_switch: Option<bool>,
+    /// SPN 598
+    pub clutch_switch: Option<bool>,
+    /// SPN 86, km/h
+    pub cruise_set_speed: Option<f64>,
+}
+
+impl Ccvs1 {
+    pub fn parse(data: &[u8]) -> Ccvs1 {
+        Ccvs1 {
+            parking_brake: state(data, 0, 2),
+            wheel_speed: scaled16(data, 1, 1.0 / 256.0, 0.0),
+            cruise_active: state(data, 3, 0),
+            brake_switch: state(data, 3, 4),
+            clutch_switch: state(data, 3, 6),
+            cruise_set_speed: scaled8(data, 5, 1.0, 0.0),
+        }
+    }
+}
+
+/// Fuel Economy (Liquid) (PGN 65266)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Lfe1 {
+    /// SPN 183, L/h
+    pub fuel_rate: Option<f64>,
+    /// SPN 184, km/L
+    pub instantaneous_economy: Option<f64>,
+    /// SPN 185, km/L
+    pub average_economy: Option<f64>,
+    /// SPN 51, %
+    pub throttle: Option<f64>,
+}
+
+impl Lfe1 {
+    pub fn parse(data: &[u8]) -> Lfe1 {
+        Lfe1 {
+            fuel_rate: scaled16(data, 0, 0.05, 0.0),
+            instantaneous_economy: scaled16(data, 2, 1.0 / 512.0, 0.0),
+            average_economy: scaled16(data, 4, 1.0 / 512.0, 0.0),
+            throttle: scaled8(data, 6, 0.4, 0.0),
+        }
+    }
+}
+
+/// Ambient Conditions (PGN 65269)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Ambient {
+    /// SPN 108, kPa
+    pub barometric_pressure: Option<f64>,
+    /// SPN 170, °C
+    pub cab: Option<f64>,
+    /// SPN 171, °C
+    pub ambient: Option<f64>,
+    /// SPN 172, °C
+    pub air_inlet: Option<f64>,
+    /// SPN 79, °C
+    pub road_surface: Option<f64>,
+}
+
+impl Ambient {
+    pub fn parse(data: &[u8]) -> Ambient {
+        Ambient {
+            barometric_pressure: scaled8(data, 0, 0.5, 0.0),
+            cab: scaled16(data, 1, 0.03125, -273.0),
+            ambient: scaled16(data, 3, 0.03125, -273.0),
+            air_inlet: scaled8(data, 5, 1.0, -40.0),
+            road_surface: scaled16(data, 6, 0.03125, -273.0),
+        }
+    }
+}
+
+/// Vehicle Electrical Power 1 (PGN 65271)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Vep1 {
+    /// SPN 114, A
+    pub battery_current: Option<f64>,
+    /// SPN 115, A
+    pub alternator_current: Option<f64>,
+    /// SPN 167, V
+    pub charging_voltage: Option<f64>,
+    /// SPN 168, V
+    pub battery_voltage: Option<f64>,
+    /// SPN 158, V
+    pub keyswitch_voltage: Option<f64>,
+}
+
+impl Vep1 {
+    pub fn parse(data: &[u8]) -> Vep1 {
+        Vep1 {
+            battery_current: scaled8(data, 0, 1.0, -125.0),
+            alternator_current: scaled8(data, 1, 1.0, 0.0),
+            charging_voltage: scaled16(data, 2, 0.05, 0.0),
+            battery_voltage: scaled16(data, 4, 0.05, 0.0),
+            keyswitch_voltage: scaled16(data, 6, 0.05, 0.0),
+        }
+    }
+}
+
+/// 1 byte value at `index`.  J1939-71 reserves FB-FF for errors and not available.
+fn scaled8(data: &[u8], index: usize, resolution: f64, offset: f64) -> Option<f64> {
+    let raw = *data.get(index)?;
+    (raw <= 0xFA).then_some(raw as f64 * resolution + offset)
+}
+
+/// Little endian 2 byte value at `index`.  FB00-FFFF are reserved.
+fn scaled16(data: &[u8], index: usize, resolution: f64, offset: f64) -> Option<f64> {
+    let raw = u16::from_le_bytes(data.get(index..index + 2)?.try_into().ok()?);
+    (raw <= 0xFAFF).then_some(raw as f64 * resolution + offset)
+}
+
+/// Little endian 4 byte value at `index`.  FB000000-FFFFFFFF are reserved.
+fn scaled32(data: &[u8], index: usize, resolution: f64, offset: f64) -> Option<f64> {
+    let raw = u32::from_le_bytes(data.get(index..index + 4)?.try_into().ok()?);
+    (raw <= 0xFAFFFFFF).then_some(raw as f64 * resolution + offset)
+}
+
+/// 2 bit state at `shift` of the byte at `index`.  2 is an error and 3 not available.
+fn state(data: &[u8], index: usize, shift: u8) -> Option<bool> {
+    match (data.get(index)? >> shift) & 3 {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_broadcasts() {
+        // 1500 rpm, 20% actual torque, controlled by the engine
+        let eec1 = J1939Packet::new(
+            None,
+            3,
+            0x0CF00400,
+            &[0xF0, 0x7D, 0x91, 0xE0, 0x2E, 0x00, 0xFF, 0xFF],
+        );
+        assert_eq!(
+            Some(Broadcast::Eec1(Eec1 {
+                drivers_demand_torque: Some(0.0),
+                actual_torque: Some(20.0),
+                engine_speed: Some(1500.0),
+                controlling_address: Some(0),
+                demand_torque: None,
+            })),
+            decode(&eec1)
+        );
+
+        // 90 °C coolant, 100 °C oil, the rest not available
+        let et1 = J1939Packet::new(
+            None,
+            6,
+            0x18FEEE00,
+            &[130, 0xFF, 0xA0, 0x2E, 0xFF, 0xFF, 0xFF, 0xFF],
+        );
+        let Some(Broadcast::Et1(et1)) = decode(&et1) else {
+            panic!("not ET1");
+        };
+        assert_eq!(
+            (Some(90.0), None, Some(100.0)),
+            (et1.coolant, et1.fuel, et1.oil)
+        );
+
+        // 88 km/h, parking brake off, cruise active
+        let ccvs1 = Ccvs1::parse(&[0xF3, 0x00, 0x58, 0xFD, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            (Some(false), Some(88.0), Some(true), None),
+            (
+                ccvs1.parking_brake,
+                ccvs1.wheel_speed,
+                ccvs1.cruise_active,
+                ccvs1.brake_switch
+            )
+        );
+
+        assert_eq!(Some(12.5), Lfe1::parse(&[0xFA, 0x00]).fuel_rate);
+        assert_eq!(Lfe1::default(), Lfe1::parse(&[0xFA]));
+        assert_eq!(
+            None,
+            decode(&J1939Packet::new(None, 6, 0x18FECA00, &[0; 8]))
+        );
+    }
+}
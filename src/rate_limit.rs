@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::bus::{BusIterator, Filter};
+use crate::clock::Clock;
+use crate::connection::{Connection, ConnectionInfo};
+use crate::packet::J1939Packet;
+use crate::tp;
+
+/// What to do with a packet sent sooner than its PGN's limit allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverRate {
+    /// fail the send with `RateLimitExceeded`
+    Drop,
+    /// wait until the limit allows it
+    Delay,
+}
+
+/// What the limiter did with a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitOutcome {
+    Sent,
+    Delayed(Duration),
+    Dropped,
+}
+
+/// Send failed because the PGN was sent too recently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitExceeded {
+    pub pgn: u32,
+    pub interval: Duration,
+}
+
+impl Display for RateLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PGN {:04X} dropped: sent more than once per {:?}",
+            self.pgn, self.interval
+        )
+    }
+}
+
+impl std::error::Error for RateLimitExceeded {}
+
+/// Minimum interval between sends of each PGN.  PGNs without a limit are not limited.
+///
+/// PDU1 PGNs are limited without regard to destination, so requests (59904) to different ECUs share a limit.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    over_rate: OverRate,
+    limits: HashMap<u32, Duration>,
+    last: HashMap<u32, Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(over_rate: OverRate) -> Self {
+        Self {
+            over_rate,
+            limits: HashMap::new(),
+            last: HashMap::new(),
+        }
+    }
+
+    /// Send `pgn` at most once per `interval`.
+    pub fn limit(mut self, pgn: u32, interval: Duration) -> Self {
+        self.limits.insert(pgn, interval);
+        self
+    }
+
+    /// Decide what to do with a send of `pgn` at `now`.  Sent and delayed packets count as sent.
+    pub fn check(&mut self, pgn: u32, now: Instant) -> RateLimitOutcome {
+        let Some(interval) = self.limits.get(&pgn) else {
+            return RateLimitOutcome::Sent;
+        };
+        let next = self.last.get(&pgn).map(|last| *last + *interval);
+        match next {
+            Some(next) if next > now => match self.over_rate {
+                OverRate::Drop => RateLimitOutcome::Dropped,
+                OverRate::Delay => {
+                    self.last.insert(pgn, next);
+                    RateLimitOutcome::Delayed(next - now)
+                }
+            },
+            _ => {
+                self.last.insert(pgn, now);
+                RateLimitOutcome::Sent
+            }
+        }
+    }
+}
+
+/// Connection that applies a `RateLimiter` to send() and transmit(), timed by the connection's clock.
+pub struct RateLimited {
+    connection: Box<dyn Connection>,
+    limiter: RateLimiter,
+    last_outcome: Option<RateLimitOutcome>,
+}
+
+impl RateLimited {
+    pub fn new(connection: Box<dyn Connection>, limiter: RateLimiter) -> Self {
+        Self {
+            connection,
+            limiter,
+            last_outcome: None,
+        }
+    }
+
+    /// What the limiter did with the most recent send.
+    pub fn last_outcome(&self) -> Option<RateLimitOutcome> {
+        self.last_outcome
+    }
+
+    fn admit(&mut self, packet: &J1939Packet) -> Result<(), anyhow::Error> {
+        let pgn = tp::base_pgn(packet);
+        let clock = self.connection.clock();
+        let outcome = self.limiter.check(pgn, clock.now());
+        self.last_outcome = Some(outcome);
+        match outcome {
+            RateLimitOutcome::Sent => Ok(()),
+            RateLimitOutcome::Delayed(wait) => {
+                log::debug!("PGN {:04X} delayed {:?}", pgn, wait);
+                clock.sleep(wait);
+                Ok(())
+            }
+            RateLimitOutcome::Dropped => Err(RateLimitExceeded {
+                pgn,
+                interval: self.limiter.limits[&pgn],
+            }
+            .into()),
+        }
+    }
+}
+
+impl Connection for RateLimited {
+    fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet, anyhow::Error> {
+        self.admit(packet)?;
+        self.connection.send(packet)
+    }
+
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<(), anyhow::Error> {
+        self.admit(packet)?;
+        self.connection.transmit(packet)
+    }
+
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
+        self.connection.iter()
+    }
+
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+        self.connection.iter_filtered(filter)
+    }
+
+    fn address(&self) -> u8 {
+        self.connection.address()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.connection.clock()
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        self.connection.info()
+    }
+
+    fn is_running(&self) -> bool {
+        self.connection.is_running()
+    }
+
+    fn close(&mut self) -> Result<(), anyhow::Error> {
+        self.connection.close()
+    }
+
+    /// Clones share the connection, but each limits its own sends.
+    fn clone_connection(&self) -> Box<dyn Connection> {
+        Box::new(RateLimited::new(
+            self.connection.clone(),
+            self.limiter.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusKind;
+    use crate::clock::MockClock;
+    use crate::connection::ConnectOptions;
+    use crate::j1939;
+    use crate::rp1210::Rp1210;
+
+    #[test]
+    fn check() {
+        let interval = Duration::from_millis(50);
+        let start = Instant::now();
+        let mut drop = RateLimiter::new(OverRate::Drop).limit(0xEA00, interval);
+        assert_eq!(RateLimitOutcome::Sent, drop.check(0xEA00, start));
+        assert_eq!(RateLimitOutcome::Dropped, drop.check(0xEA00, start + interval / 2));
+        assert_eq!(RateLimitOutcome::Sent, drop.check(0xFEF1, start));
+        assert_eq!(RateLimitOutcome::Sent, drop.check(0xEA00, start + interval));
+
+        let mut delay = RateLimiter::new(OverRate::Delay).limit(0xEA00, interval);
+        assert_eq!(RateLimitOutcome::Sent, delay.check(0xEA00, start));
+        assert_eq!(
+            RateLimitOutcome::Delayed(interval),
+            delay.check(0xEA00, start)
+        );
+        // the delayed send is scheduled, so the next one waits for it
+        assert_eq!(
+            RateLimitOutcome::Delayed(interval * 2),
+            delay.check(0xEA00, start)
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn delays_on_the_connection_clock() -> anyhow::Result<()> {
+        let clock = MockClock::new();
+        let sim = Rp1210::new_with_options(
+            "SIM",
+            19,
+            None,
+            "",
+            0xF9,
+            false,
+            &ConnectOptions {
+                clock: Arc::new(clock.clone()),
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        let interval = Duration::from_secs(3600);
+        let limiter = RateLimiter::new(OverRate::Delay).limit(0xEA00, interval);
+        let mut limited = RateLimited::new(Box::new(sim), limiter);
+        let start = clock.now();
+        let wall = Instant::now();
+        limited.send(&j1939::request(0xFEEC, 0xFF, 0xF9))?;
+        limited.send(&j1939::request(0xFEEC, 0xFF, 0xF9))?;
+        assert_eq!(Some(RateLimitOutcome::Delayed(interval)), limited.last_outcome());
+        assert_eq!(interval, clock.now() - start);
+        assert!(wall.elapsed() < Duration::from_secs(60));
+        limited.close()
+    }
+}
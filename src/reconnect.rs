@@ -0,0 +1,466 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::bus::{Bus, BusIterator, BusKind, Filter};
+use crate::connection::{self, Connection, ConnectionFactory, ConnectionInfo};
+use crate::packet::J1939Packet;
+
+/// How hard `Reconnecting` tries before giving up on a lost adapter.
+///
+/// The delay before each attempt doubles, starting at `initial_delay`.  Reconnecting stops after `attempts`
+/// failed connects, or when the next delay would take it past `budget` since the adapter was lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub attempts: u32,
+    pub budget: Duration,
+    pub initial_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            budget: Duration::from_secs(30),
+            initial_delay: Duration::from_millis(250),
+        }
+    }
+}
+
+/// The adapter was lost and couldn't be reconnected within the `ReconnectPolicy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectFailed {
+    pub attempts: u32,
+    pub elapsed: Duration,
+    /// error from the last connect
+    pub last_error: Option<String>,
+}
+
+impl Display for ReconnectFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Adapter lost: {} reconnect attempts failed in {:?}",
+            self.attempts, self.elapsed
+        )?;
+        if let Some(e) = &self.last_error {
+            write!(f, ": {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ReconnectFailed {}
+
+type Shared = Arc<Mutex<Option<Box<dyn Connection>>>>;
+
+/// Connection that reconnects with its factory when the underlying connection's stream ends without close(),
+/// e.g. when the bridge process exits.
+///
+/// Packets are relayed onto this connection's own bus, created once and kept across reconnects, so iter()
+/// consumers continue with the new connection's packets without subscribing again.  When the policy is
+/// exhausted the bus is closed.  iter() then just ends; use iter_results() to receive the `ReconnectFailed`.
+///
+/// Clones share the relay thread and the current connection.
+#[derive(Clone)]
+pub struct Reconnecting {
+    /// None once this handle is closed
+    relay: Option<Arc<Relay>>,
+    connection: Shared,
+    bus: Box<dyn Bus<J1939Packet>>,
+    address: u8,
+    reconnects: Arc<AtomicU32>,
+    failure: Arc<Mutex<Option<ReconnectFailed>>>,
+}
+
+/// The relay thread, shared by clones of a `Reconnecting`.  Stopped when the last clone is closed or dropped.
+struct Relay {
+    connection: Shared,
+    bus: Box<dyn Bus<J1939Packet>>,
+    closing: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Relay {
+    fn close(&mut self) -> Result<()> {
+        self.closing.store(true, Ordering::Relaxed);
+        let result = match self.connection.lock().unwrap().take() {
+            Some(mut c) => c.close(),
+            None => Ok(()),
+        };
+        if let Some(thread) = self.thread.take() {
+            thread
+                .join()
+                .map_err(|_| anyhow!("reconnect thread panicked"))?;
+        }
+        self.bus.close();
+        result
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+impl Reconnecting {
+    /// Connect with `factory`.  The first connect isn't retried.
+    pub fn new(
+        factory: Box<dyn ConnectionFactory>,
+        policy: ReconnectPolicy,
+        bus: BusKind,
+    ) -> Result<Reconnecting> {
+        let first = factory.connect()?;
+        let address = first.address();
+        let connection: Shared = Arc::new(Mutex::new(Some(first)));
+        let bus = bus.create();
+        let reconnects = Arc::new(AtomicU32::new(0));
+        let failure = Arc::new(Mutex::new(None));
+        let closing = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let connection = connection.clone();
+            let mut bus = bus.clone();
+            let reconnects = reconnects.clone();
+            let failure = failure.clone();
+            let closing = closing.clone();
+            std::thread::Builder::new()
+                .name("reconnect".into())
+                .spawn(move || {
+                    loop {
+                        let packets = match connection.lock().unwrap().as_ref() {
+                            Some(c) => c.iter(),
+                            None => break,
+                        };
+                        for p in packets {
+                            if closing.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            bus.push(p);
+                        }
+                        if closing.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        log::warn!("Adapter lost.  Reconnecting.");
+                        if let Some(mut lost) = connection.lock().unwrap().take() {
+                            let _ = lost.close();
+                        }
+                        match reconnect(factory.as_ref(), &policy, &closing) {
+                            Ok(c) => {
+                                reconnects.fetch_add(1, Ordering::Relaxed);
+                                log::warn!("Reconnected");
+                                *connection.lock().unwrap() = Some(c);
+                            }
+                            Err(e) => {
+                                log::error!("{}", e);
+                                *failure.lock().unwrap() = Some(e);
+                                break;
+                            }
+                        }
+                    }
+                    // ends iter() and iter_results(), which then reports the failure
+                    bus.close();
+                })?
+        };
+        let relay = Relay {
+            connection: connection.clone(),
+            bus: bus.clone(),
+            closing,
+            thread: Some(thread),
+        };
+        Ok(Reconnecting {
+            relay: Some(Arc::new(relay)),
+            connection,
+            bus,
+            address,
+            reconnects,
+            failure,
+        })
+    }
+
+    fn open(&self) -> Result<()> {
+        match self.relay {
+            Some(_) => Ok(()),
+            None => Err(anyhow!("Connection closed")),
+        }
+    }
+
+    /// Number of times the adapter was lost and successfully reconnected.
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Why reconnecting gave up, once it has.
+    pub fn failure(&self) -> Option<ReconnectFailed> {
+        self.failure.lock().unwrap().clone()
+    }
+
+    /// Close the current connection and connect again, as if the adapter was lost, e.g. to recover a wedged
+    /// adapter.  Returns once the old connection is closed; see reconnects() for when the new one is up.
+    pub fn force_reconnect(&self) -> Result<()> {
+        self.open()?;
+        match self.connection.lock().unwrap().take() {
+            Some(mut c) => c.close(),
+            None => Ok(()),
+        }
+    }
+
+    /// iter(), ending with `Err(ReconnectFailed)` if the adapter was lost for good.  Ends without an error on close().
+    pub fn iter_results(&self) -> impl Iterator<Item = Result<Option<J1939Packet>>> {
+        connection::until_failure(self.bus.iter(), self.failure.clone())
+    }
+}
+
+/// Connect with backoff, for at most policy.attempts tries within policy.budget.
+fn reconnect(
+    factory: &dyn ConnectionFactory,
+    policy: &ReconnectPolicy,
+    closing: &AtomicBool,
+) -> Result<Box<dyn Connection>, ReconnectFailed> {
+    let start = Instant::now();
+    let mut delay = policy.initial_delay;
+    let mut last_error = None;
+    let mut attempts = 0;
+    while attempts < policy.attempts
+        && start.elapsed() + delay <= policy.budget
+        && !closing.load(Ordering::Relaxed)
+    {
+        std::thread::sleep(delay);
+        attempts += 1;
+        match factory.connect() {
+            Ok(c) => return Ok(c),
+            Err(e) => {
+                log::debug!("reconnect attempt {}: {:#}", attempts, e);
+                last_error = Some(format!("{:#}", e));
+            }
+        }
+        delay *= 2;
+    }
+    Err(ReconnectFailed {
+        attempts,
+        elapsed: start.elapsed(),
+        last_error,
+    })
+}
+
+impl Connection for Reconnecting {
+    fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet> {
+        self.open()?;
+        match self.connection.lock().unwrap().as_mut() {
+            Some(c) => c.send(packet),
+            None => Err(anyhow!("Adapter lost")),
+        }
+    }
+
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<()> {
+        self.open()?;
+        match self.connection.lock().unwrap().as_mut() {
+            Some(c) => c.transmit(packet),
+            None => Err(anyhow!("Adapter lost")),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter()
+    }
+
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter_filtered(filter)
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Info of the current connection.  Default while reconnecting.
+    fn info(&self) -> ConnectionInfo {
+        self.connection
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|c| c.info())
+            .unwrap_or_default()
+    }
+
+    /// True while reconnecting, until the policy is exhausted.
+    fn is_running(&self) -> bool {
+        self.relay
+            .as_ref()
+            .is_some_and(|r| r.thread.as_ref().is_some_and(|t| !t.is_finished()))
+    }
+
+    /// The relay and the current connection stop with the last clone.
+    fn close(&mut self) -> Result<()> {
+        match self.relay.take().and_then(Arc::into_inner) {
+            Some(mut relay) => relay.close(),
+            None => Ok(()),
+        }
+    }
+
+    fn clone_connection(&self) -> Box<dyn Connection> {
+        Box::new(self.clone())
+    }
+}
+
+impl Drop for Reconnecting {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+
+    use super::*;
+
+    /// Connection whose stream ends after a few packets, as if the adapter was unplugged.
+    struct Flaky {
+        bus: Box<dyn Bus<J1939Packet>>,
+    }
+
+    impl Flaky {
+        fn new(seq: u8) -> Flaky {
+            let bus: Box<dyn Bus<J1939Packet>> = BusKind::PushBus.create();
+            let mut push = bus.clone();
+            std::thread::spawn(move || {
+                for _ in 0..3 {
+                    std::thread::sleep(Duration::from_millis(10));
+                    push.push(Some(J1939Packet::new(Some(0), 0, 0x18FFAA00, &[seq])));
+                }
+                // closing discards unread packets, so give Reconnecting time to relay them
+                std::thread::sleep(Duration::from_millis(50));
+                push.close();
+            });
+            Flaky { bus }
+        }
+    }
+
+    impl Connection for Flaky {
+        fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet> {
+            Ok(packet.clone())
+        }
+        fn transmit(&mut self, _packet: &J1939Packet) -> Result<()> {
+            Ok(())
+        }
+        fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
+            self.bus.iter()
+        }
+        fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+            self.bus.iter_filtered(filter)
+        }
+        fn address(&self) -> u8 {
+            0xF9
+        }
+        fn info(&self) -> ConnectionInfo {
+            ConnectionInfo::default()
+        }
+        fn is_running(&self) -> bool {
+            true
+        }
+        fn close(&mut self) -> Result<()> {
+            self.bus.close();
+            Ok(())
+        }
+        fn clone_connection(&self) -> Box<dyn Connection> {
+            Box::new(Flaky {
+                bus: self.bus.clone(),
+            })
+        }
+    }
+
+    /// Connects `available` times, then fails.
+    struct Factory {
+        connects: AtomicU32,
+        available: u32,
+    }
+
+    impl ConnectionFactory for Factory {
+        fn connect(&self) -> Result<Box<dyn Connection>> {
+            let n = self.connects.fetch_add(1, Ordering::Relaxed);
+            if n >= self.available {
+                bail!("adapter not found");
+            }
+            Ok(Box::new(Flaky::new(n as u8)))
+        }
+    }
+
+    #[test]
+    fn gives_up_with_error() -> Result<()> {
+        let policy = ReconnectPolicy {
+            attempts: 3,
+            budget: Duration::from_secs(5),
+            initial_delay: Duration::from_millis(5),
+        };
+        let factory = Factory {
+            connects: AtomicU32::new(0),
+            available: 2,
+        };
+        let reconnecting = Reconnecting::new(Box::new(factory), policy, BusKind::PushBus)?;
+        let results: Vec<_> = reconnecting.iter_results().collect();
+        // packets from both connections, then the error
+        let seqs: std::collections::HashSet<u8> = results
+            .iter()
+            .filter_map(|r| r.as_ref().ok()?.as_ref().map(|p| p.data()[0]))
+            .collect();
+        assert_eq!(std::collections::HashSet::from([0, 1]), seqs);
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        let failed = error.downcast_ref::<ReconnectFailed>().unwrap();
+        assert_eq!(3, failed.attempts);
+        assert_eq!(Some("adapter not found".to_string()), failed.last_error);
+        assert_eq!(1, reconnecting.reconnects());
+        Ok(())
+    }
+
+    #[test]
+    fn consumers_survive_a_reconnect() -> Result<()> {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let descriptor = crate::ConnectionDescriptor::builder("SIM", 25)
+            .bus(BusKind::MultiQueue)
+            .build()?;
+        let mut reconnecting =
+            Reconnecting::new(Box::new(descriptor), policy, BusKind::MultiQueue)?;
+        let mut packets = reconnecting.iter().flatten();
+        assert!(packets.next().is_some());
+
+        reconnecting.force_reconnect()?;
+        let end = Instant::now() + Duration::from_secs(5);
+        while reconnecting.reconnects() == 0 && Instant::now() < end {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(1, reconnecting.reconnects());
+        // the same consumer receives the new connection's packets
+        let after = Instant::now();
+        while Instant::now() - after < Duration::from_millis(500) {
+            packets.next();
+        }
+        assert!(packets.next().is_some());
+        assert!(reconnecting.is_running());
+        reconnecting.close()
+    }
+
+    #[test]
+    fn budget() {
+        let policy = ReconnectPolicy {
+            attempts: 100,
+            budget: Duration::from_secs(1),
+            initial_delay: Duration::from_millis(100),
+        };
+        let factory = Factory {
+            connects: AtomicU32::new(0),
+            available: 0,
+        };
+        let failed = reconnect(&factory, &policy, &AtomicBool::new(false))
+            .err()
+            .unwrap();
+        // 100 + 200 + 400 ms, then 800 ms would exceed the budget
+        assert_eq!(3, failed.attempts);
+    }
+}
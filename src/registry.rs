@@ -0,0 +1,70 @@
+use std::sync::Mutex;
+
+/// An RP1210 client opened by this process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenClient {
+    pub adapter: String,
+    pub device: i16,
+    pub channel: Option<u8>,
+}
+
+static OPEN: Mutex<Vec<OpenClient>> = Mutex::new(Vec::new());
+
+/// Clients currently open in this process.  RP1210 has no standard way to ask the driver about clients
+/// opened by other processes.
+pub fn open_clients() -> Vec<OpenClient> {
+    OPEN.lock().unwrap().clone()
+}
+
+/// Removes the client from the registry when dropped.
+pub(crate) struct Registration(OpenClient);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut open = OPEN.lock().unwrap();
+        if let Some(i) = open.iter().position(|c| *c == self.0) {
+            open.remove(i);
+        }
+    }
+}
+
+/// Record a new client, warning if the same device and channel is already open.
+pub(crate) fn register(adapter: &str, device: i16, channel: Option<u8>) -> Registration {
+    let client = OpenClient {
+        adapter: adapter.to_string(),
+        device,
+        channel,
+    };
+    let mut open = OPEN.lock().unwrap();
+    if open.contains(&client) {
+        log::warn!(
+            "{} device {} channel {} is already open in this process",
+            adapter,
+            device,
+            channel.unwrap_or(0)
+        );
+    }
+    open.push(client.clone());
+    Registration(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_and_drop() {
+        let client = OpenClient {
+            adapter: "TEST".into(),
+            device: 99,
+            channel: Some(2),
+        };
+        let a = register("TEST", 99, Some(2));
+        let b = register("TEST", 99, Some(2));
+        assert_eq!(2, open_clients().iter().filter(|c| **c == client).count());
+        drop(a);
+        assert_eq!(1, open_clients().iter().filter(|c| **c == client).count());
+        drop(b);
+        assert!(!open_clients().contains(&client));
+    }
+}
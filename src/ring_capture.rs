@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::clock::Clock;
+use crate::connection::Connection;
+use crate::packet::J1939Packet;
+
+/// Keeps the last `window` of a connection's traffic in memory, for saving when an intermittent fault shows up,
+/// e.g. with `session::write_session()`.  Records from a background thread until stopped or dropped.
+///
+/// Packets are aged by the connection's clock as they arrive, so adapter time stamps don't matter.
+pub struct RingCapture {
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    packets: Arc<Mutex<VecDeque<(Instant, J1939Packet)>>>,
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RingCapture {
+    /// Start recording everything `connection` receives from now on.
+    pub fn start(connection: &dyn Connection, window: Duration) -> Result<RingCapture> {
+        let clock = connection.clock();
+        let packets: Arc<Mutex<VecDeque<(Instant, J1939Packet)>>> = Default::default();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stream = connection.iter();
+            let clock = clock.clone();
+            let packets = packets.clone();
+            let stopping = stopping.clone();
+            std::thread::Builder::new()
+                .name("ring capture".into())
+                .spawn(move || {
+                    for p in stream {
+                        if stopping.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        if let Some(p) = p {
+                            let now = clock.now();
+                            let mut packets = packets.lock().unwrap();
+                            packets.push_back((now, p));
+                            expire(&mut packets, now, window);
+                        }
+                    }
+                })?
+        };
+        Ok(RingCapture {
+            window,
+            clock,
+            packets,
+            stopping,
+            thread: Some(thread),
+        })
+    }
+
+    /// The packets received within the last window, oldest first.  Recording continues.
+    pub fn trigger(&self) -> Vec<J1939Packet> {
+        let mut packets = self.packets.lock().unwrap();
+        expire(&mut packets, self.clock.now(), self.window);
+        packets.iter().map(|(_, p)| p.clone()).collect()
+    }
+
+    /// Stop recording.  trigger() still returns what was recorded.
+    pub fn stop(&mut self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RingCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Drop the packets that arrived more than `window` before `now`.
+fn expire(packets: &mut VecDeque<(Instant, J1939Packet)>, now: Instant, window: Duration) {
+    while packets
+        .front()
+        .is_some_and(|(arrived, _)| now.saturating_duration_since(*arrived) > window)
+    {
+        packets.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusKind;
+    use crate::clock::MockClock;
+    use crate::connection::ConnectOptions;
+    use crate::rp1210::Rp1210;
+
+    #[test]
+    #[allow(deprecated)]
+    fn keeps_the_last_window() -> Result<()> {
+        let clock = MockClock::new();
+        let mut sim = Rp1210::new_with_options(
+            "SIM",
+            24,
+            None,
+            "",
+            0xF9,
+            true,
+            &ConnectOptions {
+                clock: Arc::new(clock.clone()),
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        let mut capture = RingCapture::start(&sim, Duration::from_secs(5))?;
+        // replayed packets have one byte, unlike the generated traffic
+        let replayed = |capture: &RingCapture, last: u8| -> Vec<u8> {
+            let end = Instant::now() + Duration::from_secs(2);
+            loop {
+                let data: Vec<u8> = capture
+                    .trigger()
+                    .iter()
+                    .filter(|p| p.data().len() == 1)
+                    .map(|p| p.data()[0])
+                    .collect();
+                if data.last() == Some(&last) || Instant::now() > end {
+                    return data;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        sim.replay([J1939Packet::new(Some(0), 0, 0x18FFAB00, &[1]).as_received()]);
+        assert_eq!(vec![1], replayed(&capture, 1));
+        clock.advance(Duration::from_secs(10));
+        sim.replay([J1939Packet::new(Some(0), 0, 0x18FFAB00, &[2]).as_received()]);
+        assert_eq!(vec![2], replayed(&capture, 2));
+
+        clock.advance(Duration::from_secs(10));
+        assert!(capture.trigger().iter().all(|p| p.data().len() != 1));
+        capture.stop();
+        sim.close()
+    }
+}
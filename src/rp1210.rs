@@ -1,7 +1,21 @@
 use crate::bus::*;
-use crate::connection::Connection;
+use crate::clock::Clock;
+use crate::connection::{
+    self, Access, ClaimMode, ConnectOptions, Connection, ConnectionInfo, FrameCounter, OnReadError, ReadMode,
+    SendOptions,
+};
+use crate::connection_string;
+use crate::filter::{self, J1939Filter};
+use crate::hardware_status::{BusState, BusStates, HARDWARE_STATUS_SIZE};
+use crate::j1587::J1587Message;
+use crate::j1939::{self, AddressClaim};
+use crate::keepalive::Keepalive;
 use crate::packet::*;
+use crate::registry::{self, Registration};
+use crate::rp1210_error::{self, ReturnCode, Rp1210Error};
 use crate::rp1210_parsing;
+use crate::timestamp::{TimestampSource, Timestamps};
+use crate::wireless::WirelessState;
 use anyhow::*;
 use libloading::os::windows::Symbol as WinSymbol;
 use libloading::*;
@@ -9,10 +23,38 @@ use std::ffi::CString;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::atomic::*;
 use std::sync::*;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use std::time::Instant;
 
-pub const PACKET_SIZE: usize = 1600;
+/// Largest J1939-21 transport protocol payload (255 packets of 7 bytes).
+pub const MAX_TP_DATA: usize = 1785;
+/// Received message header: timestamp (4), echo (1), PGN (3), priority (1), source (1), destination (1).  See
+/// `Rp1210Message`.
+pub const RX_HEADER_SIZE: usize = 11;
+/// Read buffer size.  Adapters that handle TP deliver up to MAX_TP_DATA bytes in one message, which the previous
+/// 1600 byte buffer could truncate.  Rounded up for driver specific extras.
+pub const PACKET_SIZE: usize = 2048;
+const _: () = assert!(PACKET_SIZE >= RX_HEADER_SIZE + MAX_TP_DATA);
+
+// RP1210_SendCommand command numbers (RP1210C)
+const CMD_RESET_DEVICE: u16 = 0;
+const CMD_SET_ALL_FILTERS_STATES_TO_PASS: u16 = 3;
+const CMD_SET_MESSAGE_FILTERING_FOR_J1939: u16 = 4;
+const CMD_ECHO_TRANSMITTED_MESSAGES: u16 = 16;
+const CMD_SET_ALL_FILTERS_STATES_TO_DISCARD: u16 = 17;
+const CMD_PROTECT_J1939_ADDRESS: u16 = 19;
+const CMD_SET_J1939_INTERPACKET_TIME: u16 = 27;
+const CMD_RELEASE_J1939_ADDRESS: u16 = 31;
+const CMD_GET_PROTOCOL_CONNECTION_SPEED: u16 = 45;
+const CMD_GET_WIRELESS_STATE: u16 = 48;
+
+const ECHO_ON: u8 = 1;
+// RP1210_SendMessage nBlockOnSend
+const NON_BLOCKING_IO: i16 = 0;
+const BLOCKING_IO: i16 = 1;
+const CLAIM_BLOCK_UNTIL_DONE: u8 = 0;
+const CLAIM_RETURN_BEFORE_COMPLETION: u8 = 2;
 
 type ClientConnectType = unsafe extern "stdcall" fn(i32, i16, *const char, i32, i32, i16) -> i16;
 type SendType = unsafe extern "stdcall" fn(i16, *const u8, i16, i16, i16) -> i16;
@@ -21,27 +63,145 @@ type CommandType = unsafe extern "stdcall" fn(u16, i16, *const u8, u16) -> i16;
 type _VERSION = unsafe extern "stdcall" fn(i16, *const u8, i16, i16) -> i16;
 type GetErrorType = unsafe extern "stdcall" fn(i16, *const u8) -> i16;
 type ClientDisconnectType = unsafe extern "stdcall" fn(i16) -> i16;
+type ReadDetailedVersionType = unsafe extern "stdcall" fn(i16, *mut u8, *mut u8, *mut u8) -> i16;
+type GetHardwareStatusType = unsafe extern "stdcall" fn(i16, *mut u8, i16, i16) -> i16;
 
 pub struct Rp1210 {
+    /// None once this handle is closed
+    client: Option<Arc<Client>>,
+    adapter: String,
+    device: i16,
+    connection_string: String,
+    frames: FrameCounter,
+    bus: Box<dyn Bus<J1939Packet>>,
+    address: u8,
+    address_claim: Option<AddressClaim>,
+    /// how connecting and set_j1939_address() wait for the claim
+    claim_mode: ClaimMode,
+    /// stopped before this handle lets go of the client, so the DLL isn't called after disconnect
+    keepalive: Option<Keepalive>,
+    inject_sent: bool,
+    /// shared with the read thread
+    timestamps: Timestamps,
+    access: Access,
+    send_options: SendOptions,
+    /// why the read thread stopped, with OnReadError::Terminate
+    read_error: Arc<Mutex<Option<Rp1210Error>>>,
+    /// the API's, kept after close
+    last_error: Arc<Mutex<Option<Rp1210Error>>>,
+    clock: Arc<dyn Clock>,
+    /// read by the read thread with a bus state interval
+    bus_states: BusStates,
+}
+
+/// RP1210 client shared by clones of an `Rp1210`.  Disconnects when the last clone is closed or dropped.
+struct Client {
     api: API,
-    bus: Box<PushBus<J1939Packet>>,
     running: Arc<AtomicBool>,
+    /// read thread.  Joined before disconnecting, so the DLL isn't called after disconnect.
+    thread: Option<JoinHandle<()>>,
+    bus: Box<dyn Bus<J1939Packet>>,
+    bus_states: BusStates,
+    /// the address the adapter protects, released on close.  Changed by set_j1939_address().
+    address_claim: Mutex<Option<AddressClaim>>,
+    registration: Option<Registration>,
+    /// set by the read thread once it disconnected for the idle timeout
+    idle: Arc<AtomicBool>,
+    /// Some with ReadMode::Poll, which has no read thread
+    poll: Option<Poll>,
+}
+
+/// What poll() needs to build packets.
+struct Poll {
+    channel: u8,
+    time_stamp_weight: f64,
+    /// tracks counter wraps across polls, as the read thread's copy does
+    timestamps: Mutex<Timestamps>,
+}
+
+impl Client {
+    /// The read thread is running until close(), unless it panicked or stopped on a read error.  Polled clients
+    /// run until close().
+    fn is_running(&self) -> bool {
+        self.running.load(Relaxed) && self.thread.as_ref().is_none_or(|t| !t.is_finished())
+    }
+
+    /// Stop the read thread, release the address and disconnect.
+    fn close(&mut self) -> Result<()> {
+        self.running.store(false, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().map_err(|_| anyhow!("RP1210 read thread panicked"))?;
+        }
+        self.bus.close();
+        self.bus_states.close();
+        if self.idle.load(Relaxed) {
+            // disconnecting released the address
+            self.api.connected = false;
+            self.address_claim = Mutex::new(None);
+        }
+        let released = match self.address_claim.get_mut().unwrap().take() {
+            Some(claim) => self.api.release_address(claim.requested).map(|_| ()),
+            None => Ok(()),
+        };
+        let disconnected = self.api.disconnect();
+        self.registration.take();
+        released.and(disconnected)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Clones share the client and bus, so they see the same stream.  Keepalives aren't cloned.
+impl Clone for Rp1210 {
+    fn clone(&self) -> Self {
+        Rp1210 {
+            client: self.client.clone(),
+            adapter: self.adapter.clone(),
+            device: self.device,
+            connection_string: self.connection_string.clone(),
+            frames: self.frames.clone(),
+            bus: self.bus.clone(),
+            address: self.address,
+            address_claim: self.address_claim,
+            claim_mode: self.claim_mode,
+            keepalive: None,
+            inject_sent: self.inject_sent,
+            timestamps: self.timestamps.clone(),
+            access: self.access,
+            send_options: self.send_options,
+            read_error: self.read_error.clone(),
+            last_error: self.last_error.clone(),
+            clock: self.clock.clone(),
+            bus_states: self.bus_states.clone(),
+        }
+    }
 }
 #[derive(Debug)]
 struct API {
     id: i16,
+    connected: bool,
 
-    _lib: Library,
+    /// shared with the read thread, so the DLL stays loaded while the thread holds its symbols
+    lib: Arc<Library>,
     client_connect_fn: WinSymbol<ClientConnectType>,
     send_fn: WinSymbol<SendType>,
     read_fn: WinSymbol<ReadType>,
     send_command_fn: WinSymbol<CommandType>,
     get_error_fn: WinSymbol<GetErrorType>,
     disconnect_fn: WinSymbol<ClientDisconnectType>,
+    /// not implemented by older drivers
+    read_detailed_version_fn: Option<WinSymbol<ReadDetailedVersionType>>,
+    get_hardware_status_fn: Option<WinSymbol<GetHardwareStatusType>>,
+    /// most recent error returned by the driver, shared with the read thread and the Rp1210 handles
+    last_error: Arc<Mutex<Option<Rp1210Error>>>,
 }
 impl Drop for API {
     fn drop(&mut self) {
-        unsafe { (*self.disconnect_fn)(self.id) };
+        let _ = self.disconnect();
     }
 }
 impl API {
@@ -56,78 +216,174 @@ impl API {
             let get_error: Symbol<GetErrorType> = lib.get(b"RP1210_GetErrorMsg\0").unwrap();
             let disconnect: Symbol<ClientDisconnectType> =
                 lib.get(b"RP1210_ClientDisconnect\0").unwrap();
+            let read_detailed_version: Option<Symbol<ReadDetailedVersionType>> =
+                lib.get(b"RP1210_ReadDetailedVersion\0").ok();
+            let get_hardware_status: Option<Symbol<GetHardwareStatusType>> =
+                lib.get(b"RP1210_GetHardwareStatus\0").ok();
             API {
                 id: 0,
+                connected: false,
                 client_connect_fn: client_connect.into_raw(),
                 send_fn: send.into_raw(),
                 read_fn: read.into_raw(),
                 send_command_fn: send_command.into_raw(),
                 get_error_fn: get_error.into_raw(),
                 disconnect_fn: disconnect.into_raw(),
-                _lib: lib,
+                read_detailed_version_fn: read_detailed_version.map(|s| s.into_raw()),
+                get_hardware_status_fn: get_hardware_status.map(|s| s.into_raw()),
+                last_error: Default::default(),
+                lib: Arc::new(lib),
             }
         })
     }
-    fn send_command(&self, cmd: u16, buf: Vec<u8>) -> Result<i16> {
-        self.verify_return(unsafe {
-            (self.send_command_fn)(cmd, self.id, buf.as_ptr(), buf.len() as u16)
-        })
+    /// RP1210_SendCommand needs a client id, so no command is valid before client_connect().
+    /// RP1210_Reset_Device (0) is only accepted from the only client, and closes every client.
+    /// Address claim (19) and release (31) are J1939 only.
+    fn send_command(&self, cmd: u16, buf: &[u8]) -> Result<i16> {
+        let rtn = unsafe { (self.send_command_fn)(cmd, self.id, buf.as_ptr(), buf.len() as u16) };
+        log::trace!("RP1210_SendCommand({}, {}, [{}]) = {}", cmd, self.id, hex(buf), rtn);
+        self.verify_return(ReturnCode::Status, rtn)
+    }
+    /// Send a command that writes its result into a `len` byte buffer.  The buffer starts filled with 0xFF.
+    fn query_command(&self, cmd: u16, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0xFF; len];
+        let rtn = unsafe { (self.send_command_fn)(cmd, self.id, buf.as_mut_ptr(), buf.len() as u16) };
+        log::trace!("RP1210_SendCommand({}, {}, {} bytes) = {} [{}]", cmd, self.id, len, rtn, hex(&buf));
+        self.verify_return(ReturnCode::Status, rtn)?;
+        Ok(buf)
+    }
+    /// Speed negotiated by the adapter, e.g. "500000".
+    fn connection_speed(&self) -> Result<String> {
+        let buf = self.query_command(CMD_GET_PROTOCOL_CONNECTION_SPEED, 17)?;
+        Ok(c_string(&buf))
+    }
+    /// DLL version.  The API and firmware versions are discarded.
+    fn driver_version(&self) -> Result<String> {
+        let read_detailed_version = self
+            .read_detailed_version_fn
+            .as_ref()
+            .ok_or(anyhow!("RP1210_ReadDetailedVersion not supported"))?;
+        let mut api = [0u8; 17];
+        let mut dll = [0u8; 17];
+        let mut fw = [0u8; 17];
+        self.verify_return(ReturnCode::Status, unsafe {
+            read_detailed_version(self.id, api.as_mut_ptr(), dll.as_mut_ptr(), fw.as_mut_ptr())
+        })?;
+        Ok(c_string(&dll))
     }
     fn get_error(&self, code: i16) -> Result<String> {
         let mut buf: [u8; 1024] = [0; 1024];
         let size = unsafe { (self.get_error_fn)(code, buf.as_mut_ptr()) } as usize;
         Ok(String::from_utf8_lossy(&buf[0..size]).to_string())
     }
-    fn verify_return(&self, v: i16) -> Result<i16> {
-        if v < 0 || v > 127 {
-            Err(anyhow!(format!("code: {} msg: {}", v, self.get_error(v)?)))
-        } else {
-            Ok(v)
-        }
+    /// `rtn` read as `kind`, with the driver's message for an error code.
+    fn verify_return(&self, kind: ReturnCode, rtn: i16) -> Result<i16> {
+        kind.check(rtn).or_else(|code| {
+            let error = Rp1210Error {
+                code,
+                message: self.get_error(code)?,
+            };
+            *self.last_error.lock().unwrap() = Some(error.clone());
+            Err(error.into())
+        })
     }
+    /// RP1210_ClientConnect has no parameter naming the application, so vendor connection managers can't be told
+    /// who holds the client.  The first parameter is a window handle for RP1210A notifications, and the two
+    /// longs are the transmit and receive buffer sizes, where 0 is the driver's default.
     fn client_connect(
         &mut self,
         dev_id: i16,
         connection_string: &str,
-        address: u8,
         app_packetize: bool,
     ) -> Result<()> {
-        let c_to_print = CString::new(connection_string).expect("CString::new failed");
-        self.id = self.verify_return(unsafe {
+        // a NUL would truncate the string, so it's refused rather than passed to the driver
+        let c_to_print = CString::new(connection_string)
+            .with_context(|| format!("Connection string {:?} contains a NUL", connection_string))?;
+        let rtn = unsafe {
             (self.client_connect_fn)(
+                // hwndClient
                 0,
                 dev_id,
                 c_to_print.as_ptr() as *const char,
+                // lTxBufferSize and lRcvBufferSize
                 0,
                 0,
                 if app_packetize { 1 } else { 0 },
             )
-        })?;
-        if !app_packetize {
-            self.send_command(
-                /*CMD_PROTECT_J1939_ADDRESS*/ 19,
-                vec![
-                    address, 0, 0, 0xE0, 0xFF, 0, 0x81, 0, 0, /*CLAIM_BLOCK_UNTIL_DONE*/ 0,
-                ],
-            )?;
+        };
+        log::trace!(
+            "RP1210_ClientConnect(0, {}, \"{}\", 0, 0, {}) = {}",
+            dev_id,
+            connection_string,
+            app_packetize as u8,
+            rtn
+        );
+        self.id = self.verify_return(ReturnCode::ClientId, rtn)?;
+        self.connected = true;
+        self.send_command(CMD_ECHO_TRANSMITTED_MESSAGES, &[ECHO_ON])?;
+        self.send_command(CMD_SET_ALL_FILTERS_STATES_TO_PASS, &[])?;
+        Ok(())
+    }
+    fn protect_address(&self, address: u8, mode: ClaimMode) -> Result<i16> {
+        // address, NAME, then how to claim
+        let mut buf = [address; 10];
+        buf[1..9].copy_from_slice(&j1939::TOOL_NAME.to_bytes());
+        buf[9] = match mode {
+            ClaimMode::BlockUntilDone => CLAIM_BLOCK_UNTIL_DONE,
+            ClaimMode::Timeout(_) | ClaimMode::NonBlocking => CLAIM_RETURN_BEFORE_COMPLETION,
+        };
+        self.send_command(CMD_PROTECT_J1939_ADDRESS, &buf)
+    }
+    fn release_address(&self, address: u8) -> Result<i16> {
+        self.send_command(CMD_RELEASE_J1939_ADDRESS, &[address])
+    }
+    fn disconnect(&mut self) -> Result<()> {
+        if self.connected {
+            self.connected = false;
+            let rtn = unsafe { (*self.disconnect_fn)(self.id) };
+            log::trace!("RP1210_ClientDisconnect({}) = {}", self.id, rtn);
+            self.verify_return(ReturnCode::Status, rtn)?;
         }
-        self.send_command(
-            /*CMD_ECHO_TRANSMITTED_MESSAGES*/ 16,
-            vec![/*ECHO_ON*/ 1],
-        )?;
-        self.send_command(/*CMD_SET_ALL_FILTERS_STATES_TO_PASS*/ 3, vec![])?;
         Ok(())
     }
-    fn send(&self, packet: &J1939Packet) -> Result<i16> {
-        let buf = &packet.packet.data;
-        self.verify_return(unsafe { (self.send_fn)(self.id, buf.as_ptr(), buf.len() as i16, 0, 0) })
+    fn send(&self, packet: &J1939Packet, options: SendOptions) -> Result<i16> {
+        self.send_bytes(packet.raw_bytes(), options)
     }
+    fn send_bytes(&self, buf: &[u8], options: SendOptions) -> Result<i16> {
+        if !self.connected {
+            bail!("Connection closed");
+        }
+        let size = rp1210_error::message_size(buf)?;
+        let notify = options.notify as i16;
+        let block = if options.blocking { BLOCKING_IO } else { NON_BLOCKING_IO };
+        let rtn = unsafe { (self.send_fn)(self.id, buf.as_ptr(), size, notify, block) };
+        log::trace!(
+            "RP1210_SendMessage({}, [{}], {}, {}, {}) = {}",
+            self.id,
+            hex(&buf),
+            buf.len(),
+            notify,
+            block,
+            rtn
+        );
+        self.verify_return(ReturnCode::Status, rtn)
+    }
+}
+
+/// Bytes as hex, for FFI traces.
+fn hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// Text up to the first NUL (or 0xFF fill).
+fn c_string(buf: &[u8]) -> String {
+    let end = buf.iter().position(|b| *b == 0 || *b == 0xFF).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).trim().to_string()
 }
 
 impl Drop for Rp1210 {
     fn drop(&mut self) {
-        self.running.store(false, Relaxed);
-        self.bus.close();
+        let _ = self.close();
     }
 }
 
@@ -140,67 +396,680 @@ impl Rp1210 {
         connection_string: &str,
         address: u8,
         app_packetized: bool,
+        bus: BusKind,
+    ) -> Result<Rp1210> {
+        Self::new_with_options(
+            id,
+            device,
+            channel,
+            connection_string,
+            address,
+            app_packetized,
+            &ConnectOptions::default(),
+            bus,
+        )
+    }
+
+    /// new() with `options`.  Read-only connections don't claim an address, and sends fail.
+    ///
+    /// Fails if connecting and claiming take longer than the connect timeout.  The driver call can't be
+    /// cancelled, so it's left to finish on its own thread, which then disconnects.
+    ///
+    /// With an idle timeout, the read thread disconnects once nothing has been received for that long.  Iterators
+    /// then end, is_running() is false and sends fail.
+    ///
+    /// With `ReadMode::Poll` there is no read thread, so the claim can't see the adapter's echo of it, and
+    /// address_claim() has no claimed address.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_options(
+        id: &str,
+        device: i16,
+        channel: Option<u8>,
+        connection_string: &str,
+        address: u8,
+        app_packetized: bool,
+        options: &ConnectOptions,
+        bus: BusKind,
     ) -> Result<Rp1210> {
+        options.check()?;
+        let access = options.access;
+        let deadline = options.connect_timeout.map(|t| Instant::now() + t);
         let time_stamp_weight = rp1210_parsing::time_stamp_weight(id)?;
+        let registration = registry::register(id, device, channel);
 
         let mut api = API::new(id)?;
+        let lib = api.lib.clone();
         let read = *api.read_fn;
         let get_error_fn = *api.get_error_fn;
-        let connection_string = channel
-            .map(|c| format!("{};Channel={}", connection_string, c))
-            .unwrap_or(connection_string.to_owned());
-        api.client_connect(device, connection_string.as_str(), address, app_packetized)?;
+        let disconnect = *api.disconnect_fn;
+        let get_hardware_status = api.get_hardware_status_fn.as_ref().map(|f| **f);
+        let last_error = api.last_error.clone();
+        let connection_string = connection_string::with_channel(connection_string, channel);
+        let (api, connected) = {
+            let connection_string = connection_string.clone();
+            with_deadline(deadline, "RP1210_ClientConnect", move || {
+                let connected = api.client_connect(device, &connection_string, app_packetized);
+                (api, connected)
+            })?
+        };
+        connected?;
+        log::debug!("{} device {} connected with {}", id, device, connection_string);
+        let adapter = id.to_string();
         let id = api.id;
 
         let running = Arc::new(AtomicBool::new(true));
-        let mut bus = PushBus::new();
-        let rp1210 = Rp1210 {
+        let mut bus = bus.create();
+        let frames = FrameCounter::new();
+        let mut rp1210 = Rp1210 {
+            client: None,
+            adapter,
+            device,
+            connection_string: connection_string.clone(),
+            frames: frames.clone(),
+            bus: bus.clone(),
+            address,
+            address_claim: None,
+            claim_mode: options.claim_mode,
+            keepalive: None,
+            inject_sent: false,
+            timestamps: Timestamps::default(),
+            access,
+            send_options: SendOptions::default(),
+            read_error: Default::default(),
+            last_error: last_error.clone(),
+            clock: options.clock.clone(),
+            bus_states: BusStates::new(),
+        };
+        let mut client = Client {
             api,
-            bus: Box::new(bus.clone()),
             running: running.clone(),
+            thread: None,
+            bus: bus.clone(),
+            bus_states: rp1210.bus_states.clone(),
+            address_claim: Mutex::new(None),
+            registration: Some(registration),
+            idle: Default::default(),
+            poll: (options.read_mode == ReadMode::Poll).then(|| Poll {
+                channel: channel.unwrap_or(0),
+                time_stamp_weight,
+                timestamps: Mutex::new(rp1210.timestamps.clone()),
+            }),
         };
-        std::thread::spawn(move || {
+        let mut timestamps = rp1210.timestamps.clone();
+        let read_error = rp1210.read_error.clone();
+        let on_read_error = options.on_read_error;
+        let idle_timeout = options.idle_timeout;
+        let idle = client.idle.clone();
+        let clock = options.clock.clone();
+        let bus_state_interval = options.bus_state_interval;
+        let mut bus_states = rp1210.bus_states.clone();
+        client.thread = (options.read_mode == ReadMode::Thread).then(|| std::thread::spawn(move || {
+            // read, get_error_fn, disconnect and get_hardware_status are only valid while lib is loaded
+            let _lib = lib;
             let mut buf: [u8; PACKET_SIZE] = [0; PACKET_SIZE];
             let channel = channel.unwrap_or(0);
+            let mut last_received = clock.now();
+            let mut next_status = clock.now();
             while running.load(Relaxed) {
+                if let (Some(interval), Some(get_hardware_status)) = (bus_state_interval, get_hardware_status) {
+                    if clock.now() >= next_status {
+                        next_status = clock.now() + interval;
+                        let mut status = [0; HARDWARE_STATUS_SIZE];
+                        // nBlockOnRequest 0 returns the current status rather than waiting for a change
+                        let rtn = unsafe {
+                            get_hardware_status(id, status.as_mut_ptr(), status.len() as i16, 0)
+                        };
+                        log::trace!("RP1210_GetHardwareStatus({}) = {} [{}]", id, rtn, hex(&status));
+                        let driver = format!("{} {}", device, connection_string);
+                        match ReturnCode::Status.check(rtn).ok().and_then(|_| BusState::parse(&status)) {
+                            Some(state) => bus_states.update(&driver, state),
+                            None => log::debug!("{}: no hardware status ({})", driver, rtn),
+                        }
+                    }
+                }
                 let size = unsafe { read(id, buf.as_mut_ptr(), PACKET_SIZE as i16, 0) };
-                if size > 0 {
-                    bus.push(Some(J1939Packet::new_rp1210(
+                // empty reads are polled every ms, so aren't traced
+                if size > 0 && size as usize <= PACKET_SIZE {
+                    log::trace!("RP1210_ReadMessage({}) = {} [{}]", id, size, hex(&buf[..size as usize]));
+                } else if size != 0 {
+                    log::trace!("RP1210_ReadMessage({}) = {}", id, size);
+                }
+                if size > 0 && size as usize > PACKET_SIZE {
+                    // never slice past the buffer.  Drivers should return ERR_MESSAGE_TOO_LONG instead.
+                    log::error!(
+                        "{} {}: {} byte message exceeds {} byte buffer",
+                        device, connection_string, size, PACKET_SIZE
+                    );
+                } else if size > 0 {
+                    let packet = J1939Packet::new_rp1210(
+                        false,
                         channel,
                         &buf[0..size as usize],
                         time_stamp_weight,
-                    )));
+                    );
+                    if !packet.echo() {
+                        last_received = clock.now();
+                    }
+                    bus.push(Some(timestamps.stamp(packet)));
+                    frames.add();
                 } else {
-                    if size < 0 {
-                        // read error
-                        let code = -size;
+                    if let Err(code) = ReturnCode::Size.check(size) {
                         let size = unsafe { (get_error_fn)(code, buf.as_mut_ptr()) } as usize;
                         let msg = String::from_utf8_lossy(&buf[0..size]).to_string();
                         let driver = format!("{} {} {}", id, device, connection_string);
-                        eprintln!("ERROR: {}: {}: {}", driver, code, msg,);
+                        log::error!("{}: {}: {}", driver, code, msg);
+                        *last_error.lock().unwrap() = Some(Rp1210Error {
+                            code,
+                            message: msg.clone(),
+                        });
+                        if on_read_error == OnReadError::Terminate {
+                            *read_error.lock().unwrap() = Some(Rp1210Error { code, message: msg });
+                            // ends iterators, which then report the error
+                            bus.close();
+                            break;
+                        }
                         std::thread::sleep(Duration::from_millis(250));
                     } else {
                         std::thread::sleep(Duration::from_millis(1));
                     }
                 }
+                let quiet = clock.now().saturating_duration_since(last_received);
+                if idle_timeout.is_some_and(|t| quiet >= t) {
+                    log::warn!(
+                        "{} {}: nothing received for {:?}.  Disconnecting.",
+                        device, connection_string, idle_timeout.unwrap()
+                    );
+                    // sends check idle first, so the client isn't used after this
+                    idle.store(true, Relaxed);
+                    let rtn = unsafe { disconnect(id) };
+                    log::trace!("RP1210_ClientDisconnect({}) = {}", id, rtn);
+                    running.store(false, Relaxed);
+                    bus.close();
+                    break;
+                }
                 bus.push(None)
             }
-        });
+        }));
+        if !app_packetized && !j1939::unclaimed(address) && access == Access::ReadWrite {
+            // a blocking claim may never return, so it's part of the connect timeout
+            let bus = rp1210.bus.clone();
+            let mode = options.claim_mode;
+            let (claimed, claim) = with_deadline(deadline, "Address claim", move || {
+                let claim = claim(&client.api, &*bus, address, mode);
+                (client, claim)
+            })?;
+            client = claimed;
+            let claim = claim?;
+            *client.address_claim.get_mut().unwrap() = Some(claim);
+            rp1210.address_claim = Some(claim);
+        }
+        rp1210.client = Some(Arc::new(client));
         Ok(rp1210)
     }
+
+    /// Pass only packets matching one of `filters`.  No filters passes everything.
+    pub fn apply_filters(&self, filters: &[J1939Filter]) -> Result<()> {
+        if filters.is_empty() {
+            self.api()?.send_command(CMD_SET_ALL_FILTERS_STATES_TO_PASS, &[])?;
+            return Ok(());
+        }
+        // filters are added to the pass list, so start from discarding everything
+        let api = self.api()?;
+        api.send_command(CMD_SET_ALL_FILTERS_STATES_TO_DISCARD, &[])?;
+        api.send_command(
+            CMD_SET_MESSAGE_FILTERING_FOR_J1939,
+            &filter::filter_block(filters),
+        )?;
+        Ok(())
+    }
+
+    /// Reset the adapter with RP1210_Reset_Device, for adapters that stop responding.
+    ///
+    /// The command needs a client, and is rejected unless it's the only client of the device, so this connects,
+    /// resets, and lets the reset close the connection.  Connect again afterwards with new().
+    pub fn reset(id: &str, device: i16, connection_string: &str) -> Result<()> {
+        let mut api = API::new(id)?;
+        api.client_connect(device, connection_string, false)?;
+        let result = api.send_command(CMD_RESET_DEVICE, &[]);
+        // the reset disconnected every client.  Otherwise, drop disconnects.
+        api.connected = result.is_err();
+        result
+            .map(|_| ())
+            .with_context(|| format!("Unable to reset {} device {}", id, device))
+    }
+
+    /// Outcome of the address claim made while connecting.  None if app packetized, read-only, or for the null
+    /// address (0xFE or 0xFF), which isn't claimed.
+    pub fn address_claim(&self) -> Option<AddressClaim> {
+        self.address_claim
+    }
+
+    /// The address the adapter protects for this handle, from its last claim.  RP1210 has no command to read it
+    /// back, so it's the address the claim echoed, or the one requested if the adapter doesn't echo claims.
+    /// None if nothing was claimed.
+    pub fn current_j1939_address(&self) -> Option<u8> {
+        self.address_claim.map(|c| c.claimed.unwrap_or(c.requested))
+    }
+
+    /// Release the claimed address and claim `address` instead, without reconnecting.  Sends use `address` from
+    /// then on.  Returns the claim's outcome, or an error on contention, which leaves no address claimed.
+    pub fn set_j1939_address(&mut self, address: u8) -> Result<AddressClaim> {
+        self.access.check_write()?;
+        let client = self.client()?;
+        let mut current = client.address_claim.lock().unwrap();
+        if let Some(previous) = current.take() {
+            client.api.release_address(previous.requested)?;
+        }
+        let claim = claim(&client.api, &*self.bus, address, self.claim_mode);
+        *current = claim.as_ref().ok().copied();
+        drop(current);
+        self.address_claim = claim.as_ref().ok().copied();
+        let claim = claim?;
+        self.address = address;
+        Ok(claim)
+    }
+
+    /// Read the next packet from the driver on this thread, with `ReadMode::Poll`.  None if nothing is waiting.
+    pub fn poll(&self) -> Result<Option<J1939Packet>> {
+        let client = self.client()?;
+        let poll = client
+            .poll
+            .as_ref()
+            .ok_or_else(|| anyhow!("Not polled.  Connect with ReadMode::Poll to poll."))?;
+        let api = &client.api;
+        let mut buf = vec![0u8; PACKET_SIZE];
+        let rtn = unsafe { (api.read_fn)(api.id, buf.as_mut_ptr(), PACKET_SIZE as i16, 0) };
+        if rtn != 0 {
+            log::trace!("RP1210_ReadMessage({}) = {}", api.id, rtn);
+        }
+        let size = api.verify_return(ReturnCode::Size, rtn)? as usize;
+        if size == 0 {
+            return Ok(None);
+        }
+        if size > PACKET_SIZE {
+            bail!("{} byte message exceeds {} byte buffer", size, PACKET_SIZE);
+        }
+        let packet =
+            J1939Packet::new_rp1210(false, poll.channel, &buf[..size], poll.time_stamp_weight);
+        self.frames.add();
+        Ok(Some(poll.timestamps.lock().unwrap().stamp(packet)))
+    }
+
+    /// The adapter's CAN error state last read, with a bus state interval.  None until read, or if the driver
+    /// doesn't report it.
+    pub fn bus_state(&self) -> Option<BusState> {
+        self.bus_states.get()
+    }
+
+    /// The bus state each time it's read and has changed, starting with the first read.  Ends on close().
+    pub fn iter_bus_state(&self) -> Box<dyn BusIterator<BusState>> {
+        self.bus_states.iter()
+    }
+
+    /// Why reading stopped, with `OnReadError::Terminate`.
+    pub fn read_error(&self) -> Option<Rp1210Error> {
+        self.read_error.lock().unwrap().clone()
+    }
+
+    /// Most recent error code and message the driver returned to any call made for this connection, e.g. by the
+    /// read thread or a send, for status displays.  Kept after later calls succeed.
+    pub fn last_error(&self) -> Option<Rp1210Error> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// iter(), ending with `Err(Rp1210Error)` if reading stopped on a read error.  Ends without an error on
+    /// close().
+    pub fn iter_results(&self) -> impl Iterator<Item = Result<Option<J1939Packet>>> {
+        connection::until_failure(self.bus.iter(), self.read_error.clone())
+    }
+
+    /// Pass `bytes` to RP1210_SendMessage as they are, for message formats J1939Packet doesn't model.  Returns
+    /// the driver's return code.  Nothing is injected or waited for, and the source address isn't rewritten.
+    pub fn send_raw(&self, bytes: &[u8]) -> Result<i16> {
+        self.access.check_write()?;
+        self.api()?.send_bytes(bytes, self.send_options)
+    }
+
+    /// Flags for RP1210_SendMessage, used by send(), transmit() and send_raw().  Keepalives don't block.
+    pub fn set_send_options(&mut self, options: SendOptions) {
+        self.send_options = options;
+    }
+
+    /// Time between the packets of the multi-packet (TP) messages the adapter sends for us, so the adapter paces
+    /// transfers rather than our send loop.  Only applies when the adapter does TP, i.e. not app packetized.
+    /// Rounded down to milliseconds.  Fails if the driver doesn't support the command.
+    pub fn set_interpacket_time(&self, time: Duration) -> Result<()> {
+        let millis = u32::try_from(time.as_millis()).unwrap_or(u32::MAX);
+        self.api()?
+            .send_command(CMD_SET_J1939_INTERPACKET_TIME, &millis.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Link state of a wireless adapter.  None for wired adapters, which reject the command.
+    pub fn wireless_state(&self) -> Option<WirelessState> {
+        let buf = self.api().ok()?.query_command(CMD_GET_WIRELESS_STATE, 2).ok()?;
+        WirelessState::parse(&buf)
+    }
+
+    /// Protocols the adapter supports, read from the vendor INI.  RP1210 defines no call that asks a connected
+    /// adapter for its protocols, so a firmware update isn't reflected until the vendor updates the INI.
+    /// Empty if the INI can't be read.
+    pub fn supported_protocols(&self) -> Vec<String> {
+        rp1210_parsing::supported_protocols(&self.adapter, self.device).unwrap_or_default()
+    }
+
+    /// Also push each sent packet onto this connection's bus, marked `sent()`, so iter() shows both directions.
+    /// Packets are pushed once the driver accepts them, so failed sends aren't recorded, and the copy may land
+    /// just after the adapter's echo.
+    pub fn set_inject_sent(&mut self, inject: bool) {
+        self.inject_sent = inject;
+    }
+
+    /// Time stamp received packets with the adapter's clock (the default) or the host's.  Shared by clones.
+    pub fn set_timestamp_source(&self, source: TimestampSource) {
+        self.timestamps.set_source(source);
+    }
+
+    /// Send `packet` every `interval` from a timer thread until stop_keepalive() or close().
+    /// Replaces any previous keepalive.  Echoes are not checked.
+    pub fn start_keepalive(&mut self, packet: &J1939Packet, interval: Duration) -> Result<()> {
+        self.stop_keepalive();
+        self.access.check_write()?;
+        let api = self.api()?;
+        // send_fn is only valid while lib is loaded
+        let lib = api.lib.clone();
+        let send = *api.send_fn;
+        let id = api.id;
+        let idle = self.client()?.idle.clone();
+        let buf = packet.packet.data.clone();
+        let size = rp1210_error::message_size(&buf)?;
+        self.keepalive = Some(Keepalive::start(interval, move || {
+            let _lib = &lib;
+            if idle.load(Relaxed) {
+                bail!("Disconnected after the idle timeout");
+            }
+            let rtn = unsafe { send(id, buf.as_ptr(), size, 0, 0) };
+            if ReturnCode::Status.check(rtn).is_err() {
+                bail!("RP1210_SendMessage returned {}", rtn);
+            }
+            Ok(())
+        })?);
+        Ok(())
+    }
+
+    pub fn stop_keepalive(&mut self) {
+        self.keepalive.take();
+    }
+
+    fn client(&self) -> Result<&Client> {
+        match &self.client {
+            Some(c) if c.idle.load(Relaxed) => bail!("Disconnected after the idle timeout"),
+            Some(c) => Ok(c),
+            None => bail!("Connection closed"),
+        }
+    }
+
+    fn api(&self) -> Result<&API> {
+        Ok(&self.client()?.api)
+    }
+
+    fn inject(&mut self, packet: &J1939Packet) {
+        if self.inject_sent {
+            self.bus.push(Some(packet.as_sent()));
+        }
+    }
+}
+
+/// Run `f`, a driver call that may block indefinitely, failing if it hasn't returned by `deadline`.  On timeout
+/// `f` keeps running on its thread, and drops what it owns when the driver finally returns.
+fn with_deadline<T: Send + 'static>(
+    deadline: Option<Instant>,
+    what: &str,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T> {
+    let Some(deadline) = deadline else {
+        return Ok(f());
+    };
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name(what.to_string())
+        .spawn(move || {
+            let _ = tx.send(f());
+        })?;
+    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .map_err(|e| match e {
+            mpsc::RecvTimeoutError::Timeout => anyhow!(
+                "{} didn't complete within the connect timeout.  Is the vehicle powered?",
+                what
+            ),
+            mpsc::RecvTimeoutError::Disconnected => anyhow!("{} panicked", what),
+        })
+}
+
+/// Claim the address, watching the bus for another node claiming the same address and for the adapter's
+/// echo of its own claim.  The read thread must be running.
+fn claim(api: &API, bus: &dyn Bus<J1939Packet>, address: u8, mode: ClaimMode) -> Result<AddressClaim> {
+    let claims = bus.iter();
+    let result = api.protect_address(address, mode);
+    // claims that arrived during a blocking claim are already queued
+    let wait = match mode {
+        ClaimMode::Timeout(timeout) => timeout,
+        ClaimMode::BlockUntilDone | ClaimMode::NonBlocking => Duration::from_millis(50),
+    };
+    let end = Instant::now() + wait;
+    let mut claim = AddressClaim::from_packets(address, &j1939::TOOL_NAME, []);
+    for p in claims.take_while(|_| Instant::now() < end).flatten() {
+        claim.add(&j1939::TOOL_NAME, &p);
+        if matches!(mode, ClaimMode::Timeout(_)) && claim.claimed.is_some() {
+            break;
+        }
+    }
+    let contention = if claim.contention { " (contention)" } else { "" };
+    result.with_context(|| format!("Unable to claim address {:02X}{}", address, contention))?;
+    if let ClaimMode::Timeout(timeout) = mode {
+        match claim.claimed {
+            None => bail!(
+                "Address claim of {:02X} didn't complete within {:?}{}",
+                address, timeout, contention
+            ),
+            Some(j1939::NULL_ADDRESS) => {
+                bail!("Unable to claim address {:02X}{}", address, contention)
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(claim)
 }
 
 impl Connection for Rp1210 {
-    /// Send packet and return packet echoed back from adapter
+    /// Send packet and return the adapter's echo of it, going by the echo byte of received messages
     fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet> {
+        self.access.check_write()?;
+        let packet = &j1939::outgoing(packet, self.address);
         let end = Instant::now() + Duration::from_secs(2);
         let stream = self.bus.iter().take_while(|_| Instant::now() < end);
-        let sent = self.api.send(packet);
-        // FIXME needs better error handling
-        sent.map(|_| stream.flat_map(|o|o).find(move |p| p.data() == packet.data()).unwrap())
+        self.api()?.send(packet, self.send_options)?;
+        // only once the driver has accepted it, so failed sends aren't recorded
+        self.inject(packet);
+        if self.send_options.blocking {
+            // the driver has confirmed it's on the bus
+            return Ok(packet.clone());
+        }
+        if self.client()?.poll.is_some() {
+            // the echo goes to poll()
+            return Ok(packet.clone());
+        }
+        stream
+            .flatten()
+            .find(|p| p.echo_of(packet))
+            .ok_or_else(|| anyhow!("No echo of {} within 2 s", packet))
+    }
+
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<()> {
+        self.access.check_write()?;
+        let packet = &j1939::outgoing(packet, self.address);
+        self.api()?.send(packet, self.send_options)?;
+        self.inject(packet);
+        Ok(())
     }
 
-    fn iter(&self) -> Box<dyn Iterator<Item = Option<J1939Packet>> + Send + Sync> {
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
         self.bus.iter()
     }
+
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter_filtered(filter)
+    }
+
+    fn is_running(&self) -> bool {
+        self.client.as_ref().is_some_and(|c| c.is_running()) && self.read_error().is_none()
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            adapter: self.adapter.clone(),
+            device: self.device,
+            connection_string: self.connection_string.clone(),
+            address: self.address,
+            address_claim: self.address_claim,
+            baud: self.api().and_then(|api| api.connection_speed()).ok(),
+            driver_version: self.api().and_then(|api| api.driver_version()).ok(),
+            frames: self.frames.frames(),
+            frames_per_second: self.frames.per_second(),
+        }
+    }
+
+    /// Stop this handle's keepalive.  The last clone stops the read thread, releases the address and
+    /// disconnects.
+    fn close(&mut self) -> Result<()> {
+        self.stop_keepalive();
+        match self.client.take().and_then(Arc::into_inner) {
+            Some(mut client) => client.close(),
+            None => Ok(()),
+        }
+    }
+
+    fn clone_connection(&self) -> Box<dyn Connection> {
+        Box::new(self.clone())
+    }
+}
+
+/// J1708 connection, for J1587 messages.  Uses the same read thread design as `Rp1210`.
+pub struct J1708 {
+    api: API,
+    bus: Box<dyn Bus<J1587Message>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    registration: Option<Registration>,
+}
+
+impl J1708 {
+    pub fn new(id: &str, device: i16, bus: BusKind) -> Result<J1708> {
+        let registration = registry::register(id, device, None);
+        let mut api = API::new(id)?;
+        let lib = api.lib.clone();
+        let read = *api.read_fn;
+        api.client_connect(device, "J1708", false)?;
+        let client = api.id;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let bus = bus.create();
+        let thread = {
+            let running = running.clone();
+            let mut bus = bus.clone();
+            std::thread::spawn(move || {
+                // read is only valid while lib is loaded
+                let _lib = lib;
+                let mut buf: [u8; PACKET_SIZE] = [0; PACKET_SIZE];
+                while running.load(Relaxed) {
+                    let size = unsafe { read(client, buf.as_mut_ptr(), PACKET_SIZE as i16, 0) };
+                    if size > 0 && (size as usize) <= PACKET_SIZE {
+                        if let Some(message) = J1587Message::from_rp1210(&buf[0..size as usize]) {
+                            bus.push(Some(message));
+                        }
+                    } else if let Err(code) = ReturnCode::Size.check(size) {
+                        log::error!("J1708 {} read error {}", device, code);
+                        std::thread::sleep(Duration::from_millis(250));
+                    } else {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    bus.push(None)
+                }
+            })
+        };
+        Ok(J1708 {
+            api,
+            bus,
+            running,
+            thread: Some(thread),
+            registration: Some(registration),
+        })
+    }
+
+    /// Send a message.  The echo is in iter().
+    pub fn send(&mut self, message: &J1587Message) -> Result<()> {
+        self.api
+            .send_bytes(&message.to_rp1210_tx(), SendOptions::default())
+            .map(|_| ())
+    }
+
+    pub fn iter(&self) -> Box<dyn BusIterator<J1587Message>> {
+        self.bus.iter()
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.running.store(false, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().map_err(|_| anyhow!("J1708 read thread panicked"))?;
+        }
+        self.bus.close();
+        self.registration.take();
+        self.api.disconnect()
+    }
+}
+
+impl Drop for J1708 {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drop connections while the read thread is reading.  Set RP1210_ADAPTER and RP1210_DEVICE.
+    #[test]
+    #[ignore = "requires an RP1210 adapter"]
+    fn drop_while_reading() -> Result<()> {
+        let adapter = std::env::var("RP1210_ADAPTER")?;
+        let device = std::env::var("RP1210_DEVICE")?.parse()?;
+        for _ in 0..20 {
+            let rp1210 = Rp1210::new(
+                &adapter,
+                device,
+                None,
+                "J1939:Baud=Auto",
+                0xF9,
+                true,
+                BusKind::PushBus,
+            )?;
+            let consumer = {
+                let packets = rp1210.iter();
+                std::thread::spawn(move || packets.count())
+            };
+            std::thread::sleep(Duration::from_millis(50));
+            drop(rp1210);
+            consumer.join().unwrap();
+        }
+        Ok(())
+    }
 }
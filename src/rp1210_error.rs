@@ -0,0 +1,101 @@
+use std::fmt::Display;
+
+pub const ERR_CLIENT_ALREADY_CONNECTED: i16 = 130;
+pub const ERR_DEVICE_IN_USE: i16 = 135;
+pub const ERR_ADDRESS_CLAIM_FAILED: i16 = 146;
+pub const ERR_ADDRESS_NEVER_CLAIMED: i16 = 147;
+
+/// Largest client id RP1210_ClientConnect returns.  Larger values are error codes.
+pub const MAX_CLIENT_ID: i16 = 127;
+
+/// Largest message RP1210_SendMessage takes: the J1939 transmit header (6 bytes) and the largest TP payload
+/// (1785 bytes).
+pub const MAX_MESSAGE_SIZE: usize = 1791;
+
+/// How an RP1210 function's return value reads, which depends on the function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnCode {
+    /// RP1210_ClientConnect: the client id, 0 to 127, or an error code above 127.
+    ClientId,
+    /// RP1210_SendCommand, RP1210_SendMessage, RP1210_ClientDisconnect...: 0 on success, or an error code above
+    /// 127.  1 to 127 aren't errors, and are passed on as informational.
+    Status,
+    /// RP1210_ReadMessage: the number of bytes read, 0 if there was nothing to read, or the error code negated.
+    Size,
+}
+
+impl ReturnCode {
+    /// `rtn` if it isn't an error, otherwise the error code.  Negative values are negated error codes for every
+    /// function, since some drivers return them that way.
+    pub fn check(self, rtn: i16) -> Result<i16, i16> {
+        match self {
+            _ if rtn < 0 => Err(rtn.saturating_abs()),
+            ReturnCode::ClientId | ReturnCode::Status if rtn > MAX_CLIENT_ID => Err(rtn),
+            _ => Ok(rtn),
+        }
+    }
+}
+
+/// Length of `buf` for RP1210_SendMessage.  Fails rather than wrapping if it exceeds `MAX_MESSAGE_SIZE`.
+pub fn message_size(buf: &[u8]) -> anyhow::Result<i16> {
+    if buf.len() > MAX_MESSAGE_SIZE {
+        anyhow::bail!(
+            "{} byte message exceeds the RP1210 maximum of {} bytes",
+            buf.len(),
+            MAX_MESSAGE_SIZE
+        );
+    }
+    Ok(buf.len() as i16)
+}
+
+/// Error code and message returned by an RP1210 function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rp1210Error {
+    pub code: i16,
+    pub message: String,
+}
+
+impl Display for Rp1210Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "code: {} msg: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Rp1210Error {}
+
+/// RP1210 error code of `error`, if it came from the driver.
+pub fn code(error: &anyhow::Error) -> Option<i16> {
+    error.downcast_ref::<Rp1210Error>().map(|e| e.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn return_code_ranges() {
+        assert_eq!(Ok(0), ReturnCode::ClientId.check(0));
+        assert_eq!(Ok(127), ReturnCode::ClientId.check(127));
+        assert_eq!(Err(128), ReturnCode::ClientId.check(128));
+        assert_eq!(Err(ERR_DEVICE_IN_USE), ReturnCode::ClientId.check(ERR_DEVICE_IN_USE));
+
+        assert_eq!(Ok(0), ReturnCode::Status.check(0));
+        assert_eq!(Ok(1), ReturnCode::Status.check(1));
+        assert_eq!(Err(128), ReturnCode::Status.check(128));
+        assert_eq!(Err(1), ReturnCode::Status.check(-1));
+
+        assert_eq!(Ok(0), ReturnCode::Size.check(0));
+        assert_eq!(Ok(4_108), ReturnCode::Size.check(4_108));
+        assert_eq!(Err(ERR_DEVICE_IN_USE), ReturnCode::Size.check(-ERR_DEVICE_IN_USE));
+        assert_eq!(Err(i16::MAX), ReturnCode::Size.check(i16::MIN));
+    }
+
+    #[test]
+    fn message_sizes() -> anyhow::Result<()> {
+        assert_eq!(1791, message_size(&[0; MAX_MESSAGE_SIZE])?);
+        assert!(message_size(&[0; MAX_MESSAGE_SIZE + 1]).is_err());
+        // would have wrapped to a negative length
+        assert!(message_size(&[0; 40_000]).is_err());
+        Ok(())
+    }
+}
@@ -1,18 +1,44 @@
 use std::fmt::Display;
 
 use anyhow::*;
+use serde::Serialize;
 
-#[derive(Debug)]
+use crate::connection::{Connection, ConnectionFactory};
+use crate::ConnectionDescriptor;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Default)]
 pub struct Rp1210Device {
     pub id: i16,
     pub name: String,
     pub description: String,
+    /// ProtocolSpeed of the listed protocol, e.g. ["250", "500", "Auto"].  Empty if the INI doesn't say.
+    pub speeds: Vec<String>,
+    /// The adapter can detect the bus speed, so `Baud=Auto` works: RP1210C CANAutoBaud, or Auto in the speeds.
+    pub auto_baud: bool,
+    /// RP1210C AutoDetectCapable: the driver can report whether the device is connected before connecting.
+    pub auto_detect: bool,
+    /// RP1210C J1939Addresses: the number of addresses the adapter can claim.  None if the INI doesn't say.
+    pub j1939_addresses: Option<u16>,
+    /// DeviceParams, passed through as written.
+    pub params: Option<String>,
+    /// RP1210C MultiJ1939Channels (or MultiCANChannels...) for the listed protocol.  None if the INI doesn't say.
+    pub channel_count: Option<u8>,
 }
-#[derive(Debug)]
+
+impl Rp1210Device {
+    /// Channels to connect with, from 1.  Devices that don't list channels have one.
+    pub fn channels(&self) -> Vec<u8> {
+        (1..=self.channel_count.unwrap_or(1)).collect()
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Rp1210Product {
     pub id: String,
     pub description: String,
     pub devices: Vec<Rp1210Device>,
+    /// Why the product's own INI couldn't be read, e.g. listed in RP121032.ini but not installed.  The
+    /// description and devices are then empty.
+    pub error: Option<String>,
 }
 
 impl Display for Rp1210Device {
@@ -23,6 +49,9 @@ impl Display for Rp1210Device {
 impl Display for Rp1210Product {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{} {}", self.id, self.description)?;
+        if let Some(error) = &self.error {
+            writeln!(f, "{}", error)?;
+        }
         for d in &self.devices {
             writeln!(f, "{}", d)?;
         }
@@ -30,114 +59,424 @@ impl Display for Rp1210Product {
     }
 }
 
+/// Set to list `mock_products()` even when RP1210 drivers are installed.
+pub const MOCK_PRODUCTS_ENV: &str = "RP1210_MOCK_PRODUCTS";
+
+/// Products listed when there is no RP121032.ini (e.g. off Windows) or MOCK_PRODUCTS_ENV is set.
+/// Off Windows, the simulator connects to any of these.
+pub fn mock_products() -> Vec<Rp1210Product> {
+    vec![
+        Rp1210Product {
+            id: "SIM".to_string(),
+            description: "Simulated Adapter 1".to_string(),
+            devices: vec![Rp1210Device {
+                id: 1,
+                name: "SIM".to_string(),
+                description: "Simulated Device".to_string(),
+                ..sim_device()
+            }],
+            error: None,
+        },
+        Rp1210Product {
+            id: "SIM".to_string(),
+            description: "Simulated Adapter 2".to_string(),
+            devices: vec![Rp1210Device {
+                id: 2,
+                name: "SIM".to_string(),
+                description: "Simulated Device 2".to_string(),
+                ..sim_device()
+            }],
+            error: None,
+        },
+        Rp1210Product {
+            id: "SIM".to_string(),
+            description: "Simulated Adapter 3".to_string(),
+            devices: vec![Rp1210Device {
+                id: 3,
+                name: "SIM".to_string(),
+                description: "Simulated Device 3".to_string(),
+                ..sim_device()
+            }],
+            error: None,
+        },
+    ]
+}
+
+/// Capabilities of a simulated device.
+fn sim_device() -> Rp1210Device {
+    Rp1210Device {
+        speeds: ["250", "500", "Auto"].map(String::from).to_vec(),
+        auto_baud: true,
+        auto_detect: false,
+        j1939_addresses: Some(1),
+        channel_count: Some(2),
+        ..Default::default()
+    }
+}
+
+/// Products with their J1939 devices.
 pub fn list_all_products() -> Result<Vec<Rp1210Product>> {
-    let start = std::time::Instant::now();
-    let load_from_file = ini::Ini::load_from_file("c:\\Windows\\RP121032.ini");
-    if load_from_file.is_err() {
-        // don't fail on linux
-        return Ok(vec![
-            Rp1210Product {
-                id: "SIM".to_string(),
-                description: "Simulated Adapter 1".to_string(),
-                devices: vec![Rp1210Device {
-                    id: 1,
-                    name: "SIM".to_string(),
-                    description: "Simulated Device".to_string(),
-                }],
-            },
-            Rp1210Product {
-                id: "SIM".to_string(),
-                description: "Simulated Adapter 2".to_string(),
-                devices: vec![Rp1210Device {
-                    id: 2,
-                    name: "SIM".to_string(),
-                    description: "Simulated Device 2".to_string(),
-                }],
-            },
-            Rp1210Product {
-                id: "SIM".to_string(),
-                description: "Simulated Adapter 3".to_string(),
-                devices: vec![Rp1210Device {
-                    id: 3,
-                    name: "SIM".to_string(),
-                    description: "Simulated Device 3".to_string(),
-                }],
-            },
-        ]);
-    }
-    let rtn = Ok(load_from_file?
-        .get_from(Some("RP1210Support"), "APIImplementations")
+    list_all_products_for("J1939")
+}
+
+/// The INI listing the installed RP1210 products.
+const RP121032_INI: &str = "c:\\Windows\\RP121032.ini";
+
+/// Product ids listed in RP121032.ini, e.g. ["NULN2R32", "PEAKRP32"].
+fn product_ids(ini: &ini::Ini) -> Vec<String> {
+    ini.get_from(Some("RP1210Support"), "APIImplementations")
         .unwrap_or("")
         .split(',')
-        .map(|s| {
-            let (description, devices) = list_devices_for_prod(s).unwrap_or_default();
-            Rp1210Product {
-                id: s.to_string(),
-                description: description.to_string(),
-                devices,
-            }
-        })
-        .collect());
-    println!("RP1210 INI parsing in {} ms", start.elapsed().as_millis());
-    rtn
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// File name and contents of RP121032.ini and each product's INI, for attaching to support tickets.  INIs that
+/// can't be read are left out, so this is empty without RP1210 drivers.  Contents that aren't UTF-8 (INIs are
+/// often Windows-1252) have the invalid bytes replaced.
+pub fn collect_support_bundle() -> Vec<(String, String)> {
+    let read = |path: &str| {
+        let contents = std::fs::read(path).ok()?;
+        let name = path.rsplit('\\').next().unwrap_or(path).to_string();
+        Some((name, String::from_utf8_lossy(&contents).to_string()))
+    };
+    let Some(index) = read(RP121032_INI) else {
+        return Vec::new();
+    };
+    let ids = ini::Ini::load_from_str(&index.1)
+        .map(|ini| product_ids(&ini))
+        .unwrap_or_default();
+    std::iter::once(Some(index))
+        .chain(ids.iter().map(|id| read(&product_ini(id))))
+        .flatten()
+        .collect()
+}
+
+/// Path of product `id`'s INI.
+fn product_ini(id: &str) -> String {
+    format!("c:\\Windows\\{}.ini", id)
 }
 
-fn list_devices_for_prod(id: &str) -> Result<(String, Vec<Rp1210Device>)> {
+/// Products with the devices that support `protocol`, e.g. "J1708".
+pub fn list_all_products_for(protocol: &str) -> Result<Vec<Rp1210Product>> {
+    if std::env::var_os(MOCK_PRODUCTS_ENV).is_some() {
+        return Ok(mock_products());
+    }
+    // don't fail on linux
+    Ok(list_products_from(&WindowsInis, protocol).unwrap_or_else(|_| mock_products()))
+}
+
+/// Where RP1210 INIs are read from.  `WindowsInis` is the drivers' own; `InMemoryInis` supplies fixtures, e.g.
+/// for tests or to list another machine's drivers off Windows.
+pub trait IniSource {
+    /// Contents of INI `name`, e.g. "RP121032.ini" or "NULN2R32.ini".
+    fn read(&self, name: &str) -> Result<String>;
+}
+
+/// INIs installed in c:\Windows by RP1210 drivers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowsInis;
+
+impl IniSource for WindowsInis {
+    fn read(&self, name: &str) -> Result<String> {
+        let path = format!("c:\\Windows\\{}", name);
+        std::fs::read_to_string(&path).with_context(|| path)
+    }
+}
+
+/// INIs by name.  Names are case insensitive, as on Windows.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryInis {
+    files: std::collections::HashMap<String, String>,
+}
+
+impl InMemoryInis {
+    pub fn insert(&mut self, name: &str, contents: &str) -> &mut Self {
+        self.files.insert(name.to_lowercase(), contents.to_string());
+        self
+    }
+}
+
+impl IniSource for InMemoryInis {
+    fn read(&self, name: &str) -> Result<String> {
+        self.files
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or_else(|| anyhow!("No {}", name))
+    }
+}
+
+/// INI `name` of `source`.
+fn load(source: &dyn IniSource, name: &str) -> Result<ini::Ini> {
+    let contents = source.read(name)?;
+    // ignore a UTF-8 byte order mark, as Ini::load_from_file() does
+    Ok(ini::Ini::load_from_str(contents.trim_start_matches('\u{feff}'))?)
+}
+
+/// Products listed in `source`'s RP121032.ini, with the devices that support `protocol`.  Fails if there is no
+/// RP121032.ini.
+pub fn list_products_from(source: &dyn IniSource, protocol: &str) -> Result<Vec<Rp1210Product>> {
     let start = std::time::Instant::now();
-    let ini = ini::Ini::load_from_file(&format!("c:\\Windows\\{}.ini", id))?;
+    let index = load(source, "RP121032.ini")?;
+    let rtn = product_ids(&index)
+        .into_iter()
+        .map(|id| product(source, id, protocol))
+        .collect();
+    log::debug!("RP1210 INI parsing in {} ms", start.elapsed().as_millis());
+    Ok(rtn)
+}
+
+/// Product `id` from its INI.  Listed with the error if the INI can't be read, rather than left out.
+fn product(source: &dyn IniSource, id: String, protocol: &str) -> Rp1210Product {
+    let (description, devices, error) = match list_devices_for_prod(source, &id, protocol) {
+        Result::Ok((description, devices)) => (description, devices, None),
+        Err(e) => {
+            log::warn!("{:#}", e);
+            (String::new(), Vec::new(), Some(format!("{:#}", e)))
+        }
+    };
+    Rp1210Product {
+        id,
+        description,
+        devices,
+        error,
+    }
+}
+
+fn list_devices_for_prod(
+    source: &dyn IniSource,
+    id: &str,
+    protocol: &str,
+) -> Result<(String, Vec<Rp1210Device>)> {
+    let start = std::time::Instant::now();
+    let ini = load(source, &format!("{}.ini", id))
+        .with_context(|| format!("{} is listed in RP121032.ini, but {}.ini can't be read", id, id))?;
+    let rtn = devices_for_protocol(&ini, protocol);
+    log::debug!("  {}.ini parsing in {} ms", id, start.elapsed().as_millis());
+    let description = ini
+        .section(Some("VendorInformation"))
+        .and_then(|s|s.get("Name"))
+        .unwrap_or_default()
+        .to_string();
+    Ok((description, rtn))
+}
+
+/// Factory for the first installed J1939 device, with the default connection string and source address.
+/// Returns None if no adapters are installed.
+pub fn first_available() -> Option<Box<dyn ConnectionFactory>> {
+    list_all_products()
+        .ok()?
+        .iter()
+        .find_map(|p| p.devices.iter().find(|d| d.id >= 0).map(|d| (p, d)))
+        .and_then(|(p, d)| descriptor(&p.id, d.id).ok())
+        .map(|d| Box::new(d) as Box<dyn ConnectionFactory>)
+}
+
+/// Command line defaults for everything but the adapter and device.
+fn descriptor(product_id: &str, device: i16) -> Result<ConnectionDescriptor> {
+    ConnectionDescriptor::builder(product_id, device).build()
+}
+
+/// Connect to the device of `product_id` whose name or description contains `device_name`
+/// (case insensitive), e.g. `connect_by_name("NULN2R32", "USB-Link 2")`.
+pub fn connect_by_name(product_id: &str, device_name: &str) -> Result<Box<dyn Connection>> {
+    let products = list_all_products()?;
+    let device = find_device(&products, product_id, device_name)?;
+    descriptor(product_id, device.id)?.connect()
+}
+
+/// An exact match is preferred over substring matches.  Fails if there is no match, or more than one.
+fn find_device<'a>(
+    products: &'a [Rp1210Product],
+    product_id: &str,
+    device_name: &str,
+) -> Result<&'a Rp1210Device> {
+    let devices: Vec<&Rp1210Device> = products
+        .iter()
+        .filter(|p| p.id.eq_ignore_ascii_case(product_id))
+        .flat_map(|p| &p.devices)
+        .collect();
+    if devices.is_empty() {
+        bail!("No J1939 devices for adapter {}", product_id);
+    }
+    let name = device_name.to_lowercase();
+    let texts = |d: &Rp1210Device| [d.name.to_lowercase(), d.description.to_lowercase()];
+    let exact: Vec<&Rp1210Device> = devices
+        .iter()
+        .copied()
+        .filter(|d| texts(d).contains(&name))
+        .collect();
+    if let [device] = exact[..] {
+        return Ok(device);
+    }
+    let matches: Vec<&Rp1210Device> = devices
+        .iter()
+        .copied()
+        .filter(|d| texts(d).iter().any(|t| t.contains(&name)))
+        .collect();
+    let list = |devices: &[&Rp1210Device]| {
+        devices
+            .iter()
+            .map(|d| format!("\n  {}", d))
+            .collect::<String>()
+    };
+    match matches[..] {
+        [device] => Ok(device),
+        [] => bail!(
+            "No {} device matches \"{}\".  Devices:{}",
+            product_id,
+            device_name,
+            list(&devices)
+        ),
+        _ => bail!(
+            "\"{}\" matches more than one {} device:{}",
+            device_name,
+            product_id,
+            list(&matches)
+        ),
+    }
+}
 
-    // find device IDs for J1939
-    let j1939_devices: Vec<&str> = ini
+/// Devices of `ini` that support `protocol`, with their capabilities.
+fn devices_for_protocol(ini: &ini::Ini, protocol: &str) -> Vec<Rp1210Device> {
+    // device IDs for the protocol, with the protocol's speeds
+    let protocol_devices: Vec<(&str, Vec<String>)> = ini
         .iter()
-        // find J1939 protocol description
+        // find protocol description
         .filter(|(section, properties)| {
             section.unwrap_or("").starts_with("ProtocolInformation")
-                && properties.get("ProtocolString") == Some("J1939")
+                && properties.get("ProtocolString") == Some(protocol)
         })
-        // which device ids support J1939?
+        // which device ids support the protocol?
         .flat_map(|(_, properties)| {
+            let speeds: Vec<String> = properties
+                .get("ProtocolSpeed")
+                .map_or(vec![], |s| s.split(',').map(|s| s.trim().to_string()).collect());
             properties
                 .get("Devices")
-                .map_or(vec![], |s| s.split(',').collect())
+                .map_or(vec![], |s| s.split(',').map(|id| id.trim()).collect())
+                .into_iter()
+                .map(move |id| (id, speeds.clone()))
         })
         .collect();
 
+    let vendor = ini.section(Some("VendorInformation"));
+    let flag = |key: &str| {
+        vendor
+            .and_then(|v| v.get(key))
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("TRUE") || v.trim() == "1")
+    };
+    let can_auto_baud = flag("CANAutoBaud");
+    let auto_detect = flag("AutoDetectCapable");
+    let j1939_addresses = vendor
+        .and_then(|v| v.get("J1939Addresses"))
+        .and_then(|v| v.trim().parse().ok());
+    let channels_key = format!("Multi{}Channels", protocol);
+
     // find the specified devices
-    let rtn = ini
-        .iter()
+    ini.iter()
+        .filter(|(section, _)| section.is_some_and(|n| n.starts_with("DeviceInformation")))
+        .filter_map(|(_, properties)| {
+            let id = properties.get("DeviceID")?.trim();
+            let (_, speeds) = protocol_devices.iter().find(|(d, _)| *d == id)?;
+            Some(Rp1210Device {
+                id: id.parse().unwrap_or(-1),
+                name: properties
+                    .get("DeviceName")
+                    .unwrap_or("Unknown")
+                    .to_string(),
+                description: properties
+                    .get("DeviceDescription")
+                    .unwrap_or("Unknown")
+                    .to_string(),
+                speeds: speeds.clone(),
+                auto_baud: can_auto_baud || speeds.iter().any(|s| s.eq_ignore_ascii_case("Auto")),
+                auto_detect,
+                j1939_addresses,
+                params: properties.get("DeviceParams").map(|s| s.to_string()),
+                channel_count: properties
+                    .get(&channels_key)
+                    .and_then(|v| v.trim().parse().ok()),
+            })
+        })
+        .collect()
+}
+
+/// Device `device` of product `product_id`, with its `protocol` capabilities.  None if it isn't listed.
+fn device_for(product_id: &str, device: i16, protocol: &str) -> Result<Option<Rp1210Device>> {
+    Ok(list_all_products_for(protocol)?
+        .into_iter()
+        .filter(|p| p.id.eq_ignore_ascii_case(product_id))
+        .flat_map(|p| p.devices)
+        .find(|d| d.id == device))
+}
+
+/// Fails if `device` of product `product_id` doesn't list `baud` among its `protocol` speeds.  Devices whose INI
+/// doesn't list speeds aren't checked.
+pub fn check_baud(product_id: &str, device: i16, protocol: &str, baud: &str) -> Result<()> {
+    let Some(device) = device_for(product_id, device, protocol)? else {
+        return Ok(());
+    };
+    let supported = if baud.eq_ignore_ascii_case("Auto") {
+        device.auto_baud
+    } else {
+        device.speeds.iter().any(|s| s.eq_ignore_ascii_case(baud))
+    };
+    if !supported && !device.speeds.is_empty() {
+        bail!(
+            "{} {} doesn't support {} Baud={}.  Supported: {}",
+            product_id,
+            device.id,
+            protocol,
+            baud,
+            device.speeds.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Fails if `device` of product `product_id` doesn't have `protocol` channel `channel`.  Devices whose INI doesn't
+/// list channels aren't checked.
+pub fn check_channel(product_id: &str, device: i16, protocol: &str, channel: u8) -> Result<()> {
+    let Some(device) = device_for(product_id, device, protocol)? else {
+        return Ok(());
+    };
+    if device.channel_count.is_some() && !device.channels().contains(&channel) {
+        bail!(
+            "{} {} has no {} channel {}.  Channels: {:?}",
+            product_id,
+            device.id,
+            protocol,
+            channel,
+            device.channels()
+        );
+    }
+    Ok(())
+}
+
+/// Protocols the INI lists for `device` of product `id`, e.g. ["J1939", "J1708"].
+pub fn supported_protocols(id: &str, device: i16) -> Result<Vec<String>> {
+    let ini = load(&WindowsInis, &format!("{}.ini", id))?;
+    Ok(protocols_for_device(&ini, device))
+}
+
+fn protocols_for_device(ini: &ini::Ini, device: i16) -> Vec<String> {
+    let device = device.to_string();
+    ini.iter()
         .filter(|(section, properties)| {
-            section
-                .map(|n| n.starts_with("DeviceInformation"))
-                .unwrap_or(false)
+            section.unwrap_or("").starts_with("ProtocolInformation")
                 && properties
-                    .get("DeviceID")
-                    .map(|id| j1939_devices.contains(&id))
-                    .unwrap_or(false)
-        })
-        .map(|(_, properties)| Rp1210Device {
-            id: properties.get("DeviceID").unwrap_or("0").parse().unwrap_or(-1),
-            name: properties
-                .get("DeviceName")
-                .unwrap_or("Unknown")
-                .to_string(),
-            description: properties
-                .get("DeviceDescription")
-                .unwrap_or("Unknown")
-                .to_string(),
+                    .get("Devices")
+                    .is_some_and(|s| s.split(',').any(|id| id.trim() == device))
         })
-        .collect();
-    println!("  {}.ini parsing in {} ms", id, start.elapsed().as_millis());
-    let description = ini
-        .section(Some("VendorInformation"))
-        .and_then(|s|s.get("Name"))
-        .unwrap_or_default()
-        .to_string();
-    Ok((description, rtn))
+        .filter_map(|(_, properties)| properties.get("ProtocolString"))
+        .map(|s| s.to_string())
+        .collect()
 }
 
 #[allow(dead_code)]
 pub fn time_stamp_weight(id: &str) -> Result<f64> {
-    let ini = ini::Ini::load_from_file(&format!("c:\\Windows\\{}.ini", id))?;
+    let ini = load(&WindowsInis, &format!("{}.ini", id))?;
     Ok(ini
         .get_from_or::<&str>(Some("VendorInformation"), "TimeStampWeight", "1")
         .parse()?)
@@ -148,7 +487,150 @@ mod tests {
 
     #[test]
     fn simple() -> Result<(), Error> {
-        list_all_products()?;
+        let products = list_all_products()?;
+        if cfg!(not(target_os = "windows")) {
+            assert_eq!(mock_products(), products);
+        }
         Ok(())
     }
+
+    #[test]
+    fn mock() {
+        let products = mock_products();
+        assert_eq!(3, products.len());
+        assert_eq!(
+            vec![1, 2, 3],
+            products
+                .iter()
+                .flat_map(|p| p.devices.iter().map(|d| d.id))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn find_device_by_name() -> Result<(), Error> {
+        let products = [Rp1210Product {
+            id: "NULN2R32".into(),
+            description: "NEXIQ Technologies USB-Link 2".into(),
+            devices: vec![
+                Rp1210Device {
+                    id: 1,
+                    name: "USB-Link 2".into(),
+                    description: "USB-Link 2, USB".into(),
+                    ..Default::default()
+                },
+                Rp1210Device {
+                    id: 2,
+                    name: "USB-Link 2 BT".into(),
+                    description: "USB-Link 2, Bluetooth".into(),
+                    ..Default::default()
+                },
+            ],
+            error: None,
+        }];
+        assert_eq!(2, find_device(&products, "NULN2R32", "bluetooth")?.id);
+        // exact match wins over substring matches
+        assert_eq!(1, find_device(&products, "nuln2r32", "USB-Link 2")?.id);
+        let ambiguous = find_device(&products, "NULN2R32", "link").unwrap_err();
+        assert!(ambiguous.to_string().contains("USB-Link 2, Bluetooth"));
+        assert!(find_device(&products, "NULN2R32", "wifi").is_err());
+        assert!(find_device(&products, "DGDPA5MA", "USB").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn support_bundle() -> Result<(), Error> {
+        let ini = ini::Ini::load_from_str("[RP1210Support]\nAPIImplementations=NULN2R32,PEAKRP32\n")?;
+        assert_eq!(vec!["NULN2R32", "PEAKRP32"], product_ids(&ini));
+        if cfg!(not(target_os = "windows")) {
+            assert!(collect_support_bundle().is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn missing_product_ini() {
+        let product = product(&WindowsInis, "NOTINSTALLED".into(), "J1939");
+        assert!(product.devices.is_empty() && product.description.is_empty());
+        let error = product.error.unwrap();
+        assert!(error.starts_with("NOTINSTALLED is listed in RP121032.ini, but"));
+    }
+
+    #[test]
+    fn products_from_fixtures() -> Result<(), Error> {
+        let mut inis = InMemoryInis::default();
+        inis.insert("RP121032.INI", "[RP1210Support]\nAPIImplementations=VENDOR32,MISSING32\n")
+            .insert(
+                "vendor32.ini",
+                "\u{feff}[VendorInformation]\nName=Vendor Adapter\n\
+                 [DeviceInformation1]\nDeviceID=1\nDeviceName=USB\nDeviceDescription=USB Device\n\
+                 [ProtocolInformation100]\nProtocolString=J1939\nProtocolSpeed=250,500\nDevices=1\n",
+            );
+        let products = list_products_from(&inis, "J1939")?;
+        assert_eq!(2, products.len());
+        assert_eq!(
+            ("VENDOR32", "Vendor Adapter", None),
+            (products[0].id.as_str(), products[0].description.as_str(), products[0].error.as_ref())
+        );
+        assert_eq!(vec![1], products[0].devices.iter().map(|d| d.id).collect::<Vec<_>>());
+        assert!(products[1].error.as_ref().unwrap().contains("MISSING32.ini can't be read"));
+        assert!(list_products_from(&inis, "J1708")?[0].devices.is_empty());
+        assert!(list_products_from(&InMemoryInis::default(), "J1939").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn device_protocols() -> Result<(), Error> {
+        let ini = ini::Ini::load_from_str(
+            "[ProtocolInformation100]\nProtocolString=J1939\nDevices=1,2\n\
+             [ProtocolInformation101]\nProtocolString=J1708\nDevices=2\n",
+        )?;
+        assert_eq!(vec!["J1939"], protocols_for_device(&ini, 1));
+        assert_eq!(vec!["J1939", "J1708"], protocols_for_device(&ini, 2));
+        assert!(protocols_for_device(&ini, 3).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn device_capabilities() -> Result<(), Error> {
+        let ini = ini::Ini::load_from_str(
+            "[VendorInformation]\nName=Vendor\nAutoDetectCapable=TRUE\nJ1939Addresses=4\n\
+             [DeviceInformation1]\nDeviceID=1\nDeviceName=USB\nDeviceDescription=USB Device\n\
+             DeviceParams=Bluetooth=No\n\
+             MultiJ1939Channels=2\nMultiCANChannels=4\n\
+             [DeviceInformation2]\nDeviceID=2\nDeviceName=BT\nDeviceDescription=Bluetooth Device\n\
+             [ProtocolInformation100]\nProtocolString=J1939\nProtocolSpeed=250,500,Auto\nDevices=1\n\
+             [ProtocolInformation101]\nProtocolString=J1939\nProtocolSpeed=250\nDevices=2\n",
+        )?;
+        let devices = devices_for_protocol(&ini, "J1939");
+        assert_eq!(2, devices.len());
+        assert_eq!(vec!["250", "500", "Auto"], devices[0].speeds);
+        assert!(devices[0].auto_baud);
+        assert!(!devices[1].auto_baud);
+        assert!(devices[1].auto_detect);
+        assert_eq!(Some(4), devices[1].j1939_addresses);
+        assert_eq!(Some("Bluetooth=No".to_string()), devices[0].params);
+        assert_eq!(vec![1, 2], devices[0].channels());
+        assert_eq!(vec![1], devices[1].channels());
+        assert!(devices_for_protocol(&ini, "J1708").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(target_os = "windows", ignore = "requires an adapter")]
+    fn baud_checked_against_speeds() {
+        assert!(check_baud("SIM", 1, "J1939", "500").is_ok());
+        assert!(check_baud("sim", 1, "J1939", "auto").is_ok());
+        let error = check_baud("SIM", 2, "J1939", "1000").unwrap_err();
+        assert!(error.to_string().contains("250, 500, Auto"));
+        assert!(check_channel("SIM", 1, "J1939", 2).is_ok());
+        assert!(check_channel("SIM", 1, "J1939", 3).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(target_os = "windows", ignore = "requires an adapter")]
+    fn first_available_connects() -> Result<(), Error> {
+        let factory = first_available().context("No adapters")?;
+        factory.connect()?.close()
+    }
 }
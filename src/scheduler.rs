@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::connection::Connection;
+use crate::j1939;
+use crate::rate_limit::RateLimitExceeded;
+
+/// A PGN to request from `dest` (0xFF for all nodes) every `interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduledRequest {
+    pub pgn: u32,
+    pub dest: u8,
+    pub interval: Duration,
+}
+
+/// Transmits requests for several PGNs, each at its own interval, from a background thread until stopped or
+/// dropped.  Responses arrive on the connection's iterators as usual.
+///
+/// Requests are coalesced: a PGN listed more than once for the same destination is requested at the shortest
+/// interval, and destination specific requests are skipped when a global request for the PGN goes out at the same
+/// time.  Wrap the connection in `RateLimited` to limit the requests; requests it drops are skipped until their
+/// next interval.
+pub struct RequestScheduler {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RequestScheduler {
+    /// Start requesting.  Every request is sent immediately, then at its interval.
+    pub fn start(
+        mut connection: Box<dyn Connection>,
+        requests: &[ScheduledRequest],
+    ) -> Result<RequestScheduler> {
+        let mut schedule: Vec<(ScheduledRequest, Instant)> = Vec::new();
+        let now = Instant::now();
+        for request in requests {
+            match schedule
+                .iter_mut()
+                .find(|(r, _)| r.pgn == request.pgn && r.dest == request.dest)
+            {
+                Some((r, _)) => r.interval = r.interval.min(request.interval),
+                None => schedule.push((*request, now)),
+            }
+        }
+        let (stop, stopped) = channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("request scheduler".into())
+            .spawn(move || loop {
+                let now = Instant::now();
+                let global: HashSet<u32> = schedule
+                    .iter()
+                    .filter(|(r, next)| r.dest == 0xFF && *next <= now)
+                    .map(|(r, _)| r.pgn)
+                    .collect();
+                for (request, next) in schedule.iter_mut().filter(|(_, next)| *next <= now) {
+                    // a late request is rescheduled from now, rather than sent repeatedly to catch up
+                    *next = (*next + request.interval).max(now);
+                    if request.dest != 0xFF && global.contains(&request.pgn) {
+                        continue;
+                    }
+                    let packet = j1939::request(request.pgn, request.dest, connection.address());
+                    match connection.transmit(&packet) {
+                        Err(e) if e.is::<RateLimitExceeded>() => {
+                            log::debug!("request scheduler: {}", e)
+                        }
+                        Err(e) => log::warn!("request scheduler: {}", e),
+                        Ok(()) => {}
+                    }
+                }
+                let wait = schedule
+                    .iter()
+                    .map(|(_, next)| next.saturating_duration_since(Instant::now()))
+                    .min()
+                    .unwrap_or(Duration::MAX);
+                // dropping the sender wakes the thread immediately
+                match stopped.recv_timeout(wait) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            })?;
+        Ok(RequestScheduler {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Stop requesting.  Returns after the last request has been sent.
+    pub fn stop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RequestScheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusKind;
+    use crate::rp1210::Rp1210;
+
+    #[test]
+    #[allow(deprecated)]
+    fn requests_at_intervals() -> Result<()> {
+        let connection: Box<dyn Connection> = Box::new(Rp1210::new(
+            "SIM",
+            1,
+            None,
+            "J1939",
+            0xF9,
+            true,
+            BusKind::MultiQueue,
+        )?);
+        let requested =
+            connection.iter_filtered(std::sync::Arc::new(|p| p.pgn() & 0xFF00 == j1939::REQUEST));
+        let every = |pgn, dest, ms| ScheduledRequest {
+            pgn,
+            dest,
+            interval: Duration::from_millis(ms),
+        };
+        let mut scheduler = RequestScheduler::start(
+            connection.clone(),
+            &[
+                every(0xF004, 0x00, 20),
+                every(0xF004, 0x00, 500),
+                every(0xFEEC, 0xFF, 60_000),
+                every(0xFEEC, 0x00, 60_000),
+            ],
+        )?;
+        let end = Instant::now() + Duration::from_secs(5);
+        let requests: Vec<(u32, u8)> = requested
+            .take_while(|_| Instant::now() < end)
+            .flatten()
+            .map(|p| {
+                (
+                    u32::from_le_bytes([p.data()[0], p.data()[1], p.data()[2], 0]),
+                    p.dest(),
+                )
+            })
+            .scan(0, |engine_speed, r| {
+                *engine_speed += (r.0 == 0xF004) as usize;
+                (*engine_speed <= 3).then_some(r)
+            })
+            .collect();
+        scheduler.stop();
+        // the global request covered the destination specific one
+        assert_eq!(3, requests.iter().filter(|r| **r == (0xF004, 0x00)).count());
+        assert_eq!(1, requests.iter().filter(|r| **r == (0xFEEC, 0xFF)).count());
+        assert!(!requests.contains(&(0xFEEC, 0x00)));
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::packet::{Direction, J1939Packet};
+
+/// Extension for recorded sessions.
+pub const EXTENSION: &str = "rp1210log";
+
+const FORMAT: &str = "rp1210log";
+const VERSION: u32 = 1;
+
+/// First line of a session.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+}
+
+/// One packet per line.  `rp1210` is the packet as the adapter delivered it, in the RP1210 transmit format if
+/// `tx`, so the packet is rebuilt exactly.  `id` and `data` are for people reading the log.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    channel: u8,
+    direction: Direction,
+    /// time(), including host time stamps and counter wraps
+    time: f64,
+    /// raw adapter time stamp.  None in the transmit format.
+    time_stamp: Option<u32>,
+    time_stamp_weight: f64,
+    id: String,
+    data: String,
+    tx: bool,
+    rp1210: String,
+}
+
+/// Write `packets` as a session: JSON lines that preserve every field of each packet, for replay.
+/// Returns the number of packets written.
+pub fn write_session(
+    writer: &mut dyn Write,
+    packets: impl IntoIterator<Item = J1939Packet>,
+) -> Result<usize> {
+    serde_json::to_writer(
+        &mut *writer,
+        &Header {
+            format: FORMAT.to_string(),
+            version: VERSION,
+        },
+    )?;
+    writeln!(writer)?;
+    let mut written = 0;
+    for p in packets {
+        let record = Record {
+            channel: p.channel(),
+            direction: p.direction(),
+            time: p.time(),
+            time_stamp: p.time_stamp(),
+            time_stamp_weight: p.time_stamp_weight(),
+            id: format!("{:08X}", p.id()),
+            data: p.data_str(),
+            tx: p.tx_format(),
+            rp1210: hex(p.raw_bytes()),
+        };
+        serde_json::to_writer(&mut *writer, &record)?;
+        writeln!(writer)?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+/// Read a session written by `write_session()`.  Replay it with the simulator's `replay()`.
+pub fn read_session(reader: impl BufRead) -> Result<Vec<J1939Packet>> {
+    let mut lines = reader.lines();
+    let header: Header =
+        serde_json::from_str(&lines.next().context("Empty session")??).context("Not a session")?;
+    if header.format != FORMAT || header.version > VERSION {
+        bail!(
+            "Unsupported session format {} {}",
+            header.format,
+            header.version
+        );
+    }
+    lines
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|(i, line)| {
+            let record: Record =
+                serde_json::from_str(&line?).with_context(|| format!("Session line {}", i + 2))?;
+            let rp1210 =
+                parse_hex(&record.rp1210).with_context(|| format!("Session line {}", i + 2))?;
+            Ok(J1939Packet::new_rp1210(
+                record.tx,
+                record.channel,
+                &rp1210,
+                record.time_stamp_weight,
+            )
+            .with_direction(record.direction)
+            .with_time(record.time))
+        })
+        .collect()
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    if !s.is_ascii() || !s.len().is_multiple_of(2) {
+        bail!("Invalid hex {}", s);
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&s[i..i + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let received = J1939Packet::new_rp1210(
+            false,
+            2,
+            &[0, 0, 0x30, 0x39, 0, 0xF1, 0xFE, 0x06, 0x00, 0xFF, 1, 2, 3],
+            50.0,
+        )
+        .with_time(7.5);
+        let packets = vec![
+            received,
+            J1939Packet::new(Some(1_000), 0, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]),
+            J1939Packet::new(None, 1, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]).as_sent(),
+        ];
+        let mut out = Vec::new();
+        assert_eq!(3, write_session(&mut out, packets.clone())?);
+        let read = read_session(&out[..])?;
+        assert_eq!(packets.len(), read.len());
+        for (p, r) in packets.iter().zip(&read) {
+            assert_eq!(p.packet.data, r.packet.data);
+            assert_eq!(
+                (p.channel(), p.direction(), p.tx_format(), p.time_stamp()),
+                (r.channel(), r.direction(), r.tx_format(), r.time_stamp())
+            );
+            assert_eq!(p.time_stamp_weight(), r.time_stamp_weight());
+            assert!((p.time() - r.time()).abs() < 1e-9);
+        }
+        assert!(read_session(&b"{\"format\":\"asc\",\"version\":1}\n"[..]).is_err());
+        Ok(())
+    }
+}
@@ -1,71 +1,744 @@
 use anyhow::*;
+use std::collections::HashMap;
 use std::sync::atomic::*;
 use std::sync::*;
-use std::thread::Builder;
+use std::thread::{Builder, JoinHandle};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::bus::{Bus, PushBus};
-use crate::connection::Connection;
+use crate::bus::{Bus, BusIterator, BusKind, Filter};
+use crate::clock::Clock;
+use crate::connection::{
+    self, Access, ClaimMode, ConnectOptions, Connection, ConnectionInfo, FrameCounter, OnReadError, ReadMode,
+    SendOptions,
+};
+use crate::filter::{self, J1939Filter};
+use crate::hardware_status::{BusState, BusStates};
+use crate::j1587::J1587Message;
+use crate::j1939::{self, AddressClaim, J1939Name};
+use crate::keepalive::Keepalive;
 use crate::packet::*;
+use crate::registry::{self, Registration};
+use crate::rp1210_error::{self, Rp1210Error, ERR_ADDRESS_NEVER_CLAIMED};
+use crate::timestamp::{TimestampSource, Timestamps};
+use crate::tp;
+use crate::wireless::WirelessState;
 
 pub struct Rp1210 {
-    bus: Box<PushBus<J1939Packet>>,
+    /// None once this handle is closed
+    client: Option<Arc<Client>>,
+    id: String,
+    device: i16,
+    connection_string: String,
+    frames: FrameCounter,
+    bus: Box<dyn Bus<J1939Packet>>,
+    address: u8,
+    keepalive: Option<Keepalive>,
+    inject_sent: bool,
+    address_claim: Option<AddressClaim>,
+    /// NAME of a simulated node that also claims our address
+    competitor: Option<J1939Name>,
+    /// how connecting and set_j1939_address() wait for the claim
+    claim_mode: ClaimMode,
+    /// shared with the simulator thread
+    timestamps: Timestamps,
+    access: Access,
+    /// simulated nodes answering requests, shared by clones
+    ecus: Arc<Mutex<Vec<SimEcu>>>,
+    /// set by fail_reads(), taken by the simulator thread
+    fault: Arc<Mutex<Option<Rp1210Error>>>,
+    /// why the simulator thread stopped, with OnReadError::Terminate
+    read_error: Arc<Mutex<Option<Rp1210Error>>>,
+    /// most recent simulated driver error, shared by clones and the simulator thread
+    last_error: Arc<Mutex<Option<Rp1210Error>>>,
+    /// false stops the generated traffic, shared by clones
+    powered: Arc<AtomicBool>,
+    /// set by apply_filters(), shared by clones and the simulator thread
+    filters: Arc<Mutex<Vec<J1939Filter>>>,
+    /// Some with ReadMode::Poll, which has no simulator thread
+    poll: Option<Arc<Mutex<SimPoll>>>,
+    clock: Arc<dyn Clock>,
+    /// set by set_bus_state(), shared by clones and the simulator thread
+    hardware: Arc<Mutex<BusState>>,
+    /// as read by the simulator thread with a bus state interval
+    bus_states: BusStates,
+}
+
+/// The simulated adapter's traffic for poll(), generated as it's polled.
+struct SimPoll {
+    /// subscribed on connect, so the echoes, answers and replays pushed onto the bus are read back
+    packets: Box<dyn BusIterator<J1939Packet>>,
+    seq: u64,
+    next: Instant,
+    channel: u8,
+    timestamps: Timestamps,
+}
+
+/// A simulated node that answers requests sent through the simulator, for testing request/response code.
+///
+/// Requests for Address Claimed are answered with the ECU's NAME, and requests for a configured PGN with its
+/// response.  Responses longer than 8 bytes are sent with BAM, to the global address.  Other requests aren't
+/// answered.
+#[derive(Debug, Clone)]
+pub struct SimEcu {
+    pub address: u8,
+    pub name: J1939Name,
+    responses: HashMap<u32, Vec<u8>>,
+    /// requests still to be ignored
+    miss: usize,
+}
+
+impl SimEcu {
+    pub fn new(address: u8, name: J1939Name) -> SimEcu {
+        SimEcu {
+            address,
+            name,
+            responses: Default::default(),
+            miss: 0,
+        }
+    }
+
+    /// Answer requests for `pgn` with `data`.
+    pub fn respond(mut self, pgn: u32, data: &[u8]) -> SimEcu {
+        self.responses.insert(pgn, data.to_vec());
+        self
+    }
+
+    /// Ignore the next `count` requests for us, like a busy ECU.
+    pub fn miss_requests(mut self, count: usize) -> SimEcu {
+        self.miss = count;
+        self
+    }
+
+    /// Packets answering `request`, if it's a request for us.
+    fn answer(&mut self, request: &J1939Packet) -> Vec<J1939Packet> {
+        let dest = request.dest();
+        if tp::base_pgn(request) != j1939::REQUEST
+            || request.data().len() < 3
+            || (dest != 0xFF && dest != self.address)
+        {
+            return Vec::new();
+        }
+        if self.miss > 0 {
+            self.miss -= 1;
+            return Vec::new();
+        }
+        let data = request.data();
+        let pgn = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+        let channel = request.channel();
+        let packet = |pgn: u32, priority: u32, dest: u8, data: &[u8]| {
+            // PDU1 PGNs carry the destination
+            let pgn = if pgn < 0xF000 { pgn & 0x3FF00 | dest as u32 } else { pgn };
+            J1939Packet::new(
+                Some(now()),
+                channel,
+                priority << 26 | pgn << 8 | self.address as u32,
+                data,
+            )
+            .as_received()
+        };
+        if pgn == j1939::ADDRESS_CLAIMED {
+            return vec![packet(pgn, 6, 0xFF, &self.name.to_bytes())];
+        }
+        let Some(response) = self.responses.get(&pgn) else {
+            return Vec::new();
+        };
+        if response.len() <= 8 {
+            return vec![packet(pgn, 6, request.source(), response)];
+        }
+        let packets = response.len().div_ceil(7);
+        let size = (response.len() as u16).to_le_bytes();
+        let pgn_bytes = pgn.to_le_bytes();
+        let cm = [
+            32,
+            size[0],
+            size[1],
+            packets as u8,
+            0xFF,
+            pgn_bytes[0],
+            pgn_bytes[1],
+            pgn_bytes[2],
+        ];
+        std::iter::once(packet(tp::TP_CM, 7, 0xFF, &cm))
+            .chain(response.chunks(7).enumerate().map(|(i, chunk)| {
+                let mut dt = [0xFF; 8];
+                dt[0] = i as u8 + 1;
+                dt[1..=chunk.len()].copy_from_slice(chunk);
+                packet(tp::TP_DT, 7, 0xFF, &dt)
+            }))
+            .collect()
+    }
+}
+
+/// Simulated adapter client, shared by clones of an `Rp1210`.  Stops when the last clone is closed or dropped.
+struct Client {
     running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    bus: Box<dyn Bus<J1939Packet>>,
+    bus_states: BusStates,
+    registration: Option<Registration>,
+}
+
+impl Client {
+    /// The simulator thread is running until close(), unless it panicked or stopped on a read error.  Polled
+    /// clients run until close().
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+            && self.thread.as_ref().is_none_or(|t| !t.is_finished())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().map_err(|_| anyhow!("Simulator thread panicked"))?;
+        }
+        self.bus.close();
+        self.bus_states.close();
+        self.registration.take();
+        Ok(())
+    }
 }
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Clones share the simulated client and bus.  Keepalives aren't cloned.
+impl Clone for Rp1210 {
+    fn clone(&self) -> Self {
+        Rp1210 {
+            client: self.client.clone(),
+            id: self.id.clone(),
+            device: self.device,
+            connection_string: self.connection_string.clone(),
+            frames: self.frames.clone(),
+            bus: self.bus.clone(),
+            address: self.address,
+            keepalive: None,
+            inject_sent: self.inject_sent,
+            address_claim: self.address_claim,
+            competitor: self.competitor,
+            claim_mode: self.claim_mode,
+            timestamps: self.timestamps.clone(),
+            access: self.access,
+            ecus: self.ecus.clone(),
+            fault: self.fault.clone(),
+            read_error: self.read_error.clone(),
+            last_error: self.last_error.clone(),
+            powered: self.powered.clone(),
+            filters: self.filters.clone(),
+            poll: self.poll.clone(),
+            clock: self.clock.clone(),
+            hardware: self.hardware.clone(),
+            bus_states: self.bus_states.clone(),
+        }
+    }
+}
+
 impl Rp1210 {
     #[deprecated(note = "Must be built with Win32 target to use RP1210 adapters.")]
     pub fn new(
-        _id: &str,
+        id: &str,
+        device: i16,
+        channel: Option<u8>,
+        connection_string: &str,
+        address: u8,
+        app_packetized: bool,
+        bus: BusKind,
+    ) -> Result<Rp1210> {
+        #[allow(deprecated)]
+        Self::new_with_options(
+            id,
+            device,
+            channel,
+            connection_string,
+            address,
+            app_packetized,
+            &ConnectOptions::default(),
+            bus,
+        )
+    }
+
+    /// new() with `options`.  Read-only connections don't claim an address, and sends fail.  The simulator
+    /// connects immediately, so the connect timeout doesn't apply.  The idle timeout counts generated, replayed
+    /// and answered packets; see set_powered().
+    #[deprecated(note = "Must be built with Win32 target to use RP1210 adapters.")]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_options(
+        id: &str,
         device: i16,
         channel: Option<u8>,
-        _connection_string: &str,
-        _address: u8,
-        _app_packetized: bool,
+        connection_string: &str,
+        address: u8,
+        app_packetized: bool,
+        options: &ConnectOptions,
+        bus: BusKind,
     ) -> Result<Rp1210> {
-        let bus = PushBus::new();
-        let running = Arc::new(AtomicBool::new(false));
+        options.check()?;
+        let access = options.access;
+        let bus = bus.create();
+        let running = Arc::new(AtomicBool::new(true));
         let dev = device as u8;
-        {
+        let frames = FrameCounter::new();
+        let timestamps = Timestamps::default();
+        let fault: Arc<Mutex<Option<Rp1210Error>>> = Default::default();
+        let read_error: Arc<Mutex<Option<Rp1210Error>>> = Default::default();
+        let last_error: Arc<Mutex<Option<Rp1210Error>>> = Default::default();
+        let on_read_error = options.on_read_error;
+        let idle_timeout = options.idle_timeout;
+        let clock = options.clock.clone();
+        let powered = Arc::new(AtomicBool::new(true));
+        let filters: Arc<Mutex<Vec<J1939Filter>>> = Default::default();
+        let hardware = Arc::new(Mutex::new(BusState::Active));
+        let bus_states = BusStates::new();
+        let bus_state_interval = options.bus_state_interval;
+        let thread = (options.read_mode == ReadMode::Thread).then(|| {
             let running = running.clone();
+            let mut timestamps = timestamps.clone();
+            let frames = frames.clone();
             let mut bus = bus.clone();
+            let fault = fault.clone();
+            let read_error = read_error.clone();
+            let last_error = last_error.clone();
+            let powered = powered.clone();
+            let filters = filters.clone();
+            let clock = clock.clone();
+            let hardware = hardware.clone();
+            let mut bus_states = bus_states.clone();
             Builder::new().name("rp1210".into()).spawn(move || {
-                running.store(true, Ordering::Relaxed);
-                let mut seq: u64 = u64::from_be_bytes([dev, 0, 0, 0, 0, 0, 0, 0]);
+                let mut seq = first_seq(dev);
+                let mut last_received = (frames.frames(), clock.now());
+                let mut next_status = clock.now();
                 while running.load(Ordering::Relaxed) {
-                    let packet = J1939Packet::new_packet(
-                        Some(now()),
-                        channel.unwrap_or(0),
-                        6,
-                        0xFEF1,
-                        0,
-                        0x0,
-                        &seq.to_be_bytes(),
-                    );
-                    bus.push(Some(packet));
+                    let state = *hardware.lock().unwrap();
+                    if let Some(interval) = bus_state_interval {
+                        if clock.now() >= next_status {
+                            next_status = clock.now() + interval;
+                            bus_states.update(&format!("SIM {}", dev), state);
+                        }
+                    }
+                    if let Some(e) = fault.lock().unwrap().take() {
+                        log::error!("SIM {}: {}", dev, e);
+                        *last_error.lock().unwrap() = Some(e.clone());
+                        if on_read_error == OnReadError::Terminate {
+                            *read_error.lock().unwrap() = Some(e);
+                            // ends iterators, which then report the error
+                            bus.close();
+                            break;
+                        }
+                    }
+                    if powered.load(Ordering::Relaxed) && !state.is_off() {
+                        let packet = generated(channel.unwrap_or(0), seq);
+                        if filter::passes(&filters.lock().unwrap(), &packet) {
+                            bus.push(Some(timestamps.stamp(packet)));
+                            frames.add();
+                        }
+                    }
+                    if frames.frames() != last_received.0 {
+                        last_received = (frames.frames(), clock.now());
+                    } else if idle_timeout
+                        .is_some_and(|t| clock.now().saturating_duration_since(last_received.1) >= t)
+                    {
+                        log::warn!("SIM {}: nothing received for {:?}", dev, idle_timeout.unwrap());
+                        running.store(false, Ordering::Relaxed);
+                        bus.close();
+                        break;
+                    }
                     std::thread::sleep(Duration::from_millis(100));
-                    seq = seq + 1;
+                    seq += 1;
                 }
-            })?;
-        }
-        Ok(Rp1210 {
-            bus: Box::new(bus.clone()),
-            running: running.clone(),
+            })
         })
+        .transpose()?;
+        let poll = (options.read_mode == ReadMode::Poll).then(|| {
+            Arc::new(Mutex::new(SimPoll {
+                packets: bus.iter(),
+                seq: first_seq(dev),
+                next: Instant::now(),
+                channel: channel.unwrap_or(0),
+                timestamps: timestamps.clone(),
+            }))
+        });
+        let client = Client {
+            running,
+            thread,
+            bus: bus.clone(),
+            bus_states: bus_states.clone(),
+            registration: Some(registry::register(id, device, channel)),
+        };
+        let mut rp1210 = Rp1210 {
+            client: Some(Arc::new(client)),
+            id: id.to_string(),
+            device,
+            connection_string: connection_string.to_string(),
+            frames,
+            bus,
+            address,
+            keepalive: None,
+            inject_sent: false,
+            address_claim: None,
+            competitor: None,
+            claim_mode: options.claim_mode,
+            timestamps,
+            access,
+            ecus: Default::default(),
+            fault,
+            read_error,
+            last_error,
+            powered,
+            filters,
+            poll,
+            clock,
+            hardware,
+            bus_states,
+        };
+        if !app_packetized && !j1939::unclaimed(address) && access == Access::ReadWrite {
+            rp1210.claim(address)?;
+        }
+        Ok(rp1210)
+    }
+
+    /// Outcome of the address claim made while connecting.  None if app packetized, read-only, or for the null
+    /// address.
+    pub fn address_claim(&self) -> Option<AddressClaim> {
+        self.address_claim
+    }
+
+    /// The address the adapter protects for this handle, from its last claim.  None if nothing was claimed.
+    pub fn current_j1939_address(&self) -> Option<u8> {
+        self.address_claim.map(|c| c.claimed.unwrap_or(c.requested))
     }
+
+    /// Release the claimed address and claim `address` instead, without reconnecting.  Sends use `address` from
+    /// then on.  Returns the claim's outcome, or an error on contention, which leaves no address claimed.
+    pub fn set_j1939_address(&mut self, address: u8) -> Result<AddressClaim> {
+        self.client()?;
+        self.access.check_write()?;
+        self.address_claim = None;
+        let claim = self.claim(address)?;
+        self.address = address;
+        Ok(claim)
+    }
+
+    /// Simulate the driver failing the next read with `error`, handled as the connection's `OnReadError` says.
+    pub fn fail_reads(&self, error: Rp1210Error) {
+        *self.fault.lock().unwrap() = Some(error);
+    }
+
+    /// Read the next packet on this thread, with `ReadMode::Poll`.  None if nothing is waiting.  The generated
+    /// traffic is made as it's polled, every 100 ms.
+    pub fn poll(&self) -> Result<Option<J1939Packet>> {
+        self.client()?;
+        let poll = self
+            .poll
+            .as_ref()
+            .ok_or_else(|| anyhow!("Not polled.  Connect with ReadMode::Poll to poll."))?;
+        if let Some(e) = self.fault.lock().unwrap().take() {
+            return Err(self.failed(e));
+        }
+        let mut poll = poll.lock().unwrap();
+        if self.powered.load(Ordering::Relaxed) && Instant::now() >= poll.next {
+            poll.next += Duration::from_millis(100);
+            let packet = generated(poll.channel, poll.seq);
+            poll.seq += 1;
+            if filter::passes(&self.filters.lock().unwrap(), &packet) {
+                let packet = poll.timestamps.stamp(packet);
+                self.bus.clone().push(Some(packet));
+                self.frames.add();
+            }
+        }
+        Ok(poll.packets.next().flatten())
+    }
+
+    /// Simulate switching the vehicle off, stopping the generated traffic, or back on.  Shared by clones.
+    pub fn set_powered(&self, powered: bool) {
+        self.powered.store(powered, Ordering::Relaxed);
+    }
+
+    /// Simulate the adapter's CAN error state, e.g. `BusState::BusOff` for a wiring fault, which stops the
+    /// generated traffic like set_powered(false).  Read every `ConnectOptions::bus_state_interval`.
+    pub fn set_bus_state(&self, state: BusState) {
+        *self.hardware.lock().unwrap() = state;
+    }
+
+    /// The bus state last read, with a bus state interval.  None until read.
+    pub fn bus_state(&self) -> Option<BusState> {
+        self.bus_states.get()
+    }
+
+    /// The bus state each time it's read and has changed, starting with the first read.  Ends on close().
+    pub fn iter_bus_state(&self) -> Box<dyn BusIterator<BusState>> {
+        self.bus_states.iter()
+    }
+
+    /// Why reading stopped, with `OnReadError::Terminate`.
+    pub fn read_error(&self) -> Option<Rp1210Error> {
+        self.read_error.lock().unwrap().clone()
+    }
+
+    /// Most recent error the simulated driver returned, from a failed read or send, for status displays.  Kept
+    /// after later calls succeed.
+    pub fn last_error(&self) -> Option<Rp1210Error> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// `error`, recorded as the last error.
+    fn failed(&self, error: Rp1210Error) -> anyhow::Error {
+        *self.last_error.lock().unwrap() = Some(error.clone());
+        error.into()
+    }
+
+    /// iter(), ending with `Err(Rp1210Error)` if reading stopped on a read error.  Ends without an error on
+    /// close().
+    pub fn iter_results(&self) -> impl Iterator<Item = Result<Option<J1939Packet>>> {
+        connection::until_failure(self.bus.iter(), self.read_error.clone())
+    }
+
+    /// Add a simulated node that answers requests sent on this connection or its clones.
+    pub fn add_ecu(&mut self, ecu: SimEcu) {
+        self.ecus.lock().unwrap().push(ecu);
+    }
+
+    /// Simulate another node claiming our address in later claim()s.  The lower NAME wins, as in J1939-81.
+    pub fn set_competitor(&mut self, name: Option<J1939Name>) {
+        self.competitor = name;
+    }
+
+    /// Claim `address` as the adapter does on connect, echoing Address Claimed, or Cannot Claim Address if a
+    /// competitor with a lower NAME takes it.  Losing fails unless the claim mode is `ClaimMode::NonBlocking`.
+    pub fn claim(&mut self, address: u8) -> Result<AddressClaim> {
+        let claims = self.bus.iter();
+        let ours = j1939::TOOL_NAME;
+        let claimed = |address: u8, name: &J1939Name| {
+            J1939Packet::new(Some(now()), 0, 0x18EEFF00 | address as u32, &name.to_bytes())
+        };
+        self.bus.push(Some(claimed(address, &ours)));
+        if let Some(competitor) = self.competitor {
+            self.bus.push(Some(claimed(address, &competitor).as_received()));
+            let value = |name: &J1939Name| u64::from_le_bytes(name.to_bytes());
+            if value(&competitor) < value(&ours) {
+                self.bus.push(Some(claimed(j1939::NULL_ADDRESS, &ours)));
+            }
+        }
+        // everything pushed above is queued
+        let claim = AddressClaim::from_packets(address, &ours, claims.map_while(|p| p));
+        if claim.claimed != Some(address) && self.claim_mode != ClaimMode::NonBlocking {
+            bail!("Unable to claim address {:02X} (contention)", address);
+        }
+        self.address_claim = Some(claim);
+        Ok(claim)
+    }
+
+    /// Also push each sent packet onto this connection's bus, marked `sent()`, so iter() shows both directions
+    /// in order.  The adapter echo, if any, still follows.
+    pub fn set_inject_sent(&mut self, inject: bool) {
+        self.inject_sent = inject;
+    }
+
+    /// There is nothing to reset.
+    pub fn reset(_id: &str, _device: i16, _connection_string: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Pass only received packets matching one of `filters`, as the adapter does.  No filters passes everything.
+    /// Echoes aren't filtered.
+    pub fn apply_filters(&self, filters: &[J1939Filter]) -> Result<()> {
+        self.client()?;
+        *self.filters.lock().unwrap() = filters.to_vec();
+        Ok(())
+    }
+
+    /// Push recorded packets, e.g. from `session::read_session()`, onto the bus as they are, keeping their time
+    /// stamps and directions.  The generated traffic continues alongside them.  Received packets are filtered.
+    pub fn replay(&mut self, packets: impl IntoIterator<Item = J1939Packet>) {
+        for packet in packets {
+            self.receive(packet);
+        }
+    }
+
+    /// Deliver a packet from the bus, unless the filters discard it.
+    fn receive(&mut self, packet: J1939Packet) {
+        if packet.direction() != Direction::Rx
+            || filter::passes(&self.filters.lock().unwrap(), &packet)
+        {
+            self.bus.push(Some(packet));
+            self.frames.add();
+        }
+    }
+
+    /// The simulator accepts raw messages, but doesn't interpret them, so nothing is echoed.
+    pub fn send_raw(&self, bytes: &[u8]) -> Result<i16> {
+        self.client()?;
+        self.access.check_write()?;
+        rp1210_error::message_size(bytes)?;
+        Ok(0)
+    }
+
+    /// The simulator echoes sends as soon as they're made, so the flags make no difference.
+    pub fn set_send_options(&mut self, _options: SendOptions) {}
+
+    /// The simulator sends packets as they come, so there's nothing to pace.
+    pub fn set_interpacket_time(&self, _time: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    /// The simulator is wired.
+    pub fn wireless_state(&self) -> Option<WirelessState> {
+        None
+    }
+
+    /// The simulator only speaks J1939.
+    pub fn supported_protocols(&self) -> Vec<String> {
+        vec!["J1939".to_string()]
+    }
+
+    /// Time stamp simulated packets with the simulator's clock (the default) or the host's.  Shared by clones.
+    pub fn set_timestamp_source(&self, source: TimestampSource) {
+        self.timestamps.set_source(source);
+    }
+
+    /// Send `packet` every `interval` until stop_keepalive() or close().  Replaces any previous keepalive.
+    pub fn start_keepalive(&mut self, packet: &J1939Packet, interval: Duration) -> Result<()> {
+        self.stop_keepalive();
+        self.access.check_write()?;
+        let mut bus = self.bus.clone();
+        let running = self.client()?.running.clone();
+        let packet = packet.clone();
+        self.keepalive = Some(Keepalive::start(interval, move || {
+            if !running.load(Ordering::Relaxed) {
+                bail!("Connection closed");
+            }
+            bus.push(Some(echo(&packet)));
+            Ok(())
+        })?);
+        Ok(())
+    }
+
+    pub fn stop_keepalive(&mut self) {
+        self.keepalive.take();
+    }
+
+    fn client(&self) -> Result<&Client> {
+        match self.client.as_deref() {
+            Some(c) if !c.running.load(Ordering::Relaxed) => {
+                bail!("Disconnected after the idle timeout")
+            }
+            Some(c) => Ok(c),
+            None => bail!("Connection closed"),
+        }
+    }
+}
+
+/// First sequence number of the generated traffic, so devices' traffic differs.
+fn first_seq(device: u8) -> u64 {
+    u64::from_be_bytes([device, 0, 0, 0, 0, 0, 0, 0])
+}
+
+/// Generated traffic, carrying sequence number `seq`.
+fn generated(channel: u8, seq: u64) -> J1939Packet {
+    J1939Packet::new_packet(Some(now()), channel, 6, 0xFEF1, 0, 0x0, &seq.to_be_bytes())
+        .as_received()
+}
+
+/// The packet as the adapter echoes it back.
+fn echo(packet: &J1939Packet) -> J1939Packet {
+    J1939Packet::new_packet(
+        Some(now()),
+        packet.channel(),
+        packet.priority(),
+        packet.pgn(),
+        packet.dest(),
+        packet.source(),
+        packet.data(),
+    )
 }
 
 impl Connection for Rp1210 {
     /// Send packet and return packet echoed back from adapter
+    /// Fails with ERR_ADDRESS_NEVER_CLAIMED for a source other than the claimed address, as adapters do.
     fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet> {
-        let mut packet =
-            J1939Packet::new_packet(Some(now()), packet.channel(), packet.priority(), packet.pgn(),packet.dest(), packet.source(),packet.data());
-        self.bus.push(Some(packet.clone()));
-        Ok(packet)
+        self.client()?;
+        self.access.check_write()?;
+        let packet = &j1939::outgoing(packet, self.address);
+        rp1210_error::message_size(packet.raw_bytes())?;
+        let claimed = self.address_claim.and_then(|c| c.claimed);
+        if claimed.is_some_and(|a| a != packet.source()) && packet.source() != j1939::NULL_ADDRESS {
+            return Err(self.failed(Rp1210Error {
+                code: ERR_ADDRESS_NEVER_CLAIMED,
+                message: format!("{:02X} wasn't claimed", packet.source()),
+            }));
+        }
+        // nothing can fail once running, so the copy can go ahead of the echo
+        if self.inject_sent {
+            self.bus.push(Some(packet.as_sent()));
+        }
+        let echoed = echo(packet);
+        self.bus.push(Some(echoed.clone()));
+        // answers follow the echo of the request, as on a real bus
+        let answers: Vec<J1939Packet> = self
+            .ecus
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .flat_map(|ecu| ecu.answer(packet))
+            .collect();
+        for answer in answers {
+            self.receive(answer);
+        }
+        Ok(echoed)
     }
 
-    fn iter(&self) -> Box<dyn Iterator<Item = Option<J1939Packet>> + Send + Sync> {
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<()> {
+        self.send(packet).map(|_| ())
+    }
+
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
         self.bus.iter()
     }
+
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter_filtered(filter)
+    }
+
+    fn is_running(&self) -> bool {
+        self.client.as_ref().is_some_and(|c| c.is_running()) && self.read_error().is_none()
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            adapter: self.id.clone(),
+            device: self.device,
+            connection_string: self.connection_string.clone(),
+            address: self.address,
+            address_claim: self.address_claim(),
+            baud: None,
+            driver_version: Some(format!("simulator {}", env!("CARGO_PKG_VERSION"))),
+            frames: self.frames.frames(),
+            frames_per_second: self.frames.per_second(),
+        }
+    }
+
+    /// Stop this handle's keepalive.  The simulator stops with the last clone.
+    fn close(&mut self) -> Result<()> {
+        self.stop_keepalive();
+        match self.client.take().and_then(Arc::into_inner) {
+            Some(mut client) => client.close(),
+            None => Ok(()),
+        }
+    }
+
+    fn clone_connection(&self) -> Box<dyn Connection> {
+        Box::new(self.clone())
+    }
 }
 
 fn now() -> u32 {
@@ -79,8 +752,697 @@ fn now() -> u32 {
 
 impl Drop for Rp1210 {
     fn drop(&mut self) {
-        self.running.store(false, Ordering::Relaxed);
+        let _ = self.close();
+    }
+}
+
+/// Simulated J1708 connection.  Sent messages are echoed.
+pub struct J1708 {
+    bus: Box<dyn Bus<J1587Message>>,
+    running: bool,
+    registration: Option<Registration>,
+}
+
+impl J1708 {
+    #[deprecated(note = "Must be built with Win32 target to use RP1210 adapters.")]
+    pub fn new(id: &str, device: i16, bus: BusKind) -> Result<J1708> {
+        Ok(J1708 {
+            bus: bus.create(),
+            running: true,
+            registration: Some(registry::register(id, device, None)),
+        })
+    }
+
+    pub fn send(&mut self, message: &J1587Message) -> Result<()> {
+        if !self.running {
+            bail!("Connection closed");
+        }
+        self.bus.push(Some(J1587Message {
+            time: Some(now()),
+            echo: true,
+            ..message.clone()
+        }));
+        Ok(())
+    }
+
+    pub fn iter(&self) -> Box<dyn BusIterator<J1587Message>> {
+        self.bus.iter()
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.running = false;
         self.bus.close();
-        //let _ = self.thread.take().unwrap().join();
+        self.registration.take();
+        Ok(())
+    }
+}
+
+impl Drop for J1708 {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::packet::Direction;
+
+    #[test]
+    #[allow(deprecated)]
+    fn close() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 1, None, "J1939:Baud=Auto", 0xF9, false, BusKind::PushBus)?;
+        let mut packets = sim.iter();
+        assert!(sim.is_running());
+        sim.close()?;
+        assert!(!sim.is_running());
+        assert!(packets.next().is_none());
+        assert!(sim.send(&J1939Packet::new(None, 0, 0x18FEF1F9, &[0; 8])).is_err());
+        // closing again does nothing
+        sim.close()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn clones_share_the_client() -> Result<()> {
+        let sim = Rp1210::new("SIM", 9, None, "", 0xF9, false, BusKind::MultiQueue)?;
+        let mut first: Box<dyn Connection> = Box::new(sim);
+        let mut second = first.clone();
+        let a = first.iter_for(Duration::from_secs(5));
+        let b = second.iter_for(Duration::from_secs(5));
+        first.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[1, 2, 3]))?;
+        second.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[4, 5, 6]))?;
+        // one stream: both clones see both sends, in order
+        let ours = |p: &J1939Packet| p.source() == 0xF9;
+        let data = |i: Box<dyn Iterator<Item = J1939Packet> + Send + Sync>| {
+            i.filter(ours).take(2).map(|p| p.data().to_vec()).collect::<Vec<_>>()
+        };
+        assert_eq!(vec![vec![1, 2, 3], vec![4, 5, 6]], data(a));
+        assert_eq!(vec![vec![1, 2, 3], vec![4, 5, 6]], data(b));
+        // closing one leaves the other connected
+        let packets = second.iter();
+        first.close()?;
+        assert!(first.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[0])).is_err());
+        second.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[7]))?;
+        assert_eq!(1, packets.flatten().filter(|p| p.data() == [7]).take(1).count());
+        drop(first);
+        let packets = second.iter();
+        second.close()?;
+        assert_eq!(0, packets.flatten().count());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn info() -> Result<()> {
+        let sim = Rp1210::new("SIM", 2, None, "J1939:Baud=Auto", 0xF9, false, BusKind::PushBus)?;
+        // wait for two simulated packets
+        let received = sim.iter_for(Duration::from_secs(5)).filter(|p| p.source() == 0);
+        assert_eq!(2, received.take(2).count());
+        let info = sim.info();
+        assert_eq!(("SIM", 2, 0xF9), (info.adapter.as_str(), info.device, info.address));
+        assert_eq!("J1939:Baud=Auto", info.connection_string);
+        assert!(info.frames >= 2);
+        assert!(info.frames_per_second > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn wait_for_idle() -> Result<()> {
+        // the simulator sends a packet every 100 ms
+        let sim = Rp1210::new("SIM", 3, None, "", 0xF9, false, BusKind::PushBus)?;
+        sim.wait_for_idle(Duration::from_millis(50), Duration::from_secs(1))?;
+        assert!(sim
+            .wait_for_idle(Duration::from_millis(200), Duration::from_millis(300))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn wait_for_idle_by_clock() -> Result<()> {
+        let clock = MockClock::new();
+        let sim = Rp1210::new_with_options(
+            "SIM",
+            31,
+            None,
+            "",
+            0xF9,
+            true,
+            &ConnectOptions {
+                clock: Arc::new(clock.clone()),
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        sim.set_powered(false);
+        let stop = Arc::new(AtomicBool::new(false));
+        let ticker = {
+            let (clock, stop) = (clock.clone(), stop.clone());
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    clock.advance(Duration::from_millis(100));
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+        // minutes of simulated time pass in well under a second
+        sim.wait_for_idle(Duration::from_secs(10), Duration::from_secs(3600))?;
+        let error = sim
+            .wait_for_idle(Duration::from_secs(3600), Duration::from_secs(10))
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Bus not idle"));
+        stop.store(true, Ordering::Relaxed);
+        ticker.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn transact() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 4, None, "", 0xF9, false, BusKind::PushBus)?;
+        let request = J1939Packet::new(None, 0, 0x18EA00F9, &[0xEC, 0xFE, 0x00]);
+        // the simulator echoes what is sent
+        let echoes = sim.transact(
+            &request,
+            &|p| p.data() == [0xEC, 0xFE, 0x00],
+            Duration::from_millis(100),
+        )?;
+        assert_eq!(1, echoes.len());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn j1708_echo() -> Result<()> {
+        let mut j1708 = J1708::new("SIM", 1, BusKind::PushBus)?;
+        let mut messages = j1708.iter().flatten();
+        j1708.send(&J1587Message::new(172, &[0, 0x50]))?;
+        let echo = messages.next().unwrap();
+        assert!(echo.echo);
+        assert_eq!((172, Some(0)), (echo.mid, echo.pid()));
+        j1708.close()?;
+        assert!(j1708.send(&J1587Message::new(172, &[0, 0x50])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn send_timed() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 5, None, "", 0xF9, false, BusKind::PushBus)?;
+        let sent = sim.send_timed(&J1939Packet::new(None, 0, 0x18EA00F9, &[0xEC, 0xFE, 0x00]))?;
+        assert_eq!([0xEC, 0xFE, 0x00], sent.echoed.data());
+        assert!(sent.echoed_at >= sent.queued_at);
+        assert!(sent.latency() < Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn inject_sent() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 6, None, "", 0xF9, false, BusKind::PushBus)?;
+        sim.set_inject_sent(true);
+        let mut packets = sim
+            .iter_for(Duration::from_millis(100))
+            .filter(|p| p.data() == [1, 2, 3]);
+        sim.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[1, 2, 3]))?;
+        assert_eq!(Direction::Tx, packets.next().unwrap().direction());
+        // followed by the echo
+        assert_eq!(Direction::Echo, packets.next().unwrap().direction());
+        assert!(packets.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn without_echoes() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 7, None, "", 0xF9, false, BusKind::PushBus)?;
+        let mut packets = sim.iter_without_echoes(Duration::from_millis(100));
+        let all = sim.iter_for(Duration::from_millis(300));
+        sim.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[1, 2, 3]))?;
+        sim.set_inject_sent(true);
+        sim.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[4, 5, 6]))?;
+        let ours = |p: &J1939Packet| p.source() == 0xF9;
+        // two echoes and the injected copy
+        assert_eq!(3, all.filter(ours).count());
+        // only the injected copy is left
+        let sent = packets.find(ours).unwrap();
+        assert!(sent.sent() && sent.data() == [4, 5, 6]);
+        let next = packets.next().unwrap();
+        assert!(!ours(&next) && next.direction() == Direction::Rx);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn address_claim() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 8, None, "", 0xF9, false, BusKind::PushBus)?;
+        let claim = sim.address_claim().unwrap();
+        assert_eq!((Some(0xF9), false), (claim.claimed, claim.contention));
+        // a competing claim we win
+        let higher = J1939Name {
+            identity_number: 1,
+            ..j1939::TOOL_NAME
+        };
+        sim.set_competitor(Some(higher));
+        let claim = sim.claim(0xF9)?;
+        assert_eq!((Some(0xF9), true), (claim.claimed, claim.contention));
+        // and one we lose
+        let lower = J1939Name {
+            manufacturer_code: 1,
+            ..j1939::TOOL_NAME
+        };
+        sim.set_competitor(Some(lower));
+        let error = sim.claim(0xF9).unwrap_err();
+        assert_eq!("Unable to claim address F9 (contention)", error.to_string());
+
+        sim.set_competitor(None);
+        let claim = sim.set_j1939_address(0xFA)?;
+        assert_eq!((Some(0xFA), Some(0xFA)), (claim.claimed, sim.current_j1939_address()));
+        let echo = sim.send(&J1939Packet::new(None, 0, 0x18FFAAFA, &[1]))?;
+        assert_eq!(0xFA, echo.source());
+        sim.set_competitor(Some(lower));
+        assert!(sim.set_j1939_address(0xFB).is_err());
+        assert_eq!(None, sim.current_j1939_address());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn claim_modes() -> Result<()> {
+        let connect = |claim_mode, read_mode| {
+            let options = ConnectOptions {
+                claim_mode,
+                read_mode,
+                ..Default::default()
+            };
+            Rp1210::new_with_options("SIM", 28, None, "", 0xF9, false, &options, BusKind::PushBus)
+        };
+        let lower = J1939Name {
+            manufacturer_code: 1,
+            ..j1939::TOOL_NAME
+        };
+        let mut sim = connect(ClaimMode::NonBlocking, ReadMode::Thread)?;
+        sim.set_competitor(Some(lower));
+        let claim = sim.claim(0xF9)?;
+        assert_eq!((Some(j1939::NULL_ADDRESS), true), (claim.claimed, claim.contention));
+        assert!(sim.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[1])).is_err());
+        sim.close()?;
+
+        let timeout = ClaimMode::Timeout(Duration::from_millis(250));
+        let mut sim = connect(timeout, ReadMode::Thread)?;
+        assert_eq!(Some(0xF9), sim.current_j1939_address());
+        sim.set_competitor(Some(lower));
+        assert!(sim.claim(0xF9).is_err());
+        sim.close()?;
+        assert!(connect(timeout, ReadMode::Poll).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn null_address() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 9, None, "", 0xFE, false, BusKind::PushBus)?;
+        assert_eq!(None, sim.address_claim());
+        let echo = sim.send(&j1939::request(j1939::ADDRESS_CLAIMED, 0xFF, 0xF9))?;
+        assert_eq!(j1939::NULL_ADDRESS, echo.source());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn ecu_answers_requests() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 11, None, "", 0xF9, false, BusKind::MultiQueue)?;
+        let engine = J1939Name {
+            function: 0,
+            identity_number: 1234,
+            ..Default::default()
+        };
+        sim.add_ecu(
+            SimEcu::new(0x00, engine)
+                .respond(0xFEEC, b"1HGCM82633A004352*")
+                .respond(0xFEE5, &[1, 0, 0, 0, 2, 0, 0, 0]),
+        );
+        sim.add_ecu(SimEcu::new(0x03, J1939Name::default()));
+
+        let vin = sim.request_pgn(0xFEEC, 0x00, Duration::from_secs(2))?;
+        assert_eq!(b"1HGCM82633A004352*", vin[0].data());
+        let hours = sim.request_pgn(0xFEE5, 0xFF, Duration::from_millis(200))?;
+        assert_eq!(1, hours.len());
+        assert_eq!(0x00, hours[0].source());
+        // the transmission doesn't have a VIN
+        assert!(sim.request_pgn(0xFEEC, 0x03, Duration::from_millis(200))?.is_empty());
+
+        let snapshot = sim.request_pgns(&[0xFEEC, 0xFEE5, 0xFEEC], 0x00, Duration::from_secs(2))?;
+        assert_eq!(2, snapshot.len());
+        assert_eq!(b"1HGCM82633A004352*", snapshot[&0xFEEC][0].data());
+        assert_eq!([1, 0, 0, 0, 2, 0, 0, 0], snapshot[&0xFEE5][0].data());
+        let global = sim.request_pgns(&[0xFEE5, 0xFEF5], 0xFF, Duration::from_millis(200))?;
+        assert_eq!(vec![0xFEE5], global.keys().copied().collect::<Vec<_>>());
+
+        let nodes = sim.scan_network(Duration::from_millis(200))?;
+        let addresses: Vec<u8> = nodes.iter().map(|n| n.address).collect();
+        assert_eq!(vec![0x00, 0x03], addresses);
+        assert_eq!(engine, nodes[0].name);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn request_retries() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 16, None, "", 0xF9, false, BusKind::PushBus)?;
+        sim.add_ecu(
+            SimEcu::new(0x00, J1939Name::default())
+                .respond(0xFEE5, &[1, 0, 0, 0, 2, 0, 0, 0])
+                .miss_requests(2),
+        );
+        let timeout = Duration::from_millis(100);
+        assert!(sim.request_pgn_retry(0xFEE5, 0x00, timeout, 1)?.is_empty());
+        let hours = sim.request_pgn_retry(0xFEE5, 0x00, timeout, 1)?;
+        assert_eq!([1, 0, 0, 0, 2, 0, 0, 0], hours[0].data());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn read_errors() -> Result<()> {
+        let error = Rp1210Error {
+            code: 141,
+            message: "ERR_HARDWARE_NOT_RESPONDING".to_string(),
+        };
+        let retry = Rp1210::new("SIM", 12, None, "", 0xF9, true, BusKind::PushBus)?;
+        let packets = retry.iter();
+        retry.fail_reads(error.clone());
+        assert_eq!(3, packets.flatten().take(3).count());
+        assert!(retry.is_running());
+        assert_eq!(Some(error.clone()), retry.last_error());
+
+        let terminate = Rp1210::new_with_options(
+            "SIM",
+            13,
+            None,
+            "",
+            0xF9,
+            true,
+            &ConnectOptions {
+                on_read_error: OnReadError::Terminate,
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        let results = terminate.iter_results();
+        terminate.fail_reads(error.clone());
+        let last = results.last().unwrap().unwrap_err();
+        assert_eq!(Some(&error), last.downcast_ref::<Rp1210Error>());
+        assert_eq!(Some(error), terminate.read_error());
+        assert!(!terminate.is_running());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn filters_and_claimed_address() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 15, None, "", 0xF9, false, BusKind::PushBus)?;
+        sim.apply_filters(&[J1939Filter::new().pgn(0xFEEC), J1939Filter::new().source(0x17)])?;
+        // ends at the deadline even though the filters discard the generated traffic
+        let packets = sim.iter_for(Duration::from_millis(300));
+        sim.replay([
+            J1939Packet::new(Some(0), 0, 0x18FEEC00, &[1]).as_received(),
+            J1939Packet::new(Some(0), 0, 0x18FEF100, &[2]).as_received(),
+            J1939Packet::new(Some(0), 0, 0x18FEF117, &[3]).as_received(),
+        ]);
+        sim.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[4]))?;
+        let received: Vec<u8> = packets.map(|p| p.data()[0]).collect();
+        // the generated traffic and PGN 0xFEF1 from the engine are discarded; echoes aren't filtered
+        assert_eq!(vec![1, 3, 4], received);
+
+        let error = sim
+            .send(&J1939Packet::new(None, 0, 0x18FFAAF8, &[5]))
+            .unwrap_err();
+        assert_eq!(
+            Some(ERR_ADDRESS_NEVER_CLAIMED),
+            crate::rp1210_error::code(&error)
+        );
+        assert_eq!(
+            Some(ERR_ADDRESS_NEVER_CLAIMED),
+            sim.last_error().map(|e| e.code)
+        );
+        assert!(sim
+            .send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[0; 2_000]))
+            .is_err());
+        sim.apply_filters(&[])?;
+        assert!(sim.iter_for(Duration::from_millis(300)).next().is_some());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn poll_mode() -> Result<()> {
+        let mut sim = Rp1210::new_with_options(
+            "SIM",
+            17,
+            None,
+            "",
+            0xF9,
+            true,
+            &ConnectOptions {
+                read_mode: ReadMode::Poll,
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        assert!(sim.client()?.thread.is_none());
+        assert!(sim.is_running());
+        let end = Instant::now() + Duration::from_secs(2);
+        let polled = std::iter::from_fn(|| (Instant::now() < end).then(|| sim.poll()));
+        assert_eq!(3, polled.flatten().flatten().take(3).count());
+
+        sim.send(&J1939Packet::new(None, 0, 0x18FFAAF9, &[7]))?;
+        let echo = std::iter::repeat_with(|| sim.poll())
+            .take(1000)
+            .find_map(|p| p.ok().flatten().filter(|p| p.echo()));
+        assert_eq!(Some(vec![7]), echo.map(|p| p.data().to_vec()));
+
+        let threaded = Rp1210::new("SIM", 18, None, "", 0xF9, true, BusKind::PushBus)?;
+        assert!(threaded.poll().is_err());
+        sim.close()?;
+        assert!(sim.poll().is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn answers_a_trigger() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 23, None, "", 0xF9, false, BusKind::PushBus)?;
+        // the query and the echo of the answer, without the generated traffic
+        let order = sim.iter_filtered(Arc::new(|p| p.data().len() == 1));
+        let mut ecu = sim.clone();
+        let query = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            ecu.replay([J1939Packet::new(Some(0), 0, 0x18FFAB00, &[1]).as_received()]);
+        });
+        let answer = J1939Packet::new(None, 0, 0x18FFACF9, &[2]);
+        let is_query: Filter<J1939Packet> = Arc::new(|p| p.id() == 0x18FFAB00);
+        let trigger = sim.send_on_trigger(is_query.clone(), &answer, Duration::from_secs(5))?;
+        query.join().unwrap();
+        assert_eq!([1], trigger.data());
+        let seen: Vec<u8> = order.flatten().take(2).map(|p| p.data()[0]).collect();
+        assert_eq!(vec![1, 2], seen);
+        assert!(sim
+            .send_on_trigger(is_query, &answer, Duration::from_millis(100))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn next_matching() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 27, None, "", 0xF9, true, BusKind::PushBus)?;
+        let generated =
+            sim.next_matching(Arc::new(|p| p.pgn() == 0xFEF1), Duration::from_secs(2))?;
+        assert_eq!((6, 8), (generated.priority(), generated.data().len()));
+        let error = sim
+            .next_matching(Arc::new(|p| p.pgn() == 0xFEEC), Duration::from_millis(200))
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Nothing matched within"));
+        sim.close()?;
+        assert!(sim.next_matching(Arc::new(|_| true), Duration::from_secs(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn requests_time_out_on_a_quiet_bus() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 29, None, "", 0xF9, true, BusKind::PushBus)?;
+        sim.set_powered(false);
+        let start = Instant::now();
+        assert!(sim.request_pgn(0xFEEC, 0x33, Duration::from_millis(200))?.is_empty());
+        assert_eq!(0, sim.iter_for(Duration::from_millis(200)).count());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        sim.close()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn snapshot_times_out_on_a_quiet_bus() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 30, None, "", 0xF9, true, BusKind::PushBus)?;
+        sim.set_powered(false);
+        sim.add_ecu(SimEcu::new(0x00, J1939Name::default()).respond(0xFEE5, &[1, 0, 0, 0, 2, 0, 0, 0]));
+        let start = Instant::now();
+        // the engine doesn't have a VIN, so the snapshot is never complete
+        let snapshot = sim.request_pgns(&[0xFEEC, 0xFEE5], 0x00, Duration::from_millis(200))?;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(vec![0xFEE5], snapshot.keys().copied().collect::<Vec<_>>());
+        assert!(sim.request_pgns(&[0xFEEC], 0xFF, Duration::from_millis(200))?.is_empty());
+        sim.close()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn windows_are_gap_free() -> Result<()> {
+        let sim = Rp1210::new("SIM", 22, None, "", 0xF9, true, BusKind::PushBus)?;
+        let seq = |p: J1939Packet| u64::from_be_bytes(p.data().try_into().unwrap());
+        let mut window = sim.iter_window(Duration::from_millis(250));
+        let mut seqs: Vec<u64> = window.by_ref().map(seq).collect();
+        let first = seqs.len();
+        // generated between the windows
+        std::thread::sleep(Duration::from_millis(300));
+        window.next_window();
+        seqs.extend(window.by_ref().map(seq));
+        assert!(first > 0 && seqs.len() > first + 2);
+        assert!(seqs.windows(2).all(|w| w[1] == w[0] + 1));
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn idle_timeout() -> Result<()> {
+        let clock = MockClock::new();
+        let mut sim = Rp1210::new_with_options(
+            "SIM",
+            14,
+            None,
+            "",
+            0xF9,
+            true,
+            &ConnectOptions {
+                idle_timeout: Some(Duration::from_secs(5)),
+                clock: Arc::new(clock.clone()),
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        let mut packets = sim.iter();
+        // traffic keeps the connection open past the timeout.  Packets sent after the check show it passed.
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(3, packets.by_ref().flatten().take(3).count());
+        assert!(sim.is_running());
+        sim.set_powered(false);
+        let off = clock.now();
+        // a second per poll of the bus, until the simulator thread disconnects
+        packets.for_each(|_| clock.advance(Duration::from_secs(1)));
+        assert!(clock.now() - off >= Duration::from_secs(5));
+        assert!(!sim.is_running());
+        assert!(sim.send(&j1939::request(0xFEEC, 0xFF, 0xF9)).is_err());
+        sim.close()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn bus_off() -> Result<()> {
+        let mut sim = Rp1210::new_with_options(
+            "SIM",
+            26,
+            None,
+            "",
+            0xF9,
+            true,
+            &ConnectOptions {
+                bus_state_interval: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        let mut states = sim.iter_bus_state().flatten();
+        assert_eq!(Some(BusState::Active), states.next());
+        sim.set_bus_state(BusState::BusOff);
+        assert_eq!(Some(BusState::BusOff), states.next());
+        assert_eq!(Some(BusState::BusOff), sim.bus_state());
+        // silent, as when the vehicle is off, but the state tells them apart
+        let generated = sim.iter_filtered(Arc::new(|p| p.data().len() == 8));
+        let end = Instant::now() + Duration::from_millis(300);
+        assert_eq!(0, generated.take_while(|_| Instant::now() < end).flatten().count());
+        sim.set_bus_state(BusState::Active);
+        assert_eq!(Some(BusState::Active), states.next());
+        sim.close()?;
+        assert_eq!(None, states.next());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn read_only() -> Result<()> {
+        let mut sim = Rp1210::new_with_options(
+            "SIM",
+            10,
+            None,
+            "",
+            0xF9,
+            false,
+            &ConnectOptions {
+                access: Access::ReadOnly,
+                ..Default::default()
+            },
+            BusKind::PushBus,
+        )?;
+        assert_eq!(None, sim.address_claim());
+        let mut packets = sim.iter_for(Duration::from_secs(5));
+        assert!(sim.send(&j1939::request(0xFEEC, 0xFF, 0xF9)).is_err());
+        assert!(sim
+            .start_keepalive(&J1939Packet::new(None, 0, 0x18FFAAF9, &[1]), Duration::from_millis(20))
+            .is_err());
+        sim.replay([J1939Packet::new(Some(0), 0, 0x18FEF100, &[1, 2, 3]).as_received()]);
+        assert_eq!([1, 2, 3], packets.next().unwrap().data());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn keepalive() -> Result<()> {
+        let mut sim = Rp1210::new("SIM", 1, None, "", 0xF9, false, BusKind::PushBus)?;
+        let packets = sim.iter_for(Duration::from_secs(5));
+        sim.start_keepalive(
+            &J1939Packet::new(None, 0, 0x18FFAAF9, &[1, 2, 3]),
+            Duration::from_millis(20),
+        )?;
+        let keepalives = packets.filter(|p| p.data() == [1, 2, 3]);
+        assert_eq!(3, keepalives.take(3).count());
+        // stop_keepalive() waits for the timer thread, so nothing follows
+        sim.stop_keepalive();
+        let packets = sim.iter_for(Duration::from_millis(50));
+        assert_eq!(0, packets.filter(|p| p.data() == [1, 2, 3]).count());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn drop_while_reading() -> Result<()> {
+        for device in 0..10 {
+            let sim = Rp1210::new("SIM", device, None, "", 0xF9, false, BusKind::MultiQueue)?;
+            let consumers: Vec<_> = (0..4)
+                .map(|_| {
+                    let packets = sim.iter();
+                    std::thread::spawn(move || packets.count())
+                })
+                .collect();
+            std::thread::sleep(Duration::from_millis(5));
+            drop(sim);
+            consumers.into_iter().for_each(|c| {
+                c.join().unwrap();
+            });
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,307 @@
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::bus::{Bus, BusIterator, BusKind, Filter};
+use crate::connection::{Connection, ConnectionInfo, FrameCounter};
+use crate::j1939;
+use crate::packet::J1939Packet;
+use crate::timestamp::Timestamps;
+
+/// J1939 over a Linux SocketCAN interface, e.g. `can0`, for CAN hardware without an RP1210 driver.
+///
+/// Uses a raw CAN socket, so no address is claimed: send an Address Claimed (`j1939::address_claimed()`) first if
+/// the network requires it.  Frames we send are looped back by the kernel and arrive as echoes.  Time stamps are
+/// host microseconds since the interface was opened.  Standard (11-bit) and remote frames are ignored.
+#[derive(Clone)]
+pub struct SocketCan {
+    /// None once this handle is closed
+    socket: Option<Arc<Socket>>,
+    interface: String,
+    bus: Box<dyn Bus<J1939Packet>>,
+    address: u8,
+    frames: FrameCounter,
+}
+
+/// Socket and read thread shared by clones of a `SocketCan`.  Closed with the last clone.
+struct Socket {
+    fd: OwnedFd,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    bus: Box<dyn Bus<J1939Packet>>,
+}
+
+impl Socket {
+    /// The read thread is running until close(), unless it panicked.
+    fn is_running(&self) -> bool {
+        self.running.load(Relaxed) && self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.running.store(false, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().map_err(|_| anyhow!("SocketCAN read thread panicked"))?;
+        }
+        self.bus.close();
+        Ok(())
+    }
+
+    fn write(&self, packet: &J1939Packet) -> Result<()> {
+        let frame = to_frame(packet)?;
+        let size = std::mem::size_of::<libc::can_frame>();
+        let written = unsafe {
+            libc::write(self.fd.as_raw_fd(), &frame as *const _ as *const libc::c_void, size)
+        };
+        if written != size as isize {
+            bail!("Unable to send {}: {}", packet, std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+impl SocketCan {
+    /// Open `interface`, which must already be up (`ip link set can0 up type can bitrate 250000`).
+    pub fn new(interface: &str, address: u8, bus: BusKind) -> Result<SocketCan> {
+        let fd = open(interface)?;
+        let bus = bus.create();
+        let frames = FrameCounter::new();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let raw = fd.as_raw_fd();
+            let running = running.clone();
+            let mut bus = bus.clone();
+            let frames = frames.clone();
+            std::thread::Builder::new()
+                .name(format!("socketcan {}", interface))
+                .spawn(move || {
+                    let start = Instant::now();
+                    let mut timestamps = Timestamps::default();
+                    while running.load(Relaxed) {
+                        // times out every 100 ms, so the thread notices close()
+                        if let Some((frame, echo)) = read(raw) {
+                            // wraps after 71 minutes.  Timestamps unwraps it.
+                            let time = start.elapsed().as_micros() as u32;
+                            if let Some(packet) = from_frame(&frame, time, echo) {
+                                bus.push(Some(timestamps.stamp(packet)));
+                                frames.add();
+                            }
+                        }
+                        bus.push(None);
+                    }
+                })?
+        };
+        let socket = Socket {
+            fd,
+            running,
+            thread: Some(thread),
+            bus: bus.clone(),
+        };
+        Ok(SocketCan {
+            socket: Some(Arc::new(socket)),
+            interface: interface.to_string(),
+            bus,
+            address,
+            frames,
+        })
+    }
+
+    fn socket(&self) -> Result<&Socket> {
+        self.socket.as_deref().ok_or(anyhow!("Connection closed"))
+    }
+}
+
+/// Raw CAN socket bound to `interface`, receiving its own frames and with a 100 ms read timeout.
+fn open(interface: &str) -> Result<OwnedFd> {
+    let name = CString::new(interface)?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        bail!("No CAN interface {}: {}", interface, std::io::Error::last_os_error());
+    }
+    let raw = unsafe { libc::socket(libc::PF_CAN, libc::SOCK_RAW, libc::CAN_RAW) };
+    if raw < 0 {
+        bail!("Unable to open a CAN socket: {}", std::io::Error::last_os_error());
+    }
+    // closes the socket if setup fails
+    let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+    let own: libc::c_int = 1;
+    let timeout = libc::timeval {
+        tv_sec: 0,
+        tv_usec: 100_000,
+    };
+    let mut address: libc::sockaddr_can = unsafe { std::mem::zeroed() };
+    address.can_family = libc::AF_CAN as libc::sa_family_t;
+    address.can_ifindex = index as libc::c_int;
+    unsafe {
+        if libc::setsockopt(
+            raw,
+            libc::SOL_CAN_RAW,
+            libc::CAN_RAW_RECV_OWN_MSGS,
+            &own as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&own) as libc::socklen_t,
+        ) < 0
+            || libc::setsockopt(
+                raw,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&timeout) as libc::socklen_t,
+            ) < 0
+            || libc::bind(
+                raw,
+                &address as *const _ as *const libc::sockaddr,
+                std::mem::size_of_val(&address) as libc::socklen_t,
+            ) < 0
+        {
+            bail!("Unable to bind to {}: {}", interface, std::io::Error::last_os_error());
+        }
+    }
+    Ok(fd)
+}
+
+/// Next frame, and whether it's one we sent.  None on timeout or error.
+fn read(fd: RawFd) -> Option<(libc::can_frame, bool)> {
+    let mut frame: libc::can_frame = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: &mut frame as *mut _ as *mut libc::c_void,
+        iov_len: std::mem::size_of::<libc::can_frame>(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    let size = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if size != std::mem::size_of::<libc::can_frame>() as isize {
+        return None;
+    }
+    // the kernel marks frames sent from this socket
+    Some((frame, msg.msg_flags & libc::MSG_CONFIRM != 0))
+}
+
+fn to_frame(packet: &J1939Packet) -> Result<libc::can_frame> {
+    let data = packet.data();
+    if data.len() > libc::CAN_MAX_DLEN {
+        bail!("{} byte packet exceeds a CAN frame.  Use TP.", data.len());
+    }
+    let mut frame: libc::can_frame = unsafe { std::mem::zeroed() };
+    frame.can_id = packet.id() | libc::CAN_EFF_FLAG;
+    frame.can_dlc = data.len() as u8;
+    frame.data[..data.len()].copy_from_slice(data);
+    Ok(frame)
+}
+
+/// Packet for an extended data frame received at `time` µs.  None for standard, remote and error frames.
+fn from_frame(frame: &libc::can_frame, time: u32, echo: bool) -> Option<J1939Packet> {
+    if frame.can_id & libc::CAN_EFF_FLAG == 0
+        || frame.can_id & (libc::CAN_RTR_FLAG | libc::CAN_ERR_FLAG) != 0
+    {
+        return None;
+    }
+    let len = (frame.can_dlc as usize).min(libc::CAN_MAX_DLEN);
+    // new() with a time marks the packet as an echo
+    let packet = J1939Packet::new(Some(time), 0, frame.can_id & libc::CAN_EFF_MASK, &frame.data[..len]);
+    Some(if echo { packet } else { packet.as_received() })
+}
+
+impl Connection for SocketCan {
+    /// Send packet and return the kernel's loopback of it.
+    fn send(&mut self, packet: &J1939Packet) -> Result<J1939Packet> {
+        let packet = &j1939::outgoing(packet, self.address);
+        let end = Instant::now() + Duration::from_secs(2);
+        let stream = self.bus.iter().take_while(|_| Instant::now() < end);
+        self.socket()?.write(packet)?;
+        stream
+            .flatten()
+            .find(|p| p.echo_of(packet))
+            .ok_or_else(|| anyhow!("No echo of {} within 2 s", packet))
+    }
+
+    fn transmit(&mut self, packet: &J1939Packet) -> Result<()> {
+        self.socket()?.write(&j1939::outgoing(packet, self.address))
+    }
+
+    fn iter(&self) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter()
+    }
+
+    fn iter_filtered(&self, filter: Filter<J1939Packet>) -> Box<dyn BusIterator<J1939Packet>> {
+        self.bus.iter_filtered(filter)
+    }
+
+    fn is_running(&self) -> bool {
+        self.socket.as_ref().is_some_and(|c| c.is_running())
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// The interface is reported as the connection string.  The bitrate is set with `ip link`, so isn't known.
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            adapter: "socketcan".to_string(),
+            device: 0,
+            connection_string: self.interface.clone(),
+            address: self.address,
+            address_claim: None,
+            baud: None,
+            driver_version: None,
+            frames: self.frames.frames(),
+            frames_per_second: self.frames.per_second(),
+        }
+    }
+
+    /// The socket closes with the last clone.
+    fn close(&mut self) -> Result<()> {
+        match self.socket.take().and_then(Arc::into_inner) {
+            Some(mut socket) => socket.close(),
+            None => Ok(()),
+        }
+    }
+
+    fn clone_connection(&self) -> Box<dyn Connection> {
+        Box::new(self.clone())
+    }
+}
+
+impl Drop for SocketCan {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Direction;
+
+    #[test]
+    fn frames() -> Result<()> {
+        let packet = J1939Packet::new(None, 0, 0x18EAFFF9, &[0xEC, 0xFE, 0x00]);
+        let frame = to_frame(&packet)?;
+        assert_eq!(0x98EAFFF9, frame.can_id);
+        assert_eq!(3, frame.can_dlc);
+
+        let received = from_frame(&frame, 1_000_000, false).unwrap();
+        assert_eq!(0x18EAFFF9, received.id());
+        assert_eq!(packet.data(), received.data());
+        assert_eq!(Direction::Rx, received.direction());
+        assert_eq!(1.0, received.time());
+        assert_eq!(Direction::Echo, from_frame(&frame, 0, true).unwrap().direction());
+
+        let mut standard = frame;
+        standard.can_id = 0x123;
+        assert!(from_frame(&standard, 0, false).is_none());
+        assert!(to_frame(&J1939Packet::new(None, 0, 0x18FEF100, &[0; 9])).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use crate::packet::J1939Packet;
+
+/// Where a connection gets the time stamps of the packets it receives.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampSource {
+    /// The adapter's time stamp, scaled by the INI's TimeStampWeight.
+    #[default]
+    Adapter,
+    /// The host's monotonic clock when the packet is read, in seconds since the first connection opened.
+    /// For adapters with unreliable time stamps, and for correlating packets from several adapters.
+    Host,
+}
+
+/// Time stamp source shared between a connection and its read thread, so it can be changed while reading.
+/// The read thread's copy also tracks wraps of the adapter's 32-bit counter.
+#[derive(Clone, Default)]
+pub(crate) struct Timestamps {
+    host: Arc<AtomicBool>,
+    last: Option<u32>,
+    wraps: u32,
+}
+
+impl Timestamps {
+    pub(crate) fn set_source(&self, source: TimestampSource) {
+        self.host.store(source == TimestampSource::Host, Relaxed);
+    }
+
+    pub(crate) fn source(&self) -> TimestampSource {
+        if self.host.load(Relaxed) {
+            TimestampSource::Host
+        } else {
+            TimestampSource::Adapter
+        }
+    }
+
+    /// Called by the read thread as each packet is read, in order.  Adapter time stamps are offset by the
+    /// counter wraps seen so far, so time() keeps increasing.
+    pub(crate) fn stamp(&mut self, packet: J1939Packet) -> J1939Packet {
+        if let Some(time_stamp) = packet.time_stamp() {
+            // a large step back is a wrap.  Small ones are the adapter's jitter.
+            if self
+                .last
+                .is_some_and(|last| time_stamp < last && last - time_stamp > u32::MAX / 2)
+            {
+                self.wraps += 1;
+            }
+            self.last = Some(time_stamp);
+        }
+        match self.source() {
+            TimestampSource::Adapter if self.wraps > 0 => {
+                let offset =
+                    packet.time_stamp_to_seconds(self.wraps as f64 * (u32::MAX as f64 + 1.0));
+                packet.with_time(packet.time() + offset)
+            }
+            TimestampSource::Adapter => packet,
+            TimestampSource::Host => packet.with_time(host_time()),
+        }
+    }
+}
+
+/// Seconds on the host's monotonic clock, shared by every connection in the process.
+fn host_time() -> f64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps() {
+        let mut timestamps = Timestamps::default();
+        let times: Vec<f64> = [
+            u32::MAX - 1000,
+            u32::MAX - 500,
+            u32::MAX - 600,
+            5,
+            1000,
+            u32::MAX - 10,
+            20,
+        ]
+        .into_iter()
+        .map(|t| {
+            timestamps
+                .stamp(J1939Packet::new(Some(t), 0, 0x18FEF100, &[0; 8]))
+                .time()
+        })
+        .collect();
+        let wrap = u32::MAX as f64 + 1.0;
+        let expected = [
+            (u32::MAX - 1000) as f64,
+            (u32::MAX - 500) as f64,
+            // jitter isn't a wrap
+            (u32::MAX - 600) as f64,
+            wrap + 5.0,
+            wrap + 1000.0,
+            wrap + (u32::MAX - 10) as f64,
+            2.0 * wrap + 20.0,
+        ];
+        for (time, expected) in times.iter().zip(expected) {
+            assert!(
+                (time - expected * 0.000001).abs() < 1e-6,
+                "{} != {}",
+                time,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn host_time_replaces_adapter_time() {
+        let mut timestamps = Timestamps::default();
+        // adapter time stamps of 1000 s, then 1 s
+        let late = J1939Packet::new(Some(1_000_000_000), 0, 0x18FEF100, &[0; 8]);
+        let early = J1939Packet::new(Some(1_000_000), 0, 0x18FEF100, &[0; 8]);
+        assert_eq!(1000.0, timestamps.stamp(late.clone()).time());
+
+        timestamps.set_source(TimestampSource::Host);
+        let first = timestamps.stamp(late).time();
+        let second = timestamps.stamp(early).time();
+        assert!(first < 1000.0);
+        assert!(first <= second);
+    }
+}
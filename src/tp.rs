@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use crate::packet::J1939Packet;
+
+/// TP.CM Connection Management
+pub const TP_CM: u32 = 0xEC00;
+/// TP.DT Data Transfer
+pub const TP_DT: u32 = 0xEB00;
+
+const CM_RTS: u8 = 16;
+const CM_ABORT: u8 = 255;
+const CM_BAM: u8 = 32;
+
+/// PGN without the destination address of PDU1 packets.
+pub(crate) fn base_pgn(packet: &J1939Packet) -> u32 {
+    crate::packet::pgn_from_id(packet.id())
+}
+
+struct Session {
+    pgn: u32,
+    size: usize,
+    /// TP.DT data by sequence number, from 1
+    frames: Vec<Option<Vec<u8>>>,
+}
+
+impl Session {
+    fn incomplete(&self, (source, dest): (u8, u8)) -> IncompleteSession {
+        IncompleteSession {
+            source,
+            dest,
+            pgn: self.pgn,
+            size: self.size,
+            missing: self
+                .frames
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| f.is_none())
+                .map(|(i, _)| i as u8 + 1)
+                .collect(),
+        }
+    }
+}
+
+/// A TP session that ended without all of its data, e.g. because a TP.DT packet was lost, so the response is
+/// partial and shouldn't be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompleteSession {
+    pub source: u8,
+    pub dest: u8,
+    /// PGN being transferred
+    pub pgn: u32,
+    /// bytes the TP.CM announced
+    pub size: usize,
+    /// TP.DT sequence numbers, from 1, that didn't arrive
+    pub missing: Vec<u8>,
+}
+
+/// Passive J1939-21 transport protocol reassembly.
+///
+/// BAM and RTS/CTS sessions are reassembled from the TP.CM and TP.DT packets seen on the bus.
+/// This does not send CTS, so RTS/CTS sessions addressed to us rely on the adapter handling
+/// the handshake (the default, when not app packetized).
+///
+/// Packets that are not part of a TP session are passed through, so this can be used on any stream.  Sessions
+/// missing TP.DT packets are never passed on truncated: they are reported by take_incomplete() when they end,
+/// with the missing sequence numbers.
+#[derive(Default)]
+pub struct Reassembler {
+    sessions: HashMap<(u8, u8), Session>,
+    incomplete: Vec<IncompleteSession>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a packet.  Returns the packet itself if it isn't TP, the reassembled packet when a
+    /// session completes, or None.
+    ///
+    /// A session ends incomplete when its last TP.DT arrives with others missing, it's aborted, or the sender
+    /// starts another session before finishing.
+    pub fn push(&mut self, packet: J1939Packet) -> Option<J1939Packet> {
+        let key = (packet.source(), packet.dest());
+        match base_pgn(&packet) {
+            TP_CM => {
+                let data = packet.data();
+                if data.len() < 8 {
+                    return None;
+                }
+                match data[0] {
+                    CM_RTS | CM_BAM => {
+                        let size = u16::from_le_bytes([data[1], data[2]]) as usize;
+                        // a TP.CM announcing too few packets for its size is missing the rest
+                        let frames = (data[3] as usize).max(size.div_ceil(7)).min(255);
+                        let session = Session {
+                            pgn: u32::from_le_bytes([data[5], data[6], data[7], 0]),
+                            size,
+                            frames: vec![None; frames],
+                        };
+                        if let Some(unfinished) = self.sessions.insert(key, session) {
+                            self.incomplete.push(unfinished.incomplete(key));
+                        }
+                    }
+                    CM_ABORT => {
+                        if let Some(aborted) = self.sessions.remove(&key) {
+                            self.incomplete.push(aborted.incomplete(key));
+                        }
+                    }
+                    _ => {}
+                }
+                None
+            }
+            TP_DT => {
+                let data = packet.data();
+                let session = self.sessions.get_mut(&key)?;
+                let sequence = *data.first()? as usize;
+                if sequence == 0 || sequence > session.frames.len() {
+                    return None;
+                }
+                session.frames[sequence - 1] = Some(data[1..].to_vec());
+                let complete = session.frames.iter().all(Option::is_some);
+                if !complete && sequence < session.frames.len() {
+                    return None;
+                }
+                let session = self.sessions.remove(&key)?;
+                if !complete {
+                    self.incomplete.push(session.incomplete(key));
+                    return None;
+                }
+                let mut payload: Vec<u8> = session.frames.into_iter().flatten().flatten().collect();
+                payload.truncate(session.size);
+                Some(packet.reassembled(session.pgn, &payload))
+            }
+            _ => Some(packet),
+        }
+    }
+
+    /// Sessions that ended incomplete since the last call.
+    pub fn take_incomplete(&mut self) -> Vec<IncompleteSession> {
+        std::mem::take(&mut self.incomplete)
+    }
+
+    /// End every open session, e.g. when collection times out, and return all the incomplete sessions.
+    pub fn finish(&mut self) -> Vec<IncompleteSession> {
+        let open: Vec<IncompleteSession> = self
+            .sessions
+            .drain()
+            .map(|(key, session)| session.incomplete(key))
+            .collect();
+        let mut incomplete = self.take_incomplete();
+        incomplete.extend(open);
+        incomplete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bam() {
+        let mut tp = Reassembler::new();
+        let cm = J1939Packet::new(None, 0, 0x1CECFF00, &[32, 9, 0, 2, 0xFF, 0xEC, 0xFE, 0x00]);
+        let dt1 = J1939Packet::new(
+            None,
+            0,
+            0x1CEBFF00,
+            &[1, b'1', b'2', b'3', b'4', b'5', b'6', b'7'],
+        );
+        let dt2 = J1939Packet::new(
+            None,
+            0,
+            0x1CEBFF00,
+            &[2, b'8', b'9', 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        );
+        assert!(tp.push(cm).is_none());
+        assert!(tp.push(dt1).is_none());
+        let p = tp.push(dt2).unwrap();
+        assert_eq!(0xFEEC, p.pgn());
+        assert_eq!(0, p.source());
+        assert_eq!(b"123456789", p.data());
+    }
+
+    #[test]
+    fn incomplete_sessions() {
+        let mut tp = Reassembler::new();
+        let cm = J1939Packet::new(None, 0, 0x1CECFF00, &[32, 20, 0, 3, 0xFF, 0xEC, 0xFE, 0x00]);
+        let dt =
+            |sequence: u8| J1939Packet::new(None, 0, 0x1CEBFF00, &[sequence, 1, 2, 3, 4, 5, 6, 7]);
+        tp.push(cm.clone());
+        tp.push(dt(1));
+        // the last packet arrives without the second, so there's no truncated VIN
+        assert!(tp.push(dt(3)).is_none());
+        let incomplete = tp.take_incomplete();
+        assert_eq!(1, incomplete.len());
+        assert_eq!(
+            (0x00, 0xFEEC, 20),
+            (incomplete[0].source, incomplete[0].pgn, incomplete[0].size)
+        );
+        assert_eq!(vec![2], incomplete[0].missing);
+        assert!(tp.take_incomplete().is_empty());
+
+        // packets out of order still complete the session
+        tp.push(cm.clone());
+        tp.push(dt(2));
+        tp.push(dt(1));
+        assert_eq!(20, tp.push(dt(3)).unwrap().data().len());
+
+        // a session still open when collection ends
+        tp.push(cm);
+        tp.push(dt(1));
+        assert_eq!(vec![2, 3], tp.finish()[0].missing);
+        assert!(tp.finish().is_empty());
+    }
+
+    #[test]
+    fn passthrough() {
+        let mut tp = Reassembler::new();
+        let p = J1939Packet::new(None, 0, 0x18FEF100, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(p.data(), tp.push(p.clone()).unwrap().data());
+    }
+}
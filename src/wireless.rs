@@ -0,0 +1,46 @@
+/// Link state of a wireless (Bluetooth/WiFi) adapter, from RP1210_Get_Wireless_State.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WirelessState {
+    pub connected: bool,
+    /// signal strength in percent, if the driver reports it
+    pub signal: Option<u8>,
+}
+
+impl WirelessState {
+    /// Parse the command buffer: connected (0 or 1), then optionally signal strength (0-100).
+    /// The buffer is filled with 0xFF before the call, so bytes the driver didn't write are ignored.
+    pub fn parse(buf: &[u8]) -> Option<WirelessState> {
+        Some(WirelessState {
+            connected: match buf.first()? {
+                0 => false,
+                1 => true,
+                _ => return None,
+            },
+            signal: buf.get(1).copied().filter(|s| *s <= 100),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(None, WirelessState::parse(&[0xFF, 0xFF]));
+        assert_eq!(
+            Some(WirelessState {
+                connected: true,
+                signal: None
+            }),
+            WirelessState::parse(&[1, 0xFF])
+        );
+        assert_eq!(
+            Some(WirelessState {
+                connected: false,
+                signal: Some(40)
+            }),
+            WirelessState::parse(&[0, 40])
+        );
+    }
+}